@@ -0,0 +1,363 @@
+//! CoAP server standing in for the telemetry endpoint of the mqtt_thread CoAP client.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::net::{SocketAddr, UdpSocket};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+#[allow(dead_code)]
+#[path = "../../mqtt_thread/src/coap.rs"]
+mod coap;
+
+use coap::{content_format, option, CoapError, Code, Message, MessageType, MessageWriter};
+
+const USAGE: &str = "\
+usage: coap-stand-in [options]
+
+Prints the payload of every POST and answers 2.04 Changed. Lines read from stdin become the
+state of /commands and are sent to its observers.
+
+options:
+  --port <port>    UDP port, default 5683
+  --drop <n>       ignore the first <n> transmissions of every confirmable request
+  --separate       acknowledge requests first and send the response separately";
+
+const COMMAND_PATH: &str = "commands";
+const MAX_MESSAGE_SIZE: usize = 1152;
+/// Responses kept to answer retransmitted requests, RFC 7252 section 4.5
+const RESPONSE_CACHE_SIZE: usize = 64;
+
+struct Options {
+    port: u16,
+    drop: u32,
+    separate: bool,
+}
+
+struct Observer {
+    remote: SocketAddr,
+    token: Vec<u8>,
+}
+
+struct Server {
+    socket: UdpSocket,
+    options: Options,
+    next_message_id: u16,
+    /// Transmissions seen per confirmable request, for `--drop`
+    transmissions: HashMap<(SocketAddr, u16), u32>,
+    responses: HashMap<(SocketAddr, u16), Vec<u8>>,
+    observers: Vec<Observer>,
+    /// Message IDs of sent notifications, a reset ends the observation
+    notifications: HashMap<(SocketAddr, u16), Vec<u8>>,
+    sequence: u32,
+    command: String,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let socket = match UdpSocket::bind(("::", options.port)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("failed to bind port {}: {e}", options.port);
+            return ExitCode::FAILURE;
+        }
+    };
+    // lets the loop check stdin for commands
+    if let Err(e) = socket.set_read_timeout(Some(Duration::from_millis(100))) {
+        eprintln!("failed to set the read timeout: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!("listening on [::]:{}", options.port);
+
+    let commands = read_commands();
+    let mut server = Server {
+        socket,
+        options,
+        next_message_id: std::process::id() as u16,
+        transmissions: HashMap::new(),
+        responses: HashMap::new(),
+        observers: Vec::new(),
+        notifications: HashMap::new(),
+        sequence: 0,
+        command: String::new(),
+    };
+
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    loop {
+        while let Ok(command) = commands.try_recv() {
+            server.notify(command);
+        }
+
+        match server.socket.recv_from(&mut buf) {
+            Ok((len, remote)) => server.handle(&buf[..len], remote),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => {
+                eprintln!("receive failed: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        port: coap::PORT,
+        drop: 0,
+        separate: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                options.port = args
+                    .next()
+                    .and_then(|port| port.parse().ok())
+                    .ok_or("--port needs a port number")?;
+            }
+            "--drop" => {
+                options.drop = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or("--drop needs a number of transmissions")?;
+            }
+            "--separate" => options.separate = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(options)
+}
+
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+impl Server {
+    fn handle(&mut self, data: &[u8], remote: SocketAddr) {
+        let message = match Message::parse(data) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("{remote}: invalid message: {e:?}");
+                return;
+            }
+        };
+
+        match message.message_type {
+            MessageType::Acknowledgement => {
+                self.notifications.remove(&(remote, message.message_id));
+            }
+            MessageType::Reset => {
+                if let Some(token) = self.notifications.remove(&(remote, message.message_id)) {
+                    println!("{remote}: reset, removing the observer");
+                    self.observers
+                        .retain(|observer| observer.remote != remote || observer.token != token);
+                }
+            }
+            MessageType::Confirmable | MessageType::NonConfirmable => {
+                self.request(&message, remote)
+            }
+        }
+    }
+
+    fn request(&mut self, request: &Message, remote: SocketAddr) {
+        let confirmable = request.message_type == MessageType::Confirmable;
+        let key = (remote, request.message_id);
+
+        if confirmable {
+            let transmissions = self.transmissions.entry(key).or_insert(0);
+            *transmissions += 1;
+            if *transmissions <= self.options.drop {
+                println!(
+                    "{remote}: dropping transmission {} of message {:#06x}",
+                    transmissions, request.message_id
+                );
+                return;
+            }
+
+            if let Some(response) = self.responses.get(&key) {
+                println!("{remote}: duplicate of message {:#06x}", request.message_id);
+                self.send(response, remote);
+                return;
+            }
+        }
+
+        if request.code == Code::EMPTY {
+            // ping
+            let reset = empty(MessageType::Reset, request.message_id);
+            self.send(&reset, remote);
+            return;
+        }
+
+        let path: Vec<&str> = request.path().collect();
+        let path = path.join("/");
+        let (code, payload) = match (request.code, path.as_str()) {
+            (Code::GET, COMMAND_PATH) => {
+                let observe = request.uint_option(option::OBSERVE);
+                if observe == Some(0) {
+                    self.observers.retain(|observer| {
+                        observer.remote != remote || observer.token != request.token
+                    });
+                    self.observers.push(Observer {
+                        remote,
+                        token: request.token.to_vec(),
+                    });
+                    println!("{remote}: observing /{path}");
+                } else if observe == Some(1) {
+                    self.observers.retain(|observer| {
+                        observer.remote != remote || observer.token != request.token
+                    });
+                }
+                (Code::CONTENT, self.command.clone().into_bytes())
+            }
+            (Code::POST, _) => {
+                println!(
+                    "{remote}: POST /{path}: {}",
+                    String::from_utf8_lossy(request.payload)
+                );
+                (Code::CHANGED, Vec::new())
+            }
+            (_, COMMAND_PATH) => (Code::METHOD_NOT_ALLOWED, Vec::new()),
+            _ => (Code::NOT_FOUND, Vec::new()),
+        };
+        let observing = code == Code::CONTENT
+            && self
+                .observers
+                .iter()
+                .any(|observer| observer.remote == remote && observer.token == request.token);
+
+        let response_type = match (confirmable, self.options.separate) {
+            (true, false) => MessageType::Acknowledgement,
+            (true, true) => {
+                let ack = empty(MessageType::Acknowledgement, request.message_id);
+                self.responses.insert(key, ack.clone());
+                self.send(&ack, remote);
+                MessageType::Confirmable
+            }
+            (false, _) => MessageType::NonConfirmable,
+        };
+        let message_id = if response_type == MessageType::Acknowledgement {
+            request.message_id
+        } else {
+            self.message_id()
+        };
+
+        let sequence = observing.then_some(self.sequence);
+        let response = match write(
+            response_type,
+            code,
+            message_id,
+            request.token,
+            sequence,
+            &payload,
+        ) {
+            Ok(response) => response,
+            Err(e) => {
+                println!("{remote}: failed to write the response: {e:?}");
+                return;
+            }
+        };
+        if response_type == MessageType::Acknowledgement {
+            self.responses.insert(key, response.clone());
+        }
+        self.send(&response, remote);
+
+        if self.responses.len() > RESPONSE_CACHE_SIZE {
+            self.responses.clear();
+            self.transmissions.clear();
+        }
+    }
+
+    /// Send `command` to all observers of `/commands`.
+    fn notify(&mut self, command: String) {
+        self.command = command;
+        self.sequence = (self.sequence + 1) & 0xff_ffff;
+        println!(
+            "/{COMMAND_PATH} = {:?}, notifying {} observer(s)",
+            self.command,
+            self.observers.len()
+        );
+
+        let observers: Vec<(SocketAddr, Vec<u8>)> = self
+            .observers
+            .iter()
+            .map(|observer| (observer.remote, observer.token.clone()))
+            .collect();
+        for (remote, token) in observers {
+            let message_id = self.message_id();
+            match write(
+                MessageType::Confirmable,
+                Code::CONTENT,
+                message_id,
+                &token,
+                Some(self.sequence),
+                self.command.as_bytes(),
+            ) {
+                Ok(notification) => {
+                    self.notifications.insert((remote, message_id), token);
+                    self.send(&notification, remote);
+                }
+                Err(e) => println!("{remote}: failed to write the notification: {e:?}"),
+            }
+        }
+    }
+
+    fn message_id(&mut self) -> u16 {
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        self.next_message_id
+    }
+
+    fn send(&self, message: &[u8], remote: SocketAddr) {
+        if let Err(e) = self.socket.send_to(message, remote) {
+            println!("{remote}: send failed: {e}");
+        }
+    }
+}
+
+fn empty(message_type: MessageType, message_id: u16) -> Vec<u8> {
+    let mut buf = [0; 4];
+    let len = MessageWriter::new(&mut buf, message_type, Code::EMPTY, message_id, &[])
+        .map(MessageWriter::finish)
+        .unwrap_or_default();
+    buf[..len].to_vec()
+}
+
+fn write(
+    message_type: MessageType,
+    code: Code,
+    message_id: u16,
+    token: &[u8],
+    observe: Option<u32>,
+    payload: &[u8],
+) -> Result<Vec<u8>, CoapError> {
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    let mut writer = MessageWriter::new(&mut buf, message_type, code, message_id, token)?;
+    if let Some(sequence) = observe {
+        writer.uint_option(option::OBSERVE, sequence)?;
+    }
+    if !payload.is_empty() {
+        writer.uint_option(option::CONTENT_FORMAT, content_format::TEXT_PLAIN as u32)?;
+        writer.payload(payload)?;
+    }
+    let len = writer.finish();
+    Ok(buf[..len].to_vec())
+}
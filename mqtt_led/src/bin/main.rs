@@ -41,6 +41,8 @@ pub struct Config {
     mqtt_password: &'static str,
 }
 
+mqtt_led::secrets!(wifi_psk, mqtt_password);
+
 // const SSID: &str = "";
 // const PASSWORD: &str = "";
 
@@ -144,7 +146,7 @@ async fn main(spawner: Spawner) {
 
         let mut config = ClientConfig::new(rust_mqtt::client::client_config::MqttVersion::MQTTv5, CountingRng(20000));
         config.add_username(app_config.mqtt_username);
-        config.add_password(app_config.mqtt_password);
+        config.add_password(SECRETS.mqtt_password.expose());
         config.add_client_id("clientId-2m3km334gd");
         config.max_packet_size = 100;
         let mut recv_buffer = [0; 80];
@@ -213,7 +215,7 @@ async fn connection(mut controller: WifiController<'static>) {
         if !matches!(controller.is_started(), Ok(true)) {
             let client_config = Configuration::Client(ClientConfiguration {
                 ssid: app_config.wifi_ssid.try_into().unwrap(),
-                password: app_config.wifi_psk.try_into().unwrap(),
+                password: (*SECRETS.wifi_psk.expose()).try_into().unwrap(),
                 ..Default::default()
             });
             controller.set_configuration(&client_config).unwrap();
@@ -0,0 +1,61 @@
+use core::fmt::{Debug, Formatter};
+
+use defmt::Format;
+
+/// Wrapper for credentials (PSKs, MQTT passwords, Thread network keys) that must never
+/// end up in logs.
+///
+/// `Debug` and `defmt::Format` only print a placeholder, the wrapped value has to be
+/// accessed explicitly with [`Secret::expose`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl<T> Format for Secret<T> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "Secret(<redacted>)")
+    }
+}
+
+/// Declares `SECRETS`, the credentials of the crate's toml_cfg `CONFIG` wrapped in [`Secret`].
+///
+/// toml_cfg can only initialise its fields with plain literals, so the wrapping happens once
+/// here and the rest of the binary only reads the credentials through `SECRETS`.
+///
+/// ```ignore
+/// secrets!(wifi_psk, mqtt_password);
+/// let psk: &str = SECRETS.wifi_psk.expose();
+/// ```
+#[macro_export]
+macro_rules! secrets {
+    ($($field:ident),+ $(,)?) => {
+        struct Secrets {
+            $($field: $crate::secret::Secret<&'static str>,)+
+        }
+
+        const SECRETS: Secrets = Secrets {
+            $($field: $crate::secret::Secret::new(CONFIG.$field),)+
+        };
+    };
+}
@@ -4,6 +4,16 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    #[default("wpa2-personal")]
+    wifi_auth_method: &'static str,
+    #[default("")]
+    wifi_eap_identity: &'static str,
+    #[default("")]
+    wifi_eap_username: &'static str,
+    #[default("")]
+    wifi_eap_password: &'static str,
+    #[default("")]
+    wifi_ca_cert: &'static str,
     #[default("")]
     mqtt_fqdn: &'static str,
     #[default(0)]
@@ -12,15 +22,54 @@ pub struct Config {
     mqtt_username: &'static str,
     #[default("")]
     mqtt_password: &'static str,
+    #[default("performance")]
+    power_profile: &'static str,
+    #[default(false)]
+    wifi_scan_on_boot: bool,
+    #[default("mqtt-led-relay")]
+    mdns_hostname: &'static str,
+    #[default("MQTT LED Relay")]
+    mdns_instance: &'static str,
+    #[default("pool.ntp.org")]
+    sntp_servers: &'static str,
+    #[default(3600)]
+    sntp_interval_secs: u64,
+    #[default("")]
+    espnow_role: &'static str,
+    #[default("")]
+    espnow_key: &'static str,
+    #[default(1)]
+    espnow_channel: u8,
 }
 
 fn main() {
     linker_be_nice();
+    copy_ca_cert();
     println!("cargo:rustc-link-arg=-Tdefmt.x");
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Copies the CA certificate configured in `wifi_ca_cert` (path relative to the crate root)
+/// to `OUT_DIR` so it can be embedded with `include_bytes!`. The ESP-IDF EAP client expects
+/// PEM data to be NUL-terminated. Without a configured certificate an empty file is written.
+fn copy_ca_cert() {
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("wifi_ca_cert.pem");
+    let path = CONFIG.wifi_ca_cert;
+
+    let mut cert = Vec::new();
+    if !path.is_empty() {
+        println!("cargo:rerun-if-changed={}", path);
+        cert = std::fs::read(path)
+            .unwrap_or_else(|e| panic!("Failed to read wifi_ca_cert `{}`: {}", path, e));
+        if cert.last() != Some(&0) {
+            cert.push(0);
+        }
+    }
+
+    std::fs::write(out, cert).unwrap();
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
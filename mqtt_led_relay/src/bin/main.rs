@@ -2,14 +2,23 @@
 #![no_main]
 
 use alloc::string::ToString;
-use defmt::{debug, info};
+use defmt::{debug, error, info};
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
-use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
+use mqtt_led_relay::espnow::{self, EspNowConfig, EspNowRole};
+use mqtt_led_relay::espnow_frame;
+use mqtt_led_relay::mdns::{self, MdnsConfig};
 use mqtt_led_relay::mqtt::create_mqtt_client;
-use mqtt_led_relay::wifi::create_wifi_stack;
+use mqtt_led_relay::outputs;
+use mqtt_led_relay::power::PowerProfile;
+use mqtt_led_relay::secret::Secret;
+use mqtt_led_relay::sizing;
+use mqtt_led_relay::sntp::{self, SntpConfig};
+use mqtt_led_relay::wifi_auth::{EapCredentials, WifiAuthMode, WifiCredentials};
+use mqtt_led_relay::wifi::{create_wifi_stack, WifiConfig};
 
 
 #[panic_handler]
@@ -25,6 +34,16 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    #[default("wpa2-personal")]
+    wifi_auth_method: &'static str,
+    #[default("")]
+    wifi_eap_identity: &'static str,
+    #[default("")]
+    wifi_eap_username: &'static str,
+    #[default("")]
+    wifi_eap_password: &'static str,
+    #[default("")]
+    wifi_ca_cert: &'static str,
     #[default("")]
     mqtt_fqdn: &'static str,
     #[default(0)]
@@ -33,8 +52,33 @@ pub struct Config {
     mqtt_username: &'static str,
     #[default("")]
     mqtt_password: &'static str,
+    #[default("performance")]
+    power_profile: &'static str,
+    #[default(false)]
+    wifi_scan_on_boot: bool,
+    #[default("mqtt-led-relay")]
+    mdns_hostname: &'static str,
+    #[default("MQTT LED Relay")]
+    mdns_instance: &'static str,
+    #[default("pool.ntp.org")]
+    sntp_servers: &'static str,
+    #[default(3600)]
+    sntp_interval_secs: u64,
+    #[default("")]
+    espnow_role: &'static str,
+    #[default("")]
+    espnow_key: &'static str,
+    #[default(1)]
+    espnow_channel: u8,
 }
 
+mqtt_led_relay::secrets!(wifi_psk, wifi_eap_password, mqtt_password, espnow_key);
+
+/// TXT entries of the `_mqtt-device._tcp` service, tells clients where to send commands
+static MDNS_TXT: [&str; 2] = ["cmd=mqtt_led_relay/command", "scan=mqtt_led_relay/scan"];
+
+static WIFI_CA_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/wifi_ca_cert.pem"));
+
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     // generator version: 0.3.1
@@ -46,28 +90,106 @@ async fn main(spawner: Spawner) {
     let app_config = CONFIG;
 
     info!("Config SSID: {}", app_config.wifi_ssid);
-    info!("Config PSK: {}", app_config.wifi_psk);
+    info!("Config Auth Method: {}", app_config.wifi_auth_method);
+    info!("Config EAP Identity: {}", app_config.wifi_eap_identity);
+    info!("Config EAP Username: {}", app_config.wifi_eap_username);
     info!("Config MQTT FQDN: {}", app_config.mqtt_fqdn);
     info!("Config MQTT Port: {}", app_config.mqtt_port);
-    info!("Config MQTT Username: {}", app_config.mqtt_username);    
+    info!("Config MQTT Username: {}", app_config.mqtt_username);
+    info!("Config Power Profile: {}", app_config.power_profile);
+
+    let power_profile = match app_config.power_profile.parse::<PowerProfile>() {
+        Ok(power_profile) => power_profile,
+        Err(e) => {
+            // the heap is not initialised yet, `to_string` would panic
+            error!("Invalid power profile: {:?}", e);
+            return;
+        }
+    };
 
-    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let config = esp_hal::Config::default().with_cpu_clock(power_profile.cpu_clock());
     let peripherals = esp_hal::init(config);
 
-    esp_alloc::heap_allocator!(size: 72 * 1024);
+    esp_alloc::heap_allocator!(size: sizing::HEAP_SIZE);
 
     let timer0 = SystemTimer::new(peripherals.SYSTIMER);
     esp_hal_embassy::init(timer0.alarm0);
 
     info!("Embassy initialized!");
 
+    sizing::log_budget();
+
+    // relay module on GPIO23 (power) and GPIO18, LED on GPIO7
+    let relay_power = Output::new(peripherals.GPIO23, Level::Low, OutputConfig::default());
+    let relay = Output::new(peripherals.GPIO18, Level::Low, OutputConfig::default());
+    let led = Output::new(peripherals.GPIO7, Level::Low, OutputConfig::default());
+    spawner.spawn(outputs::outputs_task(relay_power, relay, led)).unwrap();
+
+    let espnow_config = if app_config.espnow_role.is_empty() {
+        None
+    } else {
+        let role = match app_config.espnow_role.parse::<EspNowRole>() {
+            Ok(role) => role,
+            Err(e) => {
+                info!("Invalid ESP-NOW role: {:?}", e.to_string().as_str());
+                return;
+            }
+        };
+        let key = match espnow_frame::parse_key(SECRETS.espnow_key.expose()) {
+            Ok(key) => key,
+            Err(e) => {
+                info!("Invalid ESP-NOW key: {:?}", e.to_string().as_str());
+                return;
+            }
+        };
+        info!("Config ESP-NOW Role: {:?}", role);
+
+        if role == EspNowRole::Controller {
+            // BOOT button of the devkit toggles the relays of all peers
+            let button = Input::new(peripherals.GPIO9, InputConfig::default().with_pull(Pull::Up));
+            spawner.spawn(espnow::button_task(button)).unwrap();
+        }
+
+        Some(EspNowConfig {
+            role,
+            key: Secret::new(key),
+            channel: app_config.espnow_channel,
+        })
+    };
+
     info!("Initializing Wifi...");
     let mut rng = esp_hal::rng::Rng::new(peripherals.RNG);
     let timer1 = TimerGroup::new(peripherals.TIMG0);
 
+    let auth_mode = match app_config.wifi_auth_method.parse::<WifiAuthMode>() {
+        Ok(auth_mode) => auth_mode,
+        Err(e) => {
+            info!("Invalid wifi config: {:?}", e.to_string().as_str());
+            return;
+        }
+    };
+
+    let wifi_credentials = WifiCredentials {
+        ssid: app_config.wifi_ssid,
+        auth_mode,
+        psk: SECRETS.wifi_psk,
+        eap: EapCredentials {
+            identity: app_config.wifi_eap_identity,
+            username: app_config.wifi_eap_username,
+            password: SECRETS.wifi_eap_password,
+            ca_cert: (!WIFI_CA_CERT.is_empty()).then_some(WIFI_CA_CERT),
+        },
+    };
+
+    let wifi_config = WifiConfig {
+        credentials: wifi_credentials,
+        power_profile,
+        scan_on_boot: app_config.wifi_scan_on_boot,
+        espnow: espnow_config,
+    };
+
     let wifi_stack = match create_wifi_stack(
-        app_config.wifi_ssid,
-        app_config.wifi_psk,
+        wifi_config,
         spawner,
         timer1,
         rng,
@@ -81,7 +203,25 @@ async fn main(spawner: Spawner) {
         }
     };
 
-    create_mqtt_client(app_config.mqtt_username, app_config.mqtt_password, app_config.mqtt_fqdn, app_config.mqtt_port, "clientId-lkasd892", spawner, wifi_stack).await.unwrap();
+    if !app_config.mdns_hostname.is_empty() {
+        let mdns_config = MdnsConfig {
+            hostname: app_config.mdns_hostname,
+            service: "_mqtt-device._tcp",
+            instance: app_config.mdns_instance,
+            // the device does not accept connections itself, the TXT record points to the topics
+            port: 0,
+            txt: &MDNS_TXT,
+        };
+        spawner.spawn(mdns::responder_task(wifi_stack, mdns_config)).unwrap();
+    }
+
+    let sntp_config = SntpConfig {
+        servers: app_config.sntp_servers,
+        interval: Duration::from_secs(app_config.sntp_interval_secs),
+    };
+    spawner.spawn(sntp::sntp_task(wifi_stack, sntp_config)).unwrap();
+
+    create_mqtt_client(app_config.mqtt_username, SECRETS.mqtt_password, app_config.mqtt_fqdn, app_config.mqtt_port, "clientId-lkasd892", spawner, wifi_stack).await.unwrap();
 
     loop {
         rng.random();
@@ -0,0 +1,68 @@
+use defmt::{info, Format};
+
+use crate::outputs::{self, OutputKind};
+use crate::power::PowerProfile;
+use crate::wifi;
+
+/// Commands received as plain text on the MQTT command topic or the ESP-NOW link.
+///
+/// Format: `<command> [argument]`, e.g. `power low-power`.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// `power <performance|balanced|low-power>`
+    SetPowerProfile(PowerProfile),
+    /// `scan`, results are published on the scan topic
+    Scan,
+    /// `relay <on|off>`
+    Relay(bool),
+    /// `led <on|off>`
+    Led(bool),
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    NotUtf8,
+    UnknownCommand,
+    InvalidArgument,
+}
+
+impl Command {
+    pub fn parse(payload: &[u8]) -> Result<Command, CommandError> {
+        let payload = core::str::from_utf8(payload).map_err(|_| CommandError::NotUtf8)?;
+        let mut parts = payload.split_whitespace();
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("power"), Some(profile), None) => profile
+                .parse()
+                .map(Command::SetPowerProfile)
+                .map_err(|_| CommandError::InvalidArgument),
+            (Some("power"), _, _) => Err(CommandError::InvalidArgument),
+            (Some("scan"), None, None) => Ok(Command::Scan),
+            (Some("scan"), _, _) => Err(CommandError::InvalidArgument),
+            (Some("relay"), Some(state), None) => parse_on_off(state).map(Command::Relay),
+            (Some("relay"), _, _) => Err(CommandError::InvalidArgument),
+            (Some("led"), Some(state), None) => parse_on_off(state).map(Command::Led),
+            (Some("led"), _, _) => Err(CommandError::InvalidArgument),
+            _ => Err(CommandError::UnknownCommand),
+        }
+    }
+
+    pub async fn execute(self) {
+        info!("Handling command {:?}", self);
+
+        match self {
+            Command::SetPowerProfile(profile) => wifi::set_power_profile(profile).await,
+            Command::Scan => wifi::request_scan().await,
+            Command::Relay(on) => outputs::set(OutputKind::Relay, on).await,
+            Command::Led(on) => outputs::set(OutputKind::Led, on).await,
+        }
+    }
+}
+
+fn parse_on_off(state: &str) -> Result<bool, CommandError> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(CommandError::InvalidArgument),
+    }
+}
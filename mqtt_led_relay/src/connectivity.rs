@@ -0,0 +1,108 @@
+use core::net::IpAddr;
+
+use defmt::{debug, Format};
+use embassy_net::Stack;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    watch::{Receiver, Watch},
+};
+
+/// Maximum number of tasks that can subscribe to connectivity changes at the same time
+/// (the mDNS responder and the tasks waiting in [`wait_online`]).
+pub const MAX_SUBSCRIBERS: usize = 4;
+
+static CONNECTIVITY: Watch<CriticalSectionRawMutex, ConnectivityState, MAX_SUBSCRIBERS> =
+    Watch::new();
+
+pub type ConnectivityReceiver =
+    Receiver<'static, CriticalSectionRawMutex, ConnectivityState, MAX_SUBSCRIBERS>;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityState {
+    /// No connection attempt is in progress
+    Disconnected,
+    /// Trying to join the network (associating with the AP)
+    Associating,
+    /// Joined the network but no IP address is configured yet
+    LinkUp,
+    /// Network is usable with the given address
+    IpAcquired(IpAddr),
+    /// A previously working connection went away
+    Lost(LostReason),
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum LostReason {
+    /// The AP disconnected us or the link went down
+    LinkDown,
+    /// The link is still up but the IP configuration was removed (e.g. DHCP lease expired)
+    AddressLost,
+    /// Joining the network failed
+    ConnectFailed,
+}
+
+impl ConnectivityState {
+    pub fn is_online(&self) -> bool {
+        matches!(self, ConnectivityState::IpAcquired(_))
+    }
+}
+
+/// Subscribe to connectivity changes.
+///
+/// Returns `None` if all `MAX_SUBSCRIBERS` receivers are already in use.
+pub fn subscribe() -> Option<ConnectivityReceiver> {
+    CONNECTIVITY.receiver()
+}
+
+/// Last published connectivity state, `Disconnected` if nothing was published yet.
+pub fn current() -> ConnectivityState {
+    CONNECTIVITY
+        .try_get()
+        .unwrap_or(ConnectivityState::Disconnected)
+}
+
+/// Wait until the network is usable and return the acquired address.
+///
+/// Uses an own receiver which is released again once the address is available.
+pub async fn wait_online() -> Option<IpAddr> {
+    let mut receiver = subscribe()?;
+
+    match receiver.get_and(|state| state.is_online()).await {
+        ConnectivityState::IpAcquired(address) => Some(address),
+        _ => None,
+    }
+}
+
+pub(crate) fn publish(state: ConnectivityState) {
+    CONNECTIVITY.sender().send_if_modified(|current| {
+        if *current == Some(state) {
+            return false;
+        }
+
+        debug!("Connectivity: {:?} -> {:?}", current, state);
+        *current = Some(state);
+        true
+    });
+}
+
+/// Tracks the IP configuration of the stack and publishes `IpAcquired` / `Lost` states.
+#[embassy_executor::task]
+pub(crate) async fn ip_task(stack: Stack<'static>) {
+    debug!("IP task started");
+
+    loop {
+        stack.wait_config_up().await;
+
+        if let Some(config) = stack.config_v4() {
+            publish(ConnectivityState::IpAcquired(IpAddr::V4(config.address.address())));
+        }
+
+        stack.wait_config_down().await;
+
+        if stack.is_link_up() {
+            publish(ConnectivityState::Lost(LostReason::AddressLost));
+        } else {
+            publish(ConnectivityState::Lost(LostReason::LinkDown));
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! ESP-NOW control link between a controller and peer devices, works without the access point.
+//!
+//! Pairing: the controller broadcasts a `PairRequest` with a fresh nonce every
+//! [`PAIRING_INTERVAL`]. Every peer knowing the pre-shared key answers with a `PairAccept`
+//! carrying a new random session and the echoed nonce. Commands are sent per peer with the
+//! session and a strictly increasing sequence number, peers acknowledge every command and drop
+//! frames of old sessions or with old sequence numbers. All frames are authenticated, see
+//! [`crate::espnow_frame`].
+//!
+//! A recorded `PairRequest` can be replayed to make a peer switch to a new session, this only
+//! delays commands until the next pairing round but never executes old commands.
+//!
+//! ESP-NOW shares the radio with the station and uses its channel while associated. While the
+//! access point is unreachable the configured channel is used, which should be the channel of
+//! the access point so both cases match.
+
+use core::{fmt::Display, str::FromStr};
+
+use defmt::{debug, info, warn, Format};
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use esp_hal::{gpio::Input, rng::Rng};
+use esp_wifi::esp_now::{
+    EspNow, EspNowManager, EspNowReceiver, EspNowSender, PeerInfo, ReceivedData, BROADCAST_ADDRESS,
+};
+use heapless::{String, Vec};
+
+use crate::command::Command;
+use crate::connectivity;
+use crate::espnow_frame::{
+    self, AckStatus, Frame, FrameKind, Freshness, ReplayGuard, KEY_LEN, MAX_FRAME_LEN,
+};
+use crate::secret::Secret;
+
+const MAX_PEERS: usize = 8;
+pub const MAX_COMMAND_LEN: usize = 64;
+
+const PAIRING_INTERVAL: Duration = Duration::from_secs(60);
+const ACK_TIMEOUT: Duration = Duration::from_millis(200);
+const SEND_ATTEMPTS: usize = 3;
+/// How often a peer checks whether it has to switch to the configured channel
+const CHANNEL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum EspNowRole {
+    /// `controller`: pairs with peers and sends commands to them
+    Controller,
+    /// `peer`: executes commands of the paired controller
+    Peer,
+}
+
+impl FromStr for EspNowRole {
+    type Err = UnknownEspNowRole;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "controller" => Ok(EspNowRole::Controller),
+            "peer" => Ok(EspNowRole::Peer),
+            _ => Err(UnknownEspNowRole),
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownEspNowRole;
+
+impl Display for UnknownEspNowRole {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "unknown ESP-NOW role, expected one of controller, peer")
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy)]
+pub struct EspNowConfig {
+    pub role: EspNowRole,
+    /// Pre-shared key of all devices of the link
+    pub key: Secret<[u8; KEY_LEN]>,
+    /// Channel used while the station is not associated
+    pub channel: u8,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// All paired peers
+    All,
+    Peer([u8; 6]),
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum EspNowLinkError {
+    CommandTooLong,
+}
+
+impl Display for EspNowLinkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ESP-NOW link error: {:?}", self)
+    }
+}
+
+struct Request {
+    target: Target,
+    command: String<MAX_COMMAND_LEN>,
+}
+
+static REQUESTS: Channel<CriticalSectionRawMutex, Request, 4> = Channel::new();
+
+/// Send a text command, e.g. `relay on`, to paired peers. Only handled by a controller.
+///
+/// Delivery happens in the background, acknowledgements and failures are logged.
+pub async fn send_command(target: Target, command: &str) -> Result<(), EspNowLinkError> {
+    let command = String::try_from(command).map_err(|_| EspNowLinkError::CommandTooLong)?;
+    REQUESTS.send(Request { target, command }).await;
+    Ok(())
+}
+
+struct Link {
+    manager: EspNowManager<'static>,
+    sender: EspNowSender<'static>,
+    receiver: EspNowReceiver<'static>,
+    key: Secret<[u8; KEY_LEN]>,
+    channel: u8,
+    address: [u8; 6],
+    packet: [u8; MAX_FRAME_LEN],
+}
+
+impl Link {
+    async fn send(&mut self, destination: [u8; 6], frame: &Frame<'_>) {
+        let len =
+            match espnow_frame::encode(frame, self.key.expose(), self.address, &mut self.packet) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("Failed to encode ESP-NOW frame: {:?}", e);
+                    return;
+                }
+            };
+
+        if let Err(e) = self
+            .sender
+            .send_async(&destination, &self.packet[..len])
+            .await
+        {
+            debug!("Failed to send ESP-NOW frame: {:?}", e);
+        }
+    }
+
+    fn add_peer(&mut self, address: [u8; 6]) {
+        if self.manager.peer_exists(&address) {
+            return;
+        }
+
+        let peer = PeerInfo {
+            peer_address: address,
+            lmk: None,
+            channel: None,
+            encrypt: false,
+        };
+        if let Err(e) = self.manager.add_peer(peer) {
+            warn!("Failed to add ESP-NOW peer {:?}: {:?}", address, e);
+        }
+    }
+
+    /// Switch to the configured channel while the station is not associated.
+    fn follow_channel(&mut self) {
+        if connectivity::current().is_online() {
+            return;
+        }
+
+        if let Err(e) = self.manager.set_channel(self.channel) {
+            debug!("Failed to set ESP-NOW channel: {:?}", e);
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn espnow_task(esp_now: EspNow<'static>, config: EspNowConfig, rng: Rng) {
+    let mut address = [0; 6];
+    esp_wifi::wifi::sta_mac(&mut address);
+    info!("ESP-NOW {:?} started on {:?}", config.role, address);
+
+    let (manager, sender, receiver) = esp_now.split();
+    let mut link = Link {
+        manager,
+        sender,
+        receiver,
+        key: config.key,
+        channel: config.channel,
+        address,
+        packet: [0; MAX_FRAME_LEN],
+    };
+
+    match config.role {
+        EspNowRole::Controller => run_controller(&mut link, rng).await,
+        EspNowRole::Peer => run_peer(&mut link, rng).await,
+    }
+}
+
+struct Peer {
+    address: [u8; 6],
+    session: u32,
+    next_seq: u32,
+}
+
+async fn run_controller(link: &mut Link, mut rng: Rng) -> ! {
+    let mut peers: Vec<Peer, MAX_PEERS> = Vec::new();
+    let mut nonce: u32 = 0;
+    let mut next_pairing = Instant::now();
+
+    loop {
+        match select3(
+            link.receiver.receive_async(),
+            REQUESTS.receive(),
+            Timer::at(next_pairing),
+        )
+        .await
+        {
+            Either3::First(received) => {
+                let Some(frame) = decode(link, &received) else {
+                    continue;
+                };
+                if frame.kind != FrameKind::PairAccept || frame.payload != &nonce.to_be_bytes()[..]
+                {
+                    continue;
+                }
+
+                let address = received.info.src_address;
+                let peer = Peer {
+                    address,
+                    session: frame.session,
+                    next_seq: 1,
+                };
+                match peers.iter_mut().find(|peer| peer.address == address) {
+                    Some(existing) => *existing = peer,
+                    None => {
+                        if peers.push(peer).is_err() {
+                            warn!("Too many ESP-NOW peers, ignoring {:?}", address);
+                            continue;
+                        }
+                        info!("Paired with ESP-NOW peer {:?}", address);
+                    }
+                }
+                link.add_peer(address);
+            }
+            Either3::Second(request) => {
+                link.follow_channel();
+                for peer in peers.iter_mut() {
+                    if matches!(request.target, Target::Peer(address) if address != peer.address) {
+                        continue;
+                    }
+                    deliver(link, peer, request.command.as_bytes()).await;
+                }
+            }
+            Either3::Third(_) => {
+                link.follow_channel();
+                nonce = rng.random();
+                let request = Frame {
+                    kind: FrameKind::PairRequest,
+                    session: nonce,
+                    seq: 0,
+                    payload: &[],
+                };
+                link.send(BROADCAST_ADDRESS, &request).await;
+                next_pairing = Instant::now() + PAIRING_INTERVAL;
+            }
+        }
+    }
+}
+
+/// Send a command to a peer, retransmitted with the same sequence number until acknowledged.
+async fn deliver(link: &mut Link, peer: &mut Peer, command: &[u8]) {
+    let seq = peer.next_seq;
+    peer.next_seq = peer.next_seq.wrapping_add(1);
+
+    let frame = Frame {
+        kind: FrameKind::Command,
+        session: peer.session,
+        seq,
+        payload: command,
+    };
+
+    for _ in 0..SEND_ATTEMPTS {
+        link.send(peer.address, &frame).await;
+
+        let ack = with_timeout(ACK_TIMEOUT, async {
+            loop {
+                let received = link.receiver.receive_async().await;
+                if received.info.src_address != peer.address {
+                    continue;
+                }
+                let Some(ack) = decode(link, &received) else {
+                    continue;
+                };
+                if ack.kind == FrameKind::Ack && ack.session == peer.session && ack.seq == seq {
+                    return AckStatus::from_payload(ack.payload);
+                }
+            }
+        })
+        .await;
+
+        match ack {
+            Ok(Some(AckStatus::Ok)) => {
+                debug!(
+                    "ESP-NOW peer {:?} acknowledged command {}",
+                    peer.address, seq
+                );
+                return;
+            }
+            Ok(status) => {
+                warn!(
+                    "ESP-NOW peer {:?} rejected command: {:?}",
+                    peer.address, status
+                );
+                return;
+            }
+            Err(_) => {}
+        }
+    }
+
+    warn!(
+        "ESP-NOW peer {:?} did not acknowledge command {}",
+        peer.address, seq
+    );
+}
+
+async fn run_peer(link: &mut Link, mut rng: Rng) -> ! {
+    let mut controller: Option<([u8; 6], ReplayGuard, AckStatus)> = None;
+
+    loop {
+        let received =
+            match with_timeout(CHANNEL_CHECK_INTERVAL, link.receiver.receive_async()).await {
+                Ok(received) => received,
+                Err(_) => {
+                    link.follow_channel();
+                    continue;
+                }
+            };
+
+        let Some(frame) = decode(link, &received) else {
+            continue;
+        };
+        let source = received.info.src_address;
+
+        match frame.kind {
+            FrameKind::PairRequest => {
+                let session = rng.random();
+                link.add_peer(source);
+
+                let nonce = frame.session.to_be_bytes();
+                let accept = Frame {
+                    kind: FrameKind::PairAccept,
+                    session,
+                    seq: 0,
+                    payload: &nonce,
+                };
+                link.send(source, &accept).await;
+
+                if controller.map(|(address, ..)| address) != Some(source) {
+                    info!("Paired with ESP-NOW controller {:?}", source);
+                }
+                controller = Some((source, ReplayGuard::new(session), AckStatus::Ok));
+            }
+            FrameKind::Command => {
+                let Some((address, guard, last_status)) = controller.as_mut() else {
+                    continue;
+                };
+                if *address != source {
+                    continue;
+                }
+
+                let status = match guard.check(frame.session, frame.seq) {
+                    Freshness::Fresh => {
+                        *last_status = match Command::parse(frame.payload) {
+                            Ok(command) => {
+                                command.execute().await;
+                                AckStatus::Ok
+                            }
+                            Err(e) => {
+                                warn!("Invalid ESP-NOW command: {:?}", e);
+                                AckStatus::InvalidCommand
+                            }
+                        };
+                        *last_status
+                    }
+                    Freshness::Duplicate => *last_status,
+                    Freshness::Replayed => {
+                        warn!("Dropping replayed ESP-NOW command from {:?}", source);
+                        continue;
+                    }
+                };
+
+                let ack = Frame {
+                    kind: FrameKind::Ack,
+                    session: frame.session,
+                    seq: frame.seq,
+                    payload: &[status as u8],
+                };
+                link.send(source, &ack).await;
+            }
+            FrameKind::PairAccept | FrameKind::Ack => {}
+        }
+    }
+}
+
+fn decode<'a>(link: &Link, received: &'a ReceivedData) -> Option<Frame<'a>> {
+    match espnow_frame::decode(
+        received.data(),
+        link.key.expose(),
+        received.info.src_address,
+    ) {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            debug!(
+                "Ignoring ESP-NOW frame from {:?}: {:?}",
+                received.info.src_address, e
+            );
+            None
+        }
+    }
+}
+
+/// Toggles the relay of all paired peers on every press of the (active low) button.
+#[embassy_executor::task]
+pub async fn button_task(mut button: Input<'static>) {
+    let mut on = false;
+
+    loop {
+        button.wait_for_falling_edge().await;
+        // debounce
+        Timer::after(Duration::from_millis(50)).await;
+        if button.is_high() {
+            continue;
+        }
+
+        on = !on;
+        let command = if on { "relay on" } else { "relay off" };
+        info!("Button pressed, sending `{}` to all ESP-NOW peers", command);
+        if let Err(e) = send_command(Target::All, command).await {
+            warn!("Failed to send ESP-NOW command: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Framing and authentication of the ESP-NOW control link, see [`crate::espnow`].
+//!
+//! Frame layout (all integers big endian):
+//!
+//! | offset | size | field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 2    | magic `RL`                              |
+//! | 2      | 1    | version                                 |
+//! | 3      | 1    | [`FrameKind`]                           |
+//! | 4      | 4    | session                                 |
+//! | 8      | 4    | sequence number                         |
+//! | 12     | 1    | payload length                          |
+//! | 13     | n    | payload                                 |
+//! | 13 + n | 8    | HMAC-SHA256 tag, truncated to 8 bytes   |
+//!
+//! The tag covers the MAC address of the sender followed by header and payload, so a frame
+//! cannot be reflected by another device.
+
+use defmt::Format;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const MAGIC: [u8; 2] = *b"RL";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 13;
+const TAG_LEN: usize = 8;
+
+pub const KEY_LEN: usize = 16;
+/// Largest frame ESP-NOW can carry
+pub const MAX_FRAME_LEN: usize = 250;
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - HEADER_LEN - TAG_LEN;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Broadcast by the controller, the session field carries a nonce echoed in the accept
+    PairRequest = 1,
+    /// Peer to controller, carries the new session and the echoed nonce as payload
+    PairAccept = 2,
+    /// Controller to peer, the payload is a text command, see [`crate::command::Command`]
+    Command = 3,
+    /// Peer to controller, echoes session and sequence number, payload is an [`AckStatus`]
+    Ack = 4,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = FrameError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(FrameKind::PairRequest),
+            2 => Ok(FrameKind::PairAccept),
+            3 => Ok(FrameKind::Command),
+            4 => Ok(FrameKind::Ack),
+            _ => Err(FrameError::UnknownKind),
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum AckStatus {
+    Ok = 0,
+    InvalidCommand = 1,
+}
+
+impl AckStatus {
+    pub fn from_payload(payload: &[u8]) -> Option<AckStatus> {
+        match payload {
+            [0] => Some(AckStatus::Ok),
+            [1] => Some(AckStatus::InvalidCommand),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion,
+    UnknownKind,
+    PayloadTooLarge,
+    BufferTooSmall,
+    BadTag,
+    InvalidKey,
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ESP-NOW frame error: {:?}", self)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub kind: FrameKind,
+    pub session: u32,
+    pub seq: u32,
+    pub payload: &'a [u8],
+}
+
+/// Parse the pre-shared key from 32 hex digits.
+pub fn parse_key(hex: &str) -> Result<[u8; KEY_LEN], FrameError> {
+    let hex = hex.as_bytes();
+    // `from_str_radix` would also accept a sign
+    if hex.len() != 2 * KEY_LEN || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Err(FrameError::InvalidKey);
+    }
+
+    let (pairs, _) = hex.as_chunks::<2>();
+    let mut key = [0; KEY_LEN];
+    for (byte, digits) in key.iter_mut().zip(pairs) {
+        let digits = core::str::from_utf8(digits).map_err(|_| FrameError::InvalidKey)?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| FrameError::InvalidKey)?;
+    }
+
+    Ok(key)
+}
+
+/// Encode and authenticate a frame sent by `source`. Returns the length of the frame.
+pub fn encode(
+    frame: &Frame<'_>,
+    key: &[u8; KEY_LEN],
+    source: [u8; 6],
+    buf: &mut [u8],
+) -> Result<usize, FrameError> {
+    if frame.payload.len() > MAX_PAYLOAD_LEN {
+        return Err(FrameError::PayloadTooLarge);
+    }
+    let len = HEADER_LEN + frame.payload.len();
+    if buf.len() < len + TAG_LEN {
+        return Err(FrameError::BufferTooSmall);
+    }
+
+    buf[0..2].copy_from_slice(&MAGIC);
+    buf[2] = VERSION;
+    buf[3] = frame.kind as u8;
+    buf[4..8].copy_from_slice(&frame.session.to_be_bytes());
+    buf[8..12].copy_from_slice(&frame.seq.to_be_bytes());
+    buf[12] = frame.payload.len() as u8;
+    buf[HEADER_LEN..len].copy_from_slice(frame.payload);
+
+    let tag = tag(key, source, &buf[..len]);
+    buf[len..len + TAG_LEN].copy_from_slice(&tag);
+
+    Ok(len + TAG_LEN)
+}
+
+/// Verify and decode a frame received from `source`.
+pub fn decode<'a>(
+    packet: &'a [u8],
+    key: &[u8; KEY_LEN],
+    source: [u8; 6],
+) -> Result<Frame<'a>, FrameError> {
+    if packet.len() < HEADER_LEN + TAG_LEN {
+        return Err(FrameError::Truncated);
+    }
+    if packet[0..2] != MAGIC {
+        return Err(FrameError::BadMagic);
+    }
+    if packet[2] != VERSION {
+        return Err(FrameError::UnsupportedVersion);
+    }
+
+    let len = HEADER_LEN + packet[12] as usize;
+    let received_tag = packet
+        .get(len..len + TAG_LEN)
+        .ok_or(FrameError::Truncated)?;
+
+    // constant time comparison
+    let expected_tag = tag(key, source, &packet[..len]);
+    let difference = expected_tag
+        .iter()
+        .zip(received_tag)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    if difference != 0 {
+        return Err(FrameError::BadTag);
+    }
+
+    Ok(Frame {
+        kind: FrameKind::try_from(packet[3])?,
+        session: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        seq: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        payload: &packet[HEADER_LEN..len],
+    })
+}
+
+fn tag(key: &[u8; KEY_LEN], source: [u8; 6], data: &[u8]) -> [u8; TAG_LEN] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    mac.update(&source);
+    mac.update(data);
+    let digest = mac.finalize().into_bytes();
+
+    let mut tag = [0; TAG_LEN];
+    tag.copy_from_slice(&digest[..TAG_LEN]);
+    tag
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    /// Retransmission of the last accepted frame, acknowledge again without executing it
+    Duplicate,
+    /// Old sequence number or foreign session
+    Replayed,
+}
+
+/// Sequence number tracking of a peer for one pairing session.
+///
+/// Sequence numbers have to increase strictly within a session. A new session is chosen by the
+/// peer on every pairing, so frames recorded in earlier sessions are rejected as well. A
+/// sequence number that wrapped around counts as replayed until the next pairing.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayGuard {
+    session: u32,
+    last_seq: Option<u32>,
+}
+
+impl ReplayGuard {
+    pub const fn new(session: u32) -> Self {
+        ReplayGuard {
+            session,
+            last_seq: None,
+        }
+    }
+
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    /// Check a received frame, fresh frames are recorded as the last accepted.
+    pub fn check(&mut self, session: u32, seq: u32) -> Freshness {
+        if session != self.session {
+            return Freshness::Replayed;
+        }
+
+        match self.last_seq {
+            Some(last) if seq == last => Freshness::Duplicate,
+            Some(last) if seq < last => Freshness::Replayed,
+            _ => {
+                self.last_seq = Some(seq);
+                Freshness::Fresh
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_LEN] = *b"0123456789abcdef";
+    const CONTROLLER: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const PEER: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+    fn command(payload: &[u8]) -> Frame<'_> {
+        Frame {
+            kind: FrameKind::Command,
+            session: 0x1234_5678,
+            seq: 42,
+            payload,
+        }
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d0E0F"),
+            Ok([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15])
+        );
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d0e"),
+            Err(FrameError::InvalidKey)
+        );
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d0e0g"),
+            Err(FrameError::InvalidKey)
+        );
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d0e+f"),
+            Err(FrameError::InvalidKey)
+        );
+        assert_eq!(
+            parse_key("000102030405060708090a0b0c0d\u{e9}0f"),
+            Err(FrameError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = command(b"toggle");
+        let len = encode(&frame, &KEY, CONTROLLER, &mut buf).unwrap();
+
+        assert_eq!(len, HEADER_LEN + 6 + TAG_LEN);
+        assert_eq!(&buf[..4], &[b'R', b'L', VERSION, FrameKind::Command as u8]);
+        assert_eq!(decode(&buf[..len], &KEY, CONTROLLER), Ok(frame));
+
+        let accept = Frame {
+            kind: FrameKind::PairAccept,
+            session: 7,
+            seq: 0,
+            payload: &[],
+        };
+        let len = encode(&accept, &KEY, PEER, &mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN + TAG_LEN);
+        assert_eq!(decode(&buf[..len], &KEY, PEER), Ok(accept));
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let payload = [0; MAX_PAYLOAD_LEN + 1];
+        let mut buf = [0; MAX_FRAME_LEN + 1];
+        assert_eq!(
+            encode(&command(&payload), &KEY, CONTROLLER, &mut buf),
+            Err(FrameError::PayloadTooLarge)
+        );
+        assert_eq!(
+            encode(
+                &command(&payload[..MAX_PAYLOAD_LEN]),
+                &KEY,
+                CONTROLLER,
+                &mut buf
+            ),
+            Ok(MAX_FRAME_LEN)
+        );
+        assert_eq!(
+            encode(
+                &command(b"on"),
+                &KEY,
+                CONTROLLER,
+                &mut buf[..HEADER_LEN + 2 + TAG_LEN - 1]
+            ),
+            Err(FrameError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_frames() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = encode(&command(b"on"), &KEY, CONTROLLER, &mut buf).unwrap();
+
+        // every byte of header, payload and tag is covered, a changed length misses the tag
+        for i in 3..len {
+            let mut tampered = buf;
+            tampered[i] ^= 0x01;
+            let expected = if i == 12 {
+                FrameError::Truncated
+            } else {
+                FrameError::BadTag
+            };
+            assert_eq!(
+                decode(&tampered[..len], &KEY, CONTROLLER),
+                Err(expected),
+                "byte {}",
+                i
+            );
+        }
+
+        // reflected by another device or sent with another key
+        assert_eq!(decode(&buf[..len], &KEY, PEER), Err(FrameError::BadTag));
+        let other_key = *b"fedcba9876543210";
+        assert_eq!(
+            decode(&buf[..len], &other_key, CONTROLLER),
+            Err(FrameError::BadTag)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = encode(&command(b"on"), &KEY, CONTROLLER, &mut buf).unwrap();
+
+        assert_eq!(
+            decode(&buf[..HEADER_LEN + TAG_LEN - 1], &KEY, CONTROLLER),
+            Err(FrameError::Truncated)
+        );
+        assert_eq!(
+            decode(&buf[..len - 1], &KEY, CONTROLLER),
+            Err(FrameError::Truncated)
+        );
+
+        let mut bad_magic = buf;
+        bad_magic[0] = b'X';
+        assert_eq!(
+            decode(&bad_magic[..len], &KEY, CONTROLLER),
+            Err(FrameError::BadMagic)
+        );
+
+        let mut bad_version = buf;
+        bad_version[2] = VERSION + 1;
+        assert_eq!(
+            decode(&bad_version[..len], &KEY, CONTROLLER),
+            Err(FrameError::UnsupportedVersion)
+        );
+
+        // authentic, but of a kind this firmware does not know
+        let mut unknown = buf;
+        unknown[3] = 9;
+        let tag = tag(&KEY, CONTROLLER, &unknown[..len - TAG_LEN]);
+        unknown[len - TAG_LEN..len].copy_from_slice(&tag);
+        assert_eq!(
+            decode(&unknown[..len], &KEY, CONTROLLER),
+            Err(FrameError::UnknownKind)
+        );
+    }
+
+    #[test]
+    fn ack_status() {
+        assert_eq!(AckStatus::from_payload(&[0]), Some(AckStatus::Ok));
+        assert_eq!(
+            AckStatus::from_payload(&[AckStatus::InvalidCommand as u8]),
+            Some(AckStatus::InvalidCommand)
+        );
+        assert_eq!(AckStatus::from_payload(&[]), None);
+        assert_eq!(AckStatus::from_payload(&[0, 0]), None);
+    }
+
+    #[test]
+    fn replay_guard_accepts_increasing_sequence_numbers() {
+        let mut guard = ReplayGuard::new(7);
+
+        assert_eq!(guard.check(7, 1), Freshness::Fresh);
+        assert_eq!(guard.check(7, 1), Freshness::Duplicate);
+        // lost frames leave gaps
+        assert_eq!(guard.check(7, 5), Freshness::Fresh);
+        assert_eq!(guard.check(7, 3), Freshness::Replayed);
+        assert_eq!(guard.check(7, 1), Freshness::Replayed);
+        assert_eq!(guard.check(7, 5), Freshness::Duplicate);
+        assert_eq!(guard.check(7, 6), Freshness::Fresh);
+    }
+
+    #[test]
+    fn replay_guard_rejects_other_sessions() {
+        let mut guard = ReplayGuard::new(7);
+        assert_eq!(guard.check(7, 10), Freshness::Fresh);
+        assert_eq!(guard.check(8, 11), Freshness::Replayed);
+
+        // after pairing again frames of the old session are replayed
+        let mut guard = ReplayGuard::new(8);
+        assert_eq!(guard.session(), 8);
+        assert_eq!(guard.check(7, 11), Freshness::Replayed);
+        assert_eq!(guard.check(8, 1), Freshness::Fresh);
+    }
+
+    #[test]
+    fn replay_guard_does_not_wrap() {
+        let mut guard = ReplayGuard::new(7);
+        assert_eq!(guard.check(7, u32::MAX - 1), Freshness::Fresh);
+        assert_eq!(guard.check(7, u32::MAX), Freshness::Fresh);
+        assert_eq!(guard.check(7, u32::MAX), Freshness::Duplicate);
+        assert_eq!(guard.check(7, 0), Freshness::Replayed);
+        assert_eq!(guard.check(7, 1), Freshness::Replayed);
+
+        let mut guard = ReplayGuard::new(8);
+        assert_eq!(guard.check(8, 0), Freshness::Fresh);
+    }
+}
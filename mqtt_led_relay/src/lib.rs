@@ -1,4 +1,27 @@
 #![no_std]
 
+extern crate alloc;
+
+pub mod command;
+pub mod connectivity;
+pub mod espnow;
+pub mod espnow_frame;
+pub mod mdns;
+pub mod mdns_packet;
+#[cfg(feature = "ipv6")]
+pub mod nd_packet;
+pub mod outputs;
+pub mod power;
+pub mod scan;
+// shared with the other examples
+#[path = "../../mqtt_led/src/secret.rs"]
+pub mod secret;
+pub mod sizing;
+#[cfg(feature = "ipv6")]
+pub mod slaac;
+pub mod sntp;
+pub mod sntp_packet;
 pub mod wifi;
-pub mod mqtt;
\ No newline at end of file
+pub mod wifi_auth;
+pub mod mqtt;
+pub mod mqtt_transport;
\ No newline at end of file
@@ -0,0 +1,241 @@
+use core::net::{IpAddr, Ipv4Addr};
+
+use defmt::{debug, error, info, warn, Format};
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::connectivity::{self, ConnectivityState};
+use crate::mdns_packet::{self, Asked, PacketError, ResponderRecords, MDNS_IPV4, MDNS_PORT};
+use crate::sizing::MDNS_BUFFER_SIZE;
+
+const RECORD_TTL: u32 = 120;
+const QUERY_ATTEMPTS: usize = 3;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the responder announces, see [`responder_task`].
+#[derive(Debug, Format, Clone, Copy)]
+pub struct MdnsConfig {
+    /// Answered as `<hostname>.local`
+    pub hostname: &'static str,
+    /// DNS-SD service type without `.local`, e.g. `_mqtt-device._tcp`
+    pub service: &'static str,
+    /// Service instance name, e.g. `Relay Kitchen`
+    pub instance: &'static str,
+    /// Port published in the SRV record
+    pub port: u16,
+    /// `key=value` entries of the TXT record
+    pub txt: &'static [&'static str],
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum MdnsError {
+    Socket,
+    Packet(PacketError),
+    Timeout,
+}
+
+impl From<PacketError> for MdnsError {
+    fn from(e: PacketError) -> Self {
+        MdnsError::Packet(e)
+    }
+}
+
+impl core::fmt::Display for MdnsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "mDNS error: {:?}", self)
+    }
+}
+
+/// Whether `name` has to be resolved with mDNS instead of unicast DNS.
+pub fn is_local_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.').as_bytes();
+    name.len() > 6 && name[name.len() - 6..].eq_ignore_ascii_case(b".local")
+}
+
+/// Resolve a `.local` name with a one-shot mDNS query (RFC 6762, section 5.1).
+pub async fn resolve(stack: Stack<'static>, name: &str) -> Result<Ipv4Addr, MdnsError> {
+    let name = name.trim_end_matches('.');
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).map_err(|_| MdnsError::Socket)?;
+
+    let mut packet = [0; MDNS_BUFFER_SIZE];
+    let group = IpEndpoint::new(MDNS_IPV4.into(), MDNS_PORT);
+
+    for attempt in 0..QUERY_ATTEMPTS {
+        debug!("mDNS query for {} (attempt {})", name, attempt + 1);
+
+        let len = mdns_packet::encode_query(0, name, &mut packet)?;
+        socket
+            .send_to(&packet[..len], group)
+            .await
+            .map_err(|_| MdnsError::Socket)?;
+
+        let answer = with_timeout(QUERY_TIMEOUT, async {
+            loop {
+                let (len, _) = match socket.recv_from(&mut packet).await {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+
+                match mdns_packet::find_a_record(&packet[..len], name) {
+                    Ok(Some(address)) => return address,
+                    Ok(None) => {}
+                    Err(e) => debug!("Ignoring malformed mDNS packet: {:?}", e),
+                }
+            }
+        })
+        .await;
+
+        if let Ok(address) = answer {
+            info!("Resolved {} to {} via mDNS", name, address);
+            return Ok(address);
+        }
+    }
+
+    Err(MdnsError::Timeout)
+}
+
+/// Answers queries for `<hostname>.local` and the configured DNS-SD service.
+///
+/// Announces the records after every (re)connect and every address change.
+#[embassy_executor::task]
+pub async fn responder_task(stack: Stack<'static>, config: MdnsConfig) {
+    info!("mDNS responder started for {}.local", config.hostname);
+
+    let Some(mut connectivity) = connectivity::subscribe() else {
+        error!("No connectivity receiver left for the mDNS responder");
+        return;
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(MDNS_PORT) {
+        error!("Failed to bind mDNS socket: {:?}", e);
+        return;
+    }
+
+    let mut packet = [0; MDNS_BUFFER_SIZE];
+    let group = IpEndpoint::new(MDNS_IPV4.into(), MDNS_PORT);
+
+    loop {
+        let address = match connectivity.get_and(|state| state.is_online()).await {
+            ConnectivityState::IpAcquired(IpAddr::V4(address)) => address,
+            _ => {
+                Timer::after(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        if let Err(e) = stack.join_multicast_group(MDNS_IPV4) {
+            warn!("Failed to join mDNS multicast group: {:?}", e);
+        }
+
+        let records = records(&config, address);
+
+        let announce = Asked {
+            host: true,
+            service: true,
+            instance: true,
+            services_enumeration: false,
+            unicast: false,
+        };
+        for _ in 0..2 {
+            match mdns_packet::encode_response(&records, &announce, &mut packet) {
+                Ok(len) => {
+                    if let Err(e) = socket.send_to(&packet[..len], group).await {
+                        warn!("Failed to send mDNS announcement: {:?}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to encode mDNS announcement: {:?}", e);
+                    return;
+                }
+            }
+            Timer::after(Duration::from_secs(1)).await;
+        }
+
+        loop {
+            // receiving is cancel safe, a datagram is only taken from the socket when returned
+            let received = match select(socket.recv_from(&mut packet), connectivity.changed()).await
+            {
+                Either::First(received) => received,
+                Either::Second(state) => {
+                    // announce the new address right away instead of on the next query
+                    debug!("mDNS: connectivity changed to {:?}", state);
+                    break;
+                }
+            };
+            let (len, meta) = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!("mDNS receive error: {:?}", e);
+                    continue;
+                }
+            };
+
+            let asked = match mdns_packet::parse_query(&packet[..len], &records) {
+                Ok(asked) if asked.any() => asked,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!("Ignoring malformed mDNS packet: {:?}", e);
+                    continue;
+                }
+            };
+
+            debug!("mDNS query from {:?}: {:?}", meta.endpoint, asked);
+
+            // legacy unicast queries and QU questions are answered directly
+            let destination = if meta.endpoint.port != MDNS_PORT || asked.unicast {
+                meta.endpoint
+            } else {
+                group
+            };
+
+            match mdns_packet::encode_response(&records, &asked, &mut packet) {
+                Ok(len) => {
+                    if let Err(e) = socket.send_to(&packet[..len], destination).await {
+                        warn!("Failed to send mDNS response: {:?}", e);
+                    }
+                }
+                Err(e) => error!("Failed to encode mDNS response: {:?}", e),
+            }
+        }
+    }
+}
+
+fn records(config: &MdnsConfig, address: Ipv4Addr) -> ResponderRecords<'static> {
+    ResponderRecords {
+        hostname: config.hostname,
+        address,
+        service: config.service,
+        instance: config.instance,
+        port: config.port,
+        txt: config.txt,
+        ttl: RECORD_TTL,
+    }
+}
@@ -0,0 +1,889 @@
+//! Minimal mDNS (RFC 6762) / DNS-SD (RFC 6763) message encoding and decoding.
+//!
+//! Only what the responder and the `.local` resolver need is supported: A, PTR, SRV and
+//! TXT records of class IN. Names are always written uncompressed, compressed names are
+//! understood when parsing.
+
+use core::net::Ipv4Addr;
+
+use defmt::Format;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+/// Top bit of the class field: "unicast response" in questions, "cache flush" in records
+const CLASS_FLAG: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+const HEADER_LEN: usize = 12;
+const MAX_POINTER_JUMPS: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+
+/// Service type enumeration name (RFC 6763, section 9)
+const SERVICES_NAME: [&str; 2] = ["_services._dns-sd._udp", "local"];
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    /// The packet ended in the middle of a field
+    Truncated,
+    /// The output buffer is too small for the message
+    BufferTooSmall,
+    /// A label is empty, longer than 63 bytes or uses a reserved label type
+    InvalidLabel,
+    /// Too many compression pointers, probably a loop
+    TooManyPointers,
+    /// The name is longer than 255 bytes
+    NameTooLong,
+}
+
+/// Records published by the responder.
+///
+/// `hostname`, `service` and `instance` are given without the `.local` suffix, e.g.
+/// `mqtt-led-relay`, `_mqtt-device._tcp` and `Relay Kitchen`.
+#[derive(Debug, Clone, Copy)]
+pub struct ResponderRecords<'a> {
+    pub hostname: &'a str,
+    pub address: Ipv4Addr,
+    pub service: &'a str,
+    pub instance: &'a str,
+    pub port: u16,
+    pub txt: &'a [&'a str],
+    pub ttl: u32,
+}
+
+impl ResponderRecords<'_> {
+    fn host_name(&self) -> [&str; 2] {
+        [self.hostname, "local"]
+    }
+
+    fn service_name(&self) -> [&str; 2] {
+        [self.service, "local"]
+    }
+
+    fn instance_name(&self) -> [&str; 3] {
+        [self.instance, self.service, "local"]
+    }
+}
+
+/// Which of our records were asked for in a query.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Asked {
+    pub host: bool,
+    pub service: bool,
+    pub instance: bool,
+    pub services_enumeration: bool,
+    /// At least one matching question had the unicast-response bit set
+    pub unicast: bool,
+}
+
+impl Asked {
+    pub fn any(&self) -> bool {
+        self.host || self.service || self.instance || self.services_enumeration
+    }
+}
+
+/// Encode a one-shot query (RFC 6762, section 5.1) for the A record of `name`
+/// (e.g. `homeassistant.local`), returns the length of the message.
+pub fn encode_query(id: u16, name: &str, buf: &mut [u8]) -> Result<usize, PacketError> {
+    let mut w = Writer::new(buf);
+
+    w.header(id, 0, 1, 0)?;
+    w.name(&[name])?;
+    w.u16(TYPE_A)?;
+    // ask for a unicast response, the query is sent from an ephemeral port
+    w.u16(CLASS_IN | CLASS_FLAG)?;
+
+    Ok(w.pos)
+}
+
+/// Search the answer and additional sections of a response for an A record of `name`.
+pub fn find_a_record(packet: &[u8], name: &str) -> Result<Option<Ipv4Addr>, PacketError> {
+    let header = Header::parse(packet)?;
+    if header.flags & FLAG_RESPONSE == 0 {
+        return Ok(None);
+    }
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..header.questions {
+        pos = skip_name(packet, pos)? + 4;
+    }
+
+    let records = header.answers as usize + header.authorities as usize + header.additionals as usize;
+    for _ in 0..records {
+        let (matches, after_name) = name_equals(packet, pos, &[name])?;
+        let rtype = read_u16(packet, after_name)?;
+        let class = read_u16(packet, after_name + 2)? & !CLASS_FLAG;
+        let rdlen = read_u16(packet, after_name + 8)? as usize;
+        let rdata = after_name + 10;
+
+        if rdata + rdlen > packet.len() {
+            return Err(PacketError::Truncated);
+        }
+
+        if matches && rtype == TYPE_A && class == CLASS_IN && rdlen == 4 {
+            let a = &packet[rdata..rdata + 4];
+            return Ok(Some(Ipv4Addr::new(a[0], a[1], a[2], a[3])));
+        }
+
+        pos = rdata + rdlen;
+    }
+
+    Ok(None)
+}
+
+/// Check the questions of a query against our records.
+///
+/// Responses and packets without questions yield an empty [`Asked`].
+pub fn parse_query(packet: &[u8], records: &ResponderRecords) -> Result<Asked, PacketError> {
+    let header = Header::parse(packet)?;
+    let mut asked = Asked::default();
+
+    if header.flags & FLAG_RESPONSE != 0 {
+        return Ok(asked);
+    }
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..header.questions {
+        let (host, _) = name_equals(packet, pos, &records.host_name())?;
+        let (service, _) = name_equals(packet, pos, &records.service_name())?;
+        let (instance, _) = name_equals(packet, pos, &records.instance_name())?;
+        let (services, after_name) = name_equals(packet, pos, &SERVICES_NAME)?;
+
+        let qtype = read_u16(packet, after_name)?;
+        let qclass = read_u16(packet, after_name + 2)?;
+        pos = after_name + 4;
+
+        if qclass & !CLASS_FLAG != CLASS_IN {
+            continue;
+        }
+
+        let matched = if host && matches!(qtype, TYPE_A | TYPE_ANY) {
+            asked.host = true;
+            true
+        } else if service && matches!(qtype, TYPE_PTR | TYPE_ANY) {
+            asked.service = true;
+            true
+        } else if instance && matches!(qtype, TYPE_SRV | TYPE_TXT | TYPE_ANY) {
+            asked.instance = true;
+            true
+        } else if services && matches!(qtype, TYPE_PTR | TYPE_ANY) {
+            asked.services_enumeration = true;
+            true
+        } else {
+            false
+        };
+
+        if matched && qclass & CLASS_FLAG != 0 {
+            asked.unicast = true;
+        }
+    }
+
+    Ok(asked)
+}
+
+/// Encode the response to the questions in `asked`, an unsolicited announcement if all
+/// flags are set. Returns the length of the message.
+pub fn encode_response(
+    records: &ResponderRecords,
+    asked: &Asked,
+    buf: &mut [u8],
+) -> Result<usize, PacketError> {
+    let mut w = Writer::new(buf);
+
+    let host = asked.host;
+    let ptr = asked.service;
+    let srv_txt = asked.service || asked.instance;
+    let enumeration = asked.services_enumeration;
+    // the host address is always useful for a client that wants to connect to the service
+    let additional_a = !host && srv_txt;
+
+    let answers = host as u16 + ptr as u16 + 2 * srv_txt as u16 + enumeration as u16;
+    w.header(0, FLAG_RESPONSE | FLAG_AUTHORITATIVE, 0, answers)?;
+    // additional record count is patched below
+    let additional_count_pos = 10;
+
+    if host {
+        host_record(&mut w, records)?;
+    }
+
+    if ptr {
+        w.record(&records.service_name(), TYPE_PTR, false, records.ttl)?;
+        w.rdata(|w| w.name(&records.instance_name()))?;
+    }
+
+    if srv_txt {
+        w.record(&records.instance_name(), TYPE_SRV, true, records.ttl)?;
+        w.rdata(|w| {
+            w.u16(0)?; // priority
+            w.u16(0)?; // weight
+            w.u16(records.port)?;
+            w.name(&records.host_name())
+        })?;
+
+        w.record(&records.instance_name(), TYPE_TXT, true, records.ttl)?;
+        w.rdata(|w| {
+            if records.txt.is_empty() {
+                // a TXT record must contain at least one (empty) string
+                return w.u8(0);
+            }
+            for entry in records.txt {
+                if entry.len() > 255 {
+                    return Err(PacketError::InvalidLabel);
+                }
+                w.u8(entry.len() as u8)?;
+                w.bytes(entry.as_bytes())?;
+            }
+            Ok(())
+        })?;
+    }
+
+    if enumeration {
+        w.record(&SERVICES_NAME, TYPE_PTR, false, records.ttl)?;
+        w.rdata(|w| w.name(&records.service_name()))?;
+    }
+
+    if additional_a {
+        host_record(&mut w, records)?;
+        w.buf[additional_count_pos..additional_count_pos + 2].copy_from_slice(&1u16.to_be_bytes());
+    }
+
+    Ok(w.pos)
+}
+
+fn host_record(w: &mut Writer, records: &ResponderRecords) -> Result<(), PacketError> {
+    w.record(&records.host_name(), TYPE_A, true, records.ttl)?;
+    w.rdata(|w| w.bytes(&records.address.octets()))
+}
+
+struct Header {
+    flags: u16,
+    questions: u16,
+    answers: u16,
+    authorities: u16,
+    additionals: u16,
+}
+
+impl Header {
+    fn parse(packet: &[u8]) -> Result<Self, PacketError> {
+        Ok(Self {
+            flags: read_u16(packet, 2)?,
+            questions: read_u16(packet, 4)?,
+            answers: read_u16(packet, 6)?,
+            authorities: read_u16(packet, 8)?,
+            additionals: read_u16(packet, 10)?,
+        })
+    }
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Result<u16, PacketError> {
+    packet
+        .get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(PacketError::Truncated)
+}
+
+/// Returns the position after the name starting at `pos`.
+fn skip_name(packet: &[u8], pos: usize) -> Result<usize, PacketError> {
+    let mut pos = pos;
+
+    loop {
+        let len = *packet.get(pos).ok_or(PacketError::Truncated)?;
+        match len {
+            0 => return Ok(pos + 1),
+            l if l & 0xc0 == 0xc0 => {
+                packet.get(pos + 1).ok_or(PacketError::Truncated)?;
+                return Ok(pos + 2);
+            }
+            l if l & 0xc0 != 0 => return Err(PacketError::InvalidLabel),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+/// Compare the (possibly compressed) name at `pos` case-insensitively with the labels of
+/// `expected`, where every part may contain several dot separated labels.
+///
+/// Returns whether the names are equal and the position after the name.
+fn name_equals(
+    packet: &[u8],
+    pos: usize,
+    expected: &[&str],
+) -> Result<(bool, usize), PacketError> {
+    let end = skip_name(packet, pos)?;
+
+    let mut expected_labels = expected.iter().flat_map(|part| part.split('.'));
+    let mut equal = true;
+    let mut pos = pos;
+    let mut jumps = 0;
+    let mut name_len = 0;
+
+    loop {
+        let len = *packet.get(pos).ok_or(PacketError::Truncated)? as usize;
+
+        if len & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return Err(PacketError::TooManyPointers);
+            }
+            let low = *packet.get(pos + 1).ok_or(PacketError::Truncated)? as usize;
+            pos = ((len & 0x3f) << 8) | low;
+            continue;
+        }
+        if len & 0xc0 != 0 {
+            return Err(PacketError::InvalidLabel);
+        }
+
+        if len == 0 {
+            return Ok((equal && expected_labels.next().is_none(), end));
+        }
+
+        name_len += len + 1;
+        if name_len > MAX_NAME_LEN {
+            return Err(PacketError::NameTooLong);
+        }
+
+        let label = packet
+            .get(pos + 1..pos + 1 + len)
+            .ok_or(PacketError::Truncated)?;
+        match expected_labels.next() {
+            Some(expected) if expected.as_bytes().eq_ignore_ascii_case(label) => {}
+            _ => equal = false,
+        }
+
+        pos += 1 + len;
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), PacketError> {
+        let end = self.pos + bytes.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(PacketError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), PacketError> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), PacketError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), PacketError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn header(
+        &mut self,
+        id: u16,
+        flags: u16,
+        questions: u16,
+        answers: u16,
+    ) -> Result<(), PacketError> {
+        self.u16(id)?;
+        self.u16(flags)?;
+        self.u16(questions)?;
+        self.u16(answers)?;
+        self.u16(0)?;
+        self.u16(0)
+    }
+
+    fn name(&mut self, parts: &[&str]) -> Result<(), PacketError> {
+        let mut name_len = 0;
+
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            if label.is_empty() || label.len() > 63 {
+                return Err(PacketError::InvalidLabel);
+            }
+            name_len += label.len() + 1;
+            if name_len > MAX_NAME_LEN {
+                return Err(PacketError::NameTooLong);
+            }
+            self.u8(label.len() as u8)?;
+            self.bytes(label.as_bytes())?;
+        }
+
+        self.u8(0)
+    }
+
+    fn record(
+        &mut self,
+        name: &[&str],
+        rtype: u16,
+        unique: bool,
+        ttl: u32,
+    ) -> Result<(), PacketError> {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(if unique { CLASS_IN | CLASS_FLAG } else { CLASS_IN })?;
+        self.u32(ttl)
+    }
+
+    /// Write the record data length followed by the data written by `f`.
+    fn rdata(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), PacketError>,
+    ) -> Result<(), PacketError> {
+        let len_pos = self.pos;
+        self.u16(0)?;
+        f(self)?;
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 42);
+
+    fn records() -> ResponderRecords<'static> {
+        ResponderRecords {
+            hostname: "mqtt-led-relay",
+            address: HOST_ADDRESS,
+            service: "_mqtt-device._tcp",
+            instance: "Relay Kitchen",
+            port: 0,
+            txt: &["cmd=mqtt_led_relay/command", "scan=mqtt_led_relay/scan"],
+            ttl: 120,
+        }
+    }
+
+    /// Hand-written packet, independent of [`Writer`]
+    struct Packet {
+        buf: [u8; 384],
+        len: usize,
+    }
+
+    impl Packet {
+        /// Without a header, e.g. for record data
+        fn empty() -> Self {
+            Self {
+                buf: [0; 384],
+                len: 0,
+            }
+        }
+
+        fn new(flags: u16, questions: u16, answers: u16) -> Self {
+            Self::empty()
+                .bytes(&[0, 0])
+                .bytes(&flags.to_be_bytes())
+                .bytes(&questions.to_be_bytes())
+                .bytes(&answers.to_be_bytes())
+                .bytes(&[0, 0, 0, 0])
+        }
+
+        fn bytes(mut self, bytes: &[u8]) -> Self {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+            self
+        }
+
+        /// Labels of `name` without the terminating zero length
+        fn labels(mut self, name: &str) -> Self {
+            for label in name.split('.') {
+                self = self.bytes(&[label.len() as u8]).bytes(label.as_bytes());
+            }
+            self
+        }
+
+        fn name(self, name: &str) -> Self {
+            self.labels(name).bytes(&[0])
+        }
+
+        fn question(self, name: &str, qtype: u16, qclass: u16) -> Self {
+            self.name(name)
+                .bytes(&qtype.to_be_bytes())
+                .bytes(&qclass.to_be_bytes())
+        }
+
+        fn record(self, name: &str, rtype: u16, class: u16, rdata: Packet) -> Self {
+            self.question(name, rtype, class)
+                .bytes(&120u32.to_be_bytes())
+                .bytes(&(rdata.len as u16).to_be_bytes())
+                .bytes(rdata.as_slice())
+        }
+
+        fn additionals(mut self, additionals: u16) -> Self {
+            self.buf[10..12].copy_from_slice(&additionals.to_be_bytes());
+            self
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    #[test]
+    fn encodes_query_with_unicast_response_bit() {
+        let mut buf = [0; 64];
+        let len = encode_query(0x1234, "homeassistant.local", &mut buf).unwrap();
+
+        let mut expected = Packet::new(0, 1, 0).question("homeassistant.local", TYPE_A, 0x8001);
+        expected.buf[..2].copy_from_slice(&0x1234u16.to_be_bytes());
+        assert_eq!(&buf[..len], expected.as_slice());
+
+        assert_eq!(
+            encode_query(0, "homeassistant.local", &mut buf[..20]),
+            Err(PacketError::BufferTooSmall)
+        );
+        assert_eq!(
+            encode_query(0, "homeassistant..local", &mut buf),
+            Err(PacketError::InvalidLabel)
+        );
+    }
+
+    #[test]
+    fn parses_qm_and_qu_questions() {
+        let qm = Packet::new(0, 1, 0).question("mqtt-led-relay.local", TYPE_A, CLASS_IN);
+        assert_eq!(
+            parse_query(qm.as_slice(), &records()),
+            Ok(Asked {
+                host: true,
+                unicast: false,
+                ..Asked::default()
+            })
+        );
+
+        let qu = Packet::new(0, 1, 0).question("MQTT-LED-Relay.LOCAL", TYPE_ANY, 0x8001);
+        assert_eq!(
+            parse_query(qu.as_slice(), &records()),
+            Ok(Asked {
+                host: true,
+                unicast: true,
+                ..Asked::default()
+            })
+        );
+
+        // the unicast bit of a question for another name does not matter
+        let other = Packet::new(0, 2, 0)
+            .question("other.local", TYPE_A, 0x8001)
+            .question("mqtt-led-relay.local", TYPE_A, CLASS_IN);
+        assert_eq!(
+            parse_query(other.as_slice(), &records()),
+            Ok(Asked {
+                host: true,
+                unicast: false,
+                ..Asked::default()
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_other_questions_and_responses() {
+        let aaaa = Packet::new(0, 1, 0).question("mqtt-led-relay.local", 28, CLASS_IN);
+        assert!(!parse_query(aaaa.as_slice(), &records()).unwrap().any());
+
+        let chaos = Packet::new(0, 1, 0).question("mqtt-led-relay.local", TYPE_A, 3);
+        assert!(!parse_query(chaos.as_slice(), &records()).unwrap().any());
+
+        let longer = Packet::new(0, 1, 0).question("mqtt-led-relay.local.lan", TYPE_A, CLASS_IN);
+        assert!(!parse_query(longer.as_slice(), &records()).unwrap().any());
+
+        let response =
+            Packet::new(FLAG_RESPONSE, 1, 0).question("mqtt-led-relay.local", TYPE_A, CLASS_IN);
+        assert!(!parse_query(response.as_slice(), &records()).unwrap().any());
+    }
+
+    #[test]
+    fn parses_compressed_question_names() {
+        // `local` of the first question starts at offset 12 + 1 + 5
+        let query = Packet::new(0, 2, 0)
+            .question("other.local", TYPE_A, CLASS_IN)
+            .labels("mqtt-led-relay")
+            .bytes(&[0xc0, 18])
+            .bytes(&TYPE_A.to_be_bytes())
+            .bytes(&0x8001u16.to_be_bytes());
+
+        assert_eq!(
+            parse_query(query.as_slice(), &records()),
+            Ok(Asked {
+                host: true,
+                unicast: true,
+                ..Asked::default()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let to_itself = Packet::new(0, 1, 0)
+            .bytes(&[0xc0, 12])
+            .bytes(&TYPE_A.to_be_bytes())
+            .bytes(&CLASS_IN.to_be_bytes());
+        assert_eq!(
+            parse_query(to_itself.as_slice(), &records()),
+            Err(PacketError::TooManyPointers)
+        );
+
+        let ping_pong = Packet::new(FLAG_RESPONSE, 0, 1)
+            .bytes(&[0xc0, 14, 0xc0, 12])
+            .bytes(&TYPE_A.to_be_bytes())
+            .bytes(&CLASS_IN.to_be_bytes())
+            .bytes(&120u32.to_be_bytes())
+            .bytes(&[0, 4])
+            .bytes(&HOST_ADDRESS.octets());
+        assert_eq!(
+            find_a_record(ping_pong.as_slice(), "homeassistant.local"),
+            Err(PacketError::TooManyPointers)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let query = Packet::new(0, 1, 0).question("mqtt-led-relay.local", TYPE_A, CLASS_IN);
+        let packet = query.as_slice();
+
+        assert_eq!(
+            parse_query(&packet[..8], &records()),
+            Err(PacketError::Truncated)
+        );
+        // in the middle of the name, the name and the question type
+        for len in [20, 34, 35] {
+            assert_eq!(
+                parse_query(&packet[..len], &records()),
+                Err(PacketError::Truncated)
+            );
+        }
+
+        let mut buf = [0; 64];
+        let len = encode_response(
+            &records(),
+            &Asked {
+                host: true,
+                unicast: false,
+                ..Asked::default()
+            },
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(
+            find_a_record(&buf[..len - 1], "mqtt-led-relay.local"),
+            Err(PacketError::Truncated)
+        );
+    }
+
+    #[test]
+    fn responses_resolve_the_host() {
+        let mut buf = [0; 64];
+        let asked = Asked {
+            host: true,
+            unicast: true,
+            ..Asked::default()
+        };
+        let len = encode_response(&records(), &asked, &mut buf).unwrap();
+        let response = &buf[..len];
+
+        assert_eq!(
+            find_a_record(response, "mqtt-led-relay.local"),
+            Ok(Some(HOST_ADDRESS))
+        );
+        assert_eq!(
+            find_a_record(response, "Mqtt-Led-Relay.Local"),
+            Ok(Some(HOST_ADDRESS))
+        );
+        assert_eq!(find_a_record(response, "other.local"), Ok(None));
+
+        // unique record, the cache flush bit is set
+        let class = &response[len - 12..len - 10];
+        assert_eq!(class, &0x8001u16.to_be_bytes());
+
+        let len = encode_response(&records(), &Asked::default(), &mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN);
+        assert_eq!(find_a_record(&buf[..len], "mqtt-led-relay.local"), Ok(None));
+
+        assert_eq!(
+            encode_response(&records(), &asked, &mut buf[..30]),
+            Err(PacketError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn parses_service_questions() {
+        let asked = |name: &str, qtype: u16, qclass: u16| {
+            let query = Packet::new(0, 1, 0).question(name, qtype, qclass);
+            parse_query(query.as_slice(), &records()).unwrap()
+        };
+
+        let service = asked("_mqtt-device._tcp.local", TYPE_PTR, CLASS_IN);
+        assert!(service.service && !service.host && !service.instance);
+
+        for qtype in [TYPE_SRV, TYPE_TXT, TYPE_ANY] {
+            let instance = asked("relay kitchen._mqtt-device._tcp.local", qtype, 0x8001);
+            assert_eq!(
+                instance,
+                Asked {
+                    instance: true,
+                    unicast: true,
+                    ..Asked::default()
+                }
+            );
+        }
+
+        let enumeration = asked("_services._dns-sd._udp.local", TYPE_PTR, CLASS_IN);
+        assert!(enumeration.services_enumeration && !enumeration.service);
+
+        // wrong record types for the names
+        assert!(!asked("_mqtt-device._tcp.local", TYPE_A, CLASS_IN).any());
+        assert!(!asked("Relay Kitchen._mqtt-device._tcp.local", TYPE_PTR, CLASS_IN).any());
+        assert!(!asked("mqtt-led-relay.local", TYPE_SRV, CLASS_IN).any());
+        assert!(!asked("_http._tcp.local", TYPE_PTR, CLASS_IN).any());
+    }
+
+    #[test]
+    fn responses_advertise_the_service() {
+        let mut buf = [0; 384];
+        let asked = Asked {
+            service: true,
+            ..Asked::default()
+        };
+        let len = encode_response(&records(), &asked, &mut buf).unwrap();
+
+        let instance = "Relay Kitchen._mqtt-device._tcp.local";
+        let expected = Packet::new(FLAG_RESPONSE | FLAG_AUTHORITATIVE, 0, 3)
+            .additionals(1)
+            .record(
+                "_mqtt-device._tcp.local",
+                TYPE_PTR,
+                CLASS_IN,
+                Packet::empty().name(instance),
+            )
+            .record(
+                instance,
+                TYPE_SRV,
+                0x8001,
+                Packet::empty()
+                    .bytes(&[0, 0, 0, 0, 0, 0])
+                    .name("mqtt-led-relay.local"),
+            )
+            .record(
+                instance,
+                TYPE_TXT,
+                0x8001,
+                Packet::empty()
+                    .bytes(&[26])
+                    .bytes(b"cmd=mqtt_led_relay/command")
+                    .bytes(&[24])
+                    .bytes(b"scan=mqtt_led_relay/scan"),
+            )
+            .record(
+                "mqtt-led-relay.local",
+                TYPE_A,
+                0x8001,
+                Packet::empty().bytes(&HOST_ADDRESS.octets()),
+            );
+        assert_eq!(&buf[..len], expected.as_slice());
+
+        // the host address in the additional section is found by the resolver as well
+        assert_eq!(
+            find_a_record(&buf[..len], "mqtt-led-relay.local"),
+            Ok(Some(HOST_ADDRESS))
+        );
+    }
+
+    #[test]
+    fn responses_enumerate_the_service_type() {
+        let mut buf = [0; 128];
+        let asked = Asked {
+            services_enumeration: true,
+            ..Asked::default()
+        };
+        let len = encode_response(&records(), &asked, &mut buf).unwrap();
+
+        let expected = Packet::new(FLAG_RESPONSE | FLAG_AUTHORITATIVE, 0, 1).record(
+            "_services._dns-sd._udp.local",
+            TYPE_PTR,
+            CLASS_IN,
+            Packet::empty().name("_mqtt-device._tcp.local"),
+        );
+        assert_eq!(&buf[..len], expected.as_slice());
+    }
+
+    #[test]
+    fn encodes_txt_entries() {
+        let mut buf = [0; 384];
+        let asked = Asked {
+            host: true,
+            instance: true,
+            ..Asked::default()
+        };
+
+        // the host is answered already, no additional record
+        let len = encode_response(&records(), &asked, &mut buf).unwrap();
+        assert_eq!(&buf[6..12], &[0, 3, 0, 0, 0, 0]);
+
+        // a TXT record always contains at least one string
+        let no_txt = ResponderRecords {
+            txt: &[],
+            ..records()
+        };
+        let len_no_txt = encode_response(&no_txt, &asked, &mut buf).unwrap();
+        assert_eq!(len_no_txt, len - 52 + 1);
+        assert_eq!(&buf[len_no_txt - 3..len_no_txt], &[0, 1, 0]);
+
+        let long = [b'a'; 256];
+        let long = [core::str::from_utf8(&long).unwrap()];
+        let long_txt = ResponderRecords {
+            txt: &long,
+            ..records()
+        };
+        assert_eq!(
+            encode_response(&long_txt, &asked, &mut buf),
+            Err(PacketError::InvalidLabel)
+        );
+    }
+
+    #[test]
+    fn finds_compressed_a_records() {
+        // the answer refers to the name of the question, another record comes first
+        let response = Packet::new(FLAG_RESPONSE, 1, 2)
+            .question("homeassistant.local", TYPE_A, CLASS_IN)
+            .bytes(&[0xc0, 12])
+            .bytes(&TYPE_ANY.to_be_bytes())
+            .bytes(&CLASS_IN.to_be_bytes())
+            .bytes(&120u32.to_be_bytes())
+            .bytes(&[0, 2, 0xaa, 0xbb])
+            .bytes(&[0xc0, 12])
+            .bytes(&TYPE_A.to_be_bytes())
+            .bytes(&0x8001u16.to_be_bytes())
+            .bytes(&120u32.to_be_bytes())
+            .bytes(&[0, 4])
+            .bytes(&HOST_ADDRESS.octets());
+
+        assert_eq!(
+            find_a_record(response.as_slice(), "homeassistant.local"),
+            Ok(Some(HOST_ADDRESS))
+        );
+
+        // a query with the same content is no answer
+        let query = Packet::new(0, 1, 2).bytes(&response.as_slice()[HEADER_LEN..]);
+        assert_eq!(
+            find_a_record(query.as_slice(), "homeassistant.local"),
+            Ok(None)
+        );
+    }
+}
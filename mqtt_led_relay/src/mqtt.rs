@@ -1,38 +1,66 @@
+use core::cell::RefCell;
+
 use anyhow::{Error, Result};
 use defmt::{debug, error, info, Format};
 use embassy_executor::Spawner;
-use embassy_net::{dns, tcp::TcpSocket, Stack};
+use embassy_futures::select::{select3, Either3};
+use embassy_net::{dns, tcp::TcpSocket, IpAddress, Stack};
 use embassy_time::{Duration, Timer};
+use heapless::String;
 use rust_mqtt::{
     client::{client::MqttClient, client_config::ClientConfig},
     utils::rng_generator::CountingRng,
 };
 use smoltcp::wire::DnsQueryType;
 
+use crate::command::Command;
+use crate::connectivity;
+use crate::mdns;
+use crate::mqtt_transport::{wait_read_ready, Transport};
+use crate::scan::{self, ScanResults, SCAN_PAYLOAD_SIZE};
+use crate::secret::Secret;
+use crate::sizing::{MQTT_BUFFER_SIZE, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE};
+
+/// Topic the client subscribes to for text commands, see [`Command`].
+pub const COMMAND_TOPIC: &str = "mqtt_led_relay/command";
+/// Topic the Wi-Fi scan results are published on as JSON, see [`scan::write_json`].
+pub const SCAN_TOPIC: &str = "mqtt_led_relay/scan";
+
 pub async fn create_mqtt_client(
     mqtt_username: &'static str,
-    mqtt_password: &'static str,
+    mqtt_password: Secret<&'static str>,
     mqtt_fqdn: &'static str,
     mqtt_port: u16,
     mqtt_client_id: &'static str,
     spawner: Spawner,
     stack: Stack<'static>,
 ) -> Result<()> {
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
+    let mut rx_buffer = [0; TCP_RX_BUFFER_SIZE];
+    let mut tx_buffer = [0; TCP_TX_BUFFER_SIZE];
 
     let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
 
+    debug!("Waiting for network connectivity...");
+    if connectivity::wait_online().await.is_none() {
+        return Err(Error::msg("No connectivity receiver available"));
+    }
+
     debug!("Resolving MQTT FQDN...");
-    let mqtt_broker_ip_address = match stack
-        .dns_query(mqtt_fqdn, DnsQueryType::A)
-        .await
-        .map(|a| a[0])
-    {
-        Ok(address) => address,
-        Err(e) => {
-            debug!("Error resolving MQTT FQDN: {:?}", e);
-            return Err(Error::msg("Failed to resolve MQTT FQDN"));
+    let mqtt_broker_ip_address = if mdns::is_local_name(mqtt_fqdn) {
+        match mdns::resolve(stack, mqtt_fqdn).await {
+            Ok(address) => address.into(),
+            Err(e) => {
+                debug!("Error resolving MQTT FQDN via mDNS: {:?}", e);
+                return Err(Error::msg("Failed to resolve MQTT FQDN"));
+            }
+        }
+    } else {
+        match resolve(stack, mqtt_fqdn).await {
+            Ok(address) => address,
+            Err(e) => {
+                debug!("Error resolving MQTT FQDN: {:?}", e);
+                return Err(Error::msg("Failed to resolve MQTT FQDN"));
+            }
         }
     };
 
@@ -47,24 +75,26 @@ pub async fn create_mqtt_client(
     }
     info!("Connected socket to MQTT broker!");
 
+    let socket = RefCell::new(socket);
+
     let mut mqtt_config = ClientConfig::new(
         rust_mqtt::client::client_config::MqttVersion::MQTTv5,
         CountingRng(20000),
     );
     mqtt_config.add_username(mqtt_username);
-    mqtt_config.add_password(mqtt_password);
+    mqtt_config.add_password(mqtt_password.expose());
     mqtt_config.add_client_id(mqtt_client_id);
-    mqtt_config.max_packet_size = 100;
+    mqtt_config.max_packet_size = MQTT_BUFFER_SIZE as u32;
 
-    let mut recv_buffer = [0; 80];
-    let mut write_buffer = [0; 80];
+    let mut recv_buffer = [0; MQTT_BUFFER_SIZE];
+    let mut write_buffer = [0; MQTT_BUFFER_SIZE];
 
     let mut mqtt_client = MqttClient::<_, 5, _>::new(
-        socket,
+        Transport(&socket),
         &mut write_buffer,
-        80,
+        MQTT_BUFFER_SIZE,
         &mut recv_buffer,
-        80,
+        MQTT_BUFFER_SIZE,
         mqtt_config,
     );
 
@@ -77,8 +107,53 @@ pub async fn create_mqtt_client(
         }
     }
 
-    mqtt_client.send_ping()
+    match mqtt_client.subscribe_to_topic(COMMAND_TOPIC).await {
+        Ok(()) => info!("Subscribed to {}", COMMAND_TOPIC),
+        Err(e) => {
+            debug!("Error subscribing to command topic: {:?}", e);
+            return Err(Error::msg("Failed to subscribe to command topic"));
+        }
+    }
+
     loop {
+        let ready = select3(
+            wait_read_ready(&socket),
+            Timer::after(Duration::from_secs(5)),
+            scan::wait_results(),
+        )
+        .await;
+
+        let command = match ready {
+            // data (or the end of the connection) is pending, the read is not interrupted
+            Either3::First(()) => match mqtt_client.receive_message().await {
+                Ok((topic, payload)) => {
+                    debug!("Received message on {}", topic);
+                    Some(Command::parse(payload))
+                }
+                Err(e) => {
+                    error!("Error receiving message: {:?}", e);
+                    return Err(Error::msg("Failed to receive MQTT message"));
+                }
+            },
+            Either3::Second(_) => None,
+            Either3::Third(results) => {
+                publish_scan_results(&mut mqtt_client, &results).await;
+                continue;
+            }
+        };
+
+        match command {
+            Some(Ok(command)) => {
+                command.execute().await;
+                continue;
+            }
+            Some(Err(e)) => {
+                error!("Invalid command: {:?}", e);
+                continue;
+            }
+            None => {}
+        }
+
         let test_string = "Hello World!";
 
         match mqtt_client
@@ -97,7 +172,41 @@ pub async fn create_mqtt_client(
                 error!("Error sending message: {:?}", e);
             }
         }
+    }
+}
 
-        Timer::after(Duration::from_secs(5)).await;
+/// Unicast DNS lookup, falls back to AAAA records for IPv6-only brokers if IPv6 is enabled.
+async fn resolve(stack: Stack<'static>, name: &str) -> Result<IpAddress, dns::Error> {
+    let result = stack.dns_query(name, DnsQueryType::A).await.map(|a| a[0]);
+
+    #[cfg(feature = "ipv6")]
+    if result.is_err() {
+        return stack.dns_query(name, DnsQueryType::Aaaa).await.map(|a| a[0]);
+    }
+
+    result
+}
+
+async fn publish_scan_results(
+    mqtt_client: &mut MqttClient<'_, Transport<'_, '_>, 5, CountingRng>,
+    results: &ScanResults,
+) {
+    let mut payload: String<SCAN_PAYLOAD_SIZE> = String::new();
+    if scan::write_json(results, &mut payload).is_err() {
+        error!("Scan results do not fit into the payload buffer");
+        return;
+    }
+
+    match mqtt_client
+        .send_message(
+            SCAN_TOPIC,
+            payload.as_bytes(),
+            rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
+            false,
+        )
+        .await
+    {
+        Ok(()) => info!("Published {} scan results", results.len()),
+        Err(e) => error!("Error publishing scan results: {:?}", e),
     }
 }
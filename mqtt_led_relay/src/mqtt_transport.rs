@@ -0,0 +1,45 @@
+//! Transport of the MQTT client that can wait for incoming data without reading it.
+
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+
+use embassy_net::tcp::{self, TcpSocket};
+
+/// Network of the MQTT client, the socket stays reachable for [`wait_read_ready`].
+///
+/// `receive_message` of rust-mqtt is not cancel-safe: dropping it after a partial read loses
+/// the framing of the MQTT stream. The loop waits until data arrives instead and only then
+/// receives the message, so the other branches of a `select` never interrupt a read.
+pub struct Transport<'s, 'b>(pub &'s RefCell<TcpSocket<'b>>);
+
+impl embedded_io_async::ErrorType for Transport<'_, '_> {
+    type Error = tcp::Error;
+}
+
+// The client task is the only user of the socket and never awaits a read and a write at the same
+// time, [`wait_read_ready`] only borrows the socket while it is polled, so the borrows held
+// across `await` never overlap.
+#[allow(clippy::await_holding_refcell_ref)]
+impl embedded_io_async::Read for Transport<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.borrow_mut().read(buf).await
+    }
+}
+
+#[allow(clippy::await_holding_refcell_ref)]
+impl embedded_io_async::Write for Transport<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.borrow_mut().write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.borrow_mut().flush().await
+    }
+}
+
+/// [`TcpSocket::wait_read_ready`] without holding a borrow of the socket between polls, so the
+/// `select` with the timers does not conflict with the borrows of [`Transport`].
+pub async fn wait_read_ready(socket: &RefCell<TcpSocket<'_>>) {
+    poll_fn(|cx| pin!(socket.borrow().wait_read_ready()).poll(cx)).await
+}
@@ -0,0 +1,592 @@
+//! Neighbor discovery (RFC 4861) messages used by [`crate::slaac`]: router solicitations and
+//! advertisements and the neighbor solicitations and advertisements of duplicate address
+//! detection. Messages are read and written including their IPv6 header, as seen by a raw
+//! socket.
+
+use core::net::Ipv6Addr;
+
+use defmt::Format;
+use heapless::Vec;
+
+const IPV6_HEADER_LEN: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+const ICMPV6_NEIGHBOR_SOLICIT: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERT: u8 = 136;
+
+const OPTION_SOURCE_LLADDR: u8 = 1;
+const OPTION_PREFIX_INFO: u8 = 3;
+const OPTION_MTU: u8 = 5;
+const OPTION_ROUTE_INFO: u8 = 24;
+const OPTION_RDNSS: u8 = 25;
+
+const PREFIX_FLAG_ON_LINK: u8 = 0x80;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+pub const MAX_PREFIXES: usize = 4;
+pub const MAX_ROUTES: usize = 4;
+pub const MAX_DNS_SERVERS: usize = 3;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum NdError {
+    Truncated,
+    NotRouterAdvert,
+    /// Hop limit is not 255 or the source is not link-local (RFC 4861, section 6.1.2)
+    InvalidSource,
+    InvalidOption,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixInfo {
+    pub prefix: Ipv6Addr,
+    pub len: u8,
+    pub on_link: bool,
+    pub autonomous: bool,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+}
+
+/// Route information option (RFC 4191), e.g. the Thread OMR prefix announced by a border router
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub prefix: Ipv6Addr,
+    pub len: u8,
+    pub lifetime: u32,
+}
+
+#[derive(Debug, Format, Clone, PartialEq, Eq)]
+pub struct RouterAdvert {
+    /// Link-local address of the router
+    pub source: Ipv6Addr,
+    /// Zero if the router must not be used as default router
+    pub router_lifetime: u16,
+    pub mtu: Option<u32>,
+    pub prefixes: Vec<PrefixInfo, MAX_PREFIXES>,
+    pub routes: Vec<RouteInfo, MAX_ROUTES>,
+    pub dns_servers: Vec<Ipv6Addr, MAX_DNS_SERVERS>,
+}
+
+impl RouterAdvert {
+    /// First prefix usable for SLAAC with a 64 bit interface identifier.
+    pub fn slaac_prefix(&self) -> Option<&PrefixInfo> {
+        self.prefixes
+            .iter()
+            .find(|prefix| prefix.autonomous && prefix.len == 64 && prefix.valid_lifetime > 0)
+    }
+}
+
+/// Modified EUI-64 interface identifier of a MAC address (RFC 4291, appendix A).
+pub fn interface_id(mac: [u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+pub fn link_local_address(mac: [u8; 6]) -> Ipv6Addr {
+    slaac_address(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Address formed from the upper 64 bits of `prefix` and the interface identifier of `mac`.
+pub fn slaac_address(prefix: Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&interface_id(mac));
+    Ipv6Addr::from(octets)
+}
+
+/// Solicited-node multicast address of `address` (RFC 4291, section 2.7.1).
+pub fn solicited_node_address(address: Ipv6Addr) -> Ipv6Addr {
+    let octets = address.octets();
+    let mut group = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0];
+    group[13..].copy_from_slice(&octets[13..]);
+    Ipv6Addr::from(group)
+}
+
+/// Parse a router advertisement including its IPv6 header, as received on a raw socket.
+pub fn parse_router_advert(packet: &[u8]) -> Result<RouterAdvert, NdError> {
+    if packet.len() < IPV6_HEADER_LEN + 16 {
+        return Err(NdError::Truncated);
+    }
+    if packet[0] >> 4 != 6 || packet[6] != NEXT_HEADER_ICMPV6 {
+        return Err(NdError::NotRouterAdvert);
+    }
+
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let icmp = packet
+        .get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)
+        .ok_or(NdError::Truncated)?;
+    if icmp.len() < 16 {
+        return Err(NdError::Truncated);
+    }
+    if icmp[0] != ICMPV6_ROUTER_ADVERT || icmp[1] != 0 {
+        return Err(NdError::NotRouterAdvert);
+    }
+
+    let source = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap());
+    if packet[7] != 255 || !source.is_unicast_link_local() {
+        return Err(NdError::InvalidSource);
+    }
+
+    let mut advert = RouterAdvert {
+        source,
+        router_lifetime: u16::from_be_bytes([icmp[6], icmp[7]]),
+        mtu: None,
+        prefixes: Vec::new(),
+        routes: Vec::new(),
+        dns_servers: Vec::new(),
+    };
+
+    let mut options = &icmp[16..];
+    while !options.is_empty() {
+        if options.len() < 2 {
+            return Err(NdError::Truncated);
+        }
+        let len = options[1] as usize * 8;
+        if len == 0 {
+            return Err(NdError::InvalidOption);
+        }
+        let option = options.get(..len).ok_or(NdError::Truncated)?;
+
+        match option[0] {
+            OPTION_PREFIX_INFO if len == 32 => {
+                let _ = advert.prefixes.push(PrefixInfo {
+                    len: option[2],
+                    on_link: option[3] & PREFIX_FLAG_ON_LINK != 0,
+                    autonomous: option[3] & PREFIX_FLAG_AUTONOMOUS != 0,
+                    valid_lifetime: read_u32(&option[4..8]),
+                    preferred_lifetime: read_u32(&option[8..12]),
+                    prefix: Ipv6Addr::from(<[u8; 16]>::try_from(&option[16..32]).unwrap()),
+                });
+            }
+            OPTION_PREFIX_INFO => return Err(NdError::InvalidOption),
+            OPTION_MTU if len == 8 => advert.mtu = Some(read_u32(&option[4..8])),
+            OPTION_ROUTE_INFO => {
+                let prefix_len = option[2];
+                if prefix_len > 128 || len > 24 {
+                    return Err(NdError::InvalidOption);
+                }
+                let mut prefix = [0; 16];
+                prefix[..len - 8].copy_from_slice(&option[8..]);
+                let _ = advert.routes.push(RouteInfo {
+                    prefix: Ipv6Addr::from(prefix),
+                    len: prefix_len,
+                    lifetime: read_u32(&option[4..8]),
+                });
+            }
+            OPTION_RDNSS if len >= 24 => {
+                let (servers, _) = option[8..].as_chunks::<16>();
+                for server in servers {
+                    let _ = advert.dns_servers.push(Ipv6Addr::from(*server));
+                }
+            }
+            _ => {}
+        }
+
+        options = &options[len..];
+    }
+
+    Ok(advert)
+}
+
+/// Encode a router solicitation with source link-layer address option, including the IPv6
+/// header, to be sent on a raw socket. Returns the length of the packet.
+pub fn encode_router_solicitation(source: Ipv6Addr, mac: [u8; 6], buf: &mut [u8]) -> usize {
+    const ICMP_LEN: usize = 8 + 8;
+
+    let packet = &mut buf[..IPV6_HEADER_LEN + ICMP_LEN];
+    packet.fill(0);
+
+    packet[0] = 0x60;
+    packet[4..6].copy_from_slice(&(ICMP_LEN as u16).to_be_bytes());
+    packet[6] = NEXT_HEADER_ICMPV6;
+    packet[7] = 255;
+    packet[8..24].copy_from_slice(&source.octets());
+    packet[24..40].copy_from_slice(&ALL_ROUTERS.octets());
+
+    let icmp = &mut packet[IPV6_HEADER_LEN..];
+    icmp[0] = ICMPV6_ROUTER_SOLICIT;
+    icmp[8] = OPTION_SOURCE_LLADDR;
+    icmp[9] = 1;
+    icmp[10..16].copy_from_slice(&mac);
+
+    let checksum = icmpv6_checksum(source, ALL_ROUTERS, icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    IPV6_HEADER_LEN + ICMP_LEN
+}
+
+/// Encode the neighbor solicitation of duplicate address detection (RFC 4862, section 5.4.2)
+/// for the tentative address `target`, including the IPv6 header. The solicitation is sent from
+/// the unspecified address to the solicited-node group of `target`. Returns the length of the
+/// packet.
+pub fn encode_dad_solicitation(target: Ipv6Addr, buf: &mut [u8]) -> usize {
+    const ICMP_LEN: usize = 8 + 16;
+
+    let destination = solicited_node_address(target);
+    let packet = &mut buf[..IPV6_HEADER_LEN + ICMP_LEN];
+    packet.fill(0);
+
+    packet[0] = 0x60;
+    packet[4..6].copy_from_slice(&(ICMP_LEN as u16).to_be_bytes());
+    packet[6] = NEXT_HEADER_ICMPV6;
+    packet[7] = 255;
+    packet[24..40].copy_from_slice(&destination.octets());
+
+    let icmp = &mut packet[IPV6_HEADER_LEN..];
+    icmp[0] = ICMPV6_NEIGHBOR_SOLICIT;
+    icmp[8..24].copy_from_slice(&target.octets());
+
+    let checksum = icmpv6_checksum(Ipv6Addr::UNSPECIFIED, destination, icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    IPV6_HEADER_LEN + ICMP_LEN
+}
+
+/// Whether `packet` (including its IPv6 header) shows that the tentative address `target` is
+/// already in use (RFC 4862, section 5.4.3 and 5.4.4): a neighbor advertisement for `target`,
+/// or the duplicate address detection of another node for the same address.
+pub fn is_dad_conflict(packet: &[u8], target: Ipv6Addr) -> bool {
+    if packet.len() < IPV6_HEADER_LEN + 24
+        || packet[0] >> 4 != 6
+        || packet[6] != NEXT_HEADER_ICMPV6
+        || packet[7] != 255
+    {
+        return false;
+    }
+
+    let source = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap());
+    let icmp = &packet[IPV6_HEADER_LEN..];
+    if icmp[1] != 0 || icmp[8..24] != target.octets() {
+        return false;
+    }
+
+    match icmp[0] {
+        ICMPV6_NEIGHBOR_ADVERT => true,
+        ICMPV6_NEIGHBOR_SOLICIT => source.is_unspecified(),
+        _ => false,
+    }
+}
+
+fn icmpv6_checksum(source: Ipv6Addr, destination: Ipv6Addr, icmp: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let word = u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]);
+            sum += word as u32;
+        }
+    };
+
+    add(&source.octets());
+    add(&destination.octets());
+    add(&(icmp.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, NEXT_HEADER_ICMPV6]);
+    add(icmp);
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x60, 0x55, 0xf9, 0x12, 0x34, 0x56];
+    const LINK_LOCAL: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x6255, 0xf9ff, 0xfe12, 0x3456);
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+    const PREFIX: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 0, 0, 0, 0);
+
+    /// Hand-written IPv6 packet with an ICMPv6 message, independent of the encoders
+    struct Packet {
+        buf: [u8; 256],
+        len: usize,
+    }
+
+    impl Packet {
+        fn new(source: Ipv6Addr, destination: Ipv6Addr, hop_limit: u8, icmp_type: u8) -> Self {
+            let mut packet = Self {
+                buf: [0; 256],
+                len: 0,
+            };
+            packet = packet
+                .bytes(&[0x60, 0, 0, 0, 0, 0, NEXT_HEADER_ICMPV6, hop_limit])
+                .bytes(&source.octets())
+                .bytes(&destination.octets())
+                .bytes(&[icmp_type, 0, 0, 0]);
+            packet
+        }
+
+        /// Router advertisement header with the given router lifetime
+        fn router_advert(source: Ipv6Addr, hop_limit: u8, lifetime: u16) -> Self {
+            Self::new(source, ALL_NODES, hop_limit, ICMPV6_ROUTER_ADVERT)
+                .bytes(&[64, 0])
+                .bytes(&lifetime.to_be_bytes())
+                .bytes(&[0; 8])
+        }
+
+        fn prefix(self, prefix: Ipv6Addr, len: u8, flags: u8, valid: u32) -> Self {
+            self.bytes(&[OPTION_PREFIX_INFO, 4, len, flags])
+                .bytes(&valid.to_be_bytes())
+                .bytes(&(valid / 2).to_be_bytes())
+                .bytes(&[0; 4])
+                .bytes(&prefix.octets())
+        }
+
+        fn bytes(mut self, bytes: &[u8]) -> Self {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+            let payload_len = (self.len.max(IPV6_HEADER_LEN) - IPV6_HEADER_LEN) as u16;
+            self.buf[4..6].copy_from_slice(&payload_len.to_be_bytes());
+            self
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    fn source_and_destination(packet: &[u8]) -> (Ipv6Addr, Ipv6Addr) {
+        (
+            Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap()),
+            Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).unwrap()),
+        )
+    }
+
+    #[test]
+    fn forms_addresses() {
+        assert_eq!(
+            interface_id(MAC),
+            [0x62, 0x55, 0xf9, 0xff, 0xfe, 0x12, 0x34, 0x56]
+        );
+        assert_eq!(link_local_address(MAC), LINK_LOCAL);
+        assert_eq!(
+            slaac_address(Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 0xffff, 0, 0, 1), MAC),
+            Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 0x6255, 0xf9ff, 0xfe12, 0x3456)
+        );
+
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff12, 0x3456);
+        assert_eq!(solicited_node_address(LINK_LOCAL), group);
+        assert_eq!(solicited_node_address(slaac_address(PREFIX, MAC)), group);
+    }
+
+    #[test]
+    fn encodes_router_solicitations() {
+        let mut buf = [0; 64];
+        let len = encode_router_solicitation(LINK_LOCAL, MAC, &mut buf);
+        let packet = &buf[..len];
+
+        assert_eq!(len, 56);
+        assert_eq!(&packet[..8], &[0x60, 0, 0, 0, 0, 16, 58, 255]);
+        assert_eq!(source_and_destination(packet), (LINK_LOCAL, ALL_ROUTERS));
+
+        let icmp = &packet[IPV6_HEADER_LEN..];
+        assert_eq!(icmp[0], ICMPV6_ROUTER_SOLICIT);
+        assert_eq!(&icmp[4..8], &[0; 4]);
+        assert_eq!(&icmp[8..], &[1, 1, 0x60, 0x55, 0xf9, 0x12, 0x34, 0x56]);
+        // a valid checksum sums up to zero
+        assert_eq!(icmpv6_checksum(LINK_LOCAL, ALL_ROUTERS, icmp), 0);
+    }
+
+    #[test]
+    fn encodes_dad_solicitations() {
+        let mut buf = [0xff; 64];
+        let len = encode_dad_solicitation(LINK_LOCAL, &mut buf);
+        let packet = &buf[..len];
+        let group = solicited_node_address(LINK_LOCAL);
+
+        assert_eq!(len, 64);
+        assert_eq!(&packet[..8], &[0x60, 0, 0, 0, 0, 24, 58, 255]);
+        assert_eq!(
+            source_and_destination(packet),
+            (Ipv6Addr::UNSPECIFIED, group)
+        );
+
+        let icmp = &packet[IPV6_HEADER_LEN..];
+        assert_eq!(&icmp[..2], &[ICMPV6_NEIGHBOR_SOLICIT, 0]);
+        assert_eq!(&icmp[4..8], &[0; 4]);
+        assert_eq!(&icmp[8..], &LINK_LOCAL.octets());
+        assert_eq!(icmpv6_checksum(Ipv6Addr::UNSPECIFIED, group, icmp), 0);
+    }
+
+    #[test]
+    fn detects_duplicate_addresses() {
+        let group = solicited_node_address(LINK_LOCAL);
+        let other = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x42);
+
+        let advert = |target: Ipv6Addr, hop_limit: u8| {
+            Packet::new(LINK_LOCAL, ALL_NODES, hop_limit, ICMPV6_NEIGHBOR_ADVERT)
+                .bytes(&[0x20, 0, 0, 0])
+                .bytes(&target.octets())
+        };
+        assert!(is_dad_conflict(
+            advert(LINK_LOCAL, 255).as_slice(),
+            LINK_LOCAL
+        ));
+        assert!(!is_dad_conflict(advert(other, 255).as_slice(), LINK_LOCAL));
+        // not from the link
+        assert!(!is_dad_conflict(
+            advert(LINK_LOCAL, 64).as_slice(),
+            LINK_LOCAL
+        ));
+
+        // another node probing the same address
+        let mut probe = [0; 64];
+        let len = encode_dad_solicitation(LINK_LOCAL, &mut probe);
+        assert!(is_dad_conflict(&probe[..len], LINK_LOCAL));
+
+        // address resolution of a configured node is no conflict
+        let resolution = Packet::new(other, group, 255, ICMPV6_NEIGHBOR_SOLICIT)
+            .bytes(&[0; 4])
+            .bytes(&LINK_LOCAL.octets());
+        assert!(!is_dad_conflict(resolution.as_slice(), LINK_LOCAL));
+
+        let truncated = advert(LINK_LOCAL, 255);
+        assert!(!is_dad_conflict(
+            &truncated.as_slice()[..truncated.len - 1],
+            LINK_LOCAL
+        ));
+    }
+
+    #[test]
+    fn parses_router_adverts() {
+        let dns = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+        let omr = Ipv6Addr::new(0xfd12, 0x3456, 0x789a, 1, 0, 0, 0, 0);
+
+        let packet = Packet::router_advert(ROUTER, 255, 1800)
+            // on-link only, not usable for SLAAC
+            .prefix(
+                Ipv6Addr::new(0x2001, 0xdb8, 9, 9, 0, 0, 0, 0),
+                64,
+                0x80,
+                3600,
+            )
+            .prefix(PREFIX, 64, 0xc0, 86400)
+            .bytes(&[OPTION_MTU, 1, 0, 0])
+            .bytes(&1480u32.to_be_bytes())
+            .bytes(&[OPTION_ROUTE_INFO, 2, 64, 0])
+            .bytes(&1800u32.to_be_bytes())
+            .bytes(&omr.octets()[..8])
+            .bytes(&[OPTION_RDNSS, 3, 0, 0])
+            .bytes(&600u32.to_be_bytes())
+            .bytes(&dns.octets())
+            // unknown options are skipped
+            .bytes(&[OPTION_SOURCE_LLADDR, 1, 0, 1, 2, 3, 4, 5]);
+
+        let advert = parse_router_advert(packet.as_slice()).unwrap();
+        assert_eq!(advert.source, ROUTER);
+        assert_eq!(advert.router_lifetime, 1800);
+        assert_eq!(advert.mtu, Some(1480));
+        assert_eq!(advert.prefixes.len(), 2);
+        assert_eq!(
+            advert.slaac_prefix(),
+            Some(&PrefixInfo {
+                prefix: PREFIX,
+                len: 64,
+                on_link: true,
+                autonomous: true,
+                valid_lifetime: 86400,
+                preferred_lifetime: 43200,
+            })
+        );
+        assert_eq!(
+            advert.routes.as_slice(),
+            &[RouteInfo {
+                prefix: omr,
+                len: 64,
+                lifetime: 1800,
+            }]
+        );
+        assert_eq!(advert.dns_servers.as_slice(), &[dns]);
+    }
+
+    #[test]
+    fn selects_slaac_prefixes() {
+        let slaac_prefix = |packet: Packet| {
+            parse_router_advert(packet.as_slice())
+                .unwrap()
+                .slaac_prefix()
+                .map(|prefix| prefix.prefix)
+        };
+
+        let wrong_length = Packet::router_advert(ROUTER, 255, 0).prefix(PREFIX, 48, 0xc0, 3600);
+        assert_eq!(slaac_prefix(wrong_length), None);
+
+        let expired = Packet::router_advert(ROUTER, 255, 0).prefix(PREFIX, 64, 0xc0, 0);
+        assert_eq!(slaac_prefix(expired), None);
+
+        let route_only = Packet::router_advert(ROUTER, 255, 0).prefix(PREFIX, 64, 0x40, 3600);
+        assert_eq!(slaac_prefix(route_only), Some(PREFIX));
+    }
+
+    #[test]
+    fn rejects_invalid_router_adverts() {
+        let forwarded = Packet::router_advert(ROUTER, 254, 1800);
+        assert_eq!(
+            parse_router_advert(forwarded.as_slice()),
+            Err(NdError::InvalidSource)
+        );
+
+        let global = Packet::router_advert(PREFIX, 255, 1800);
+        assert_eq!(
+            parse_router_advert(global.as_slice()),
+            Err(NdError::InvalidSource)
+        );
+
+        let solicitation = Packet::new(ROUTER, ALL_NODES, 255, ICMPV6_NEIGHBOR_SOLICIT)
+            .bytes(&[0; 4])
+            .bytes(&LINK_LOCAL.octets());
+        assert_eq!(
+            parse_router_advert(solicitation.as_slice()),
+            Err(NdError::NotRouterAdvert)
+        );
+
+        let zero_length = Packet::router_advert(ROUTER, 255, 1800).bytes(&[OPTION_MTU, 0]);
+        assert_eq!(
+            parse_router_advert(zero_length.as_slice()),
+            Err(NdError::InvalidOption)
+        );
+
+        let short_prefix = Packet::router_advert(ROUTER, 255, 1800).bytes(&[
+            OPTION_PREFIX_INFO,
+            1,
+            64,
+            0xc0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        assert_eq!(
+            parse_router_advert(short_prefix.as_slice()),
+            Err(NdError::InvalidOption)
+        );
+
+        let advert = Packet::router_advert(ROUTER, 255, 1800).prefix(PREFIX, 64, 0xc0, 3600);
+        let packet = advert.as_slice();
+        assert_eq!(
+            parse_router_advert(&packet[..IPV6_HEADER_LEN + 15]),
+            Err(NdError::Truncated)
+        );
+        // the payload length covers the cut off prefix option
+        assert_eq!(
+            parse_router_advert(&packet[..packet.len() - 1]),
+            Err(NdError::Truncated)
+        );
+    }
+}
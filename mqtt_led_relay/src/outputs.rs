@@ -0,0 +1,41 @@
+use defmt::{info, Format};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use esp_hal::gpio::Output;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    Relay,
+    Led,
+}
+
+static OUTPUT_REQUESTS: Channel<CriticalSectionRawMutex, (OutputKind, bool), 4> = Channel::new();
+
+/// Switch an output, applied by [`outputs_task`].
+pub async fn set(kind: OutputKind, on: bool) {
+    OUTPUT_REQUESTS.send((kind, on)).await;
+}
+
+/// Owns the GPIOs of the relay and the LED. The relay module is powered on start.
+#[embassy_executor::task]
+pub async fn outputs_task(
+    mut relay_power: Output<'static>,
+    mut relay: Output<'static>,
+    mut led: Output<'static>,
+) {
+    relay_power.set_high();
+
+    loop {
+        let (kind, on) = OUTPUT_REQUESTS.receive().await;
+        info!("Switching {:?} {}", kind, if on { "on" } else { "off" });
+
+        let output = match kind {
+            OutputKind::Relay => &mut relay,
+            OutputKind::Led => &mut led,
+        };
+        if on {
+            output.set_high();
+        } else {
+            output.set_low();
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use core::{fmt::Display, str::FromStr};
+
+use defmt::Format;
+use esp_hal::clock::CpuClock;
+use esp_wifi::config::PowerSaveMode;
+
+/// Power profiles trading energy consumption against latency.
+///
+/// The CPU clock can only be set in `esp_hal::init`, so switching the profile at runtime
+/// only changes the radio power-save mode. The CPU clock of the startup profile stays
+/// active until the next reboot.
+///
+/// The listen interval is read by esp-wifi at build time from `ESP_WIFI_CONFIG_LISTEN_INTERVAL`
+/// (set to 10 beacons in `.cargo/config.toml`). The radio only follows it in maximum modem
+/// sleep, so it only matters for [`PowerProfile::LowPower`].
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum PowerProfile {
+    /// `performance` (default): 160 MHz, radio always on.
+    ///
+    /// Highest current draw (~100 mA while associated), commands are received immediately.
+    Performance,
+    /// `balanced`: 160 MHz, minimum modem sleep.
+    ///
+    /// The radio sleeps between beacons and wakes up for every DTIM. Roughly halves the
+    /// average current, adds up to one beacon interval (~100 ms) of receive latency.
+    Balanced,
+    /// `low-power`: 80 MHz, maximum modem sleep.
+    ///
+    /// The radio only wakes up every listen interval (10 beacons), so incoming commands can take
+    /// ~1 s or longer and MQTT keep alives must be at least a few seconds.
+    /// Lowest current draw, suited for relays that mostly wait for commands.
+    LowPower,
+}
+
+impl PowerProfile {
+    pub fn cpu_clock(&self) -> CpuClock {
+        match self {
+            PowerProfile::Performance | PowerProfile::Balanced => CpuClock::_160MHz,
+            PowerProfile::LowPower => CpuClock::_80MHz,
+        }
+    }
+
+    pub fn power_save_mode(&self) -> PowerSaveMode {
+        match self {
+            PowerProfile::Performance => PowerSaveMode::None,
+            PowerProfile::Balanced => PowerSaveMode::Minimum,
+            PowerProfile::LowPower => PowerSaveMode::Maximum,
+        }
+    }
+}
+
+impl FromStr for PowerProfile {
+    type Err = UnknownPowerProfile;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "performance" => Ok(PowerProfile::Performance),
+            "balanced" => Ok(PowerProfile::Balanced),
+            "low-power" => Ok(PowerProfile::LowPower),
+            _ => Err(UnknownPowerProfile),
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownPowerProfile;
+
+impl Display for UnknownPowerProfile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "unknown power profile, expected one of performance, balanced, low-power"
+        )
+    }
+}
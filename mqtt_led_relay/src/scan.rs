@@ -0,0 +1,150 @@
+use core::fmt::Write;
+
+use defmt::Format;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use esp_wifi::wifi::{AccessPointInfo, AuthMethod};
+use heapless::{String, Vec};
+
+/// Maximum number of networks kept from a single scan, sorted by signal strength.
+pub const MAX_SCAN_RESULTS: usize = 10;
+
+/// Access points read from the driver per scan, the strongest [`MAX_SCAN_RESULTS`] of them
+/// are kept.
+pub const SCAN_CAPACITY: usize = 32;
+
+/// Size of the JSON payload produced by [`write_json`] for `MAX_SCAN_RESULTS` networks.
+pub const SCAN_PAYLOAD_SIZE: usize = 1024;
+
+pub type ScanResults = Vec<NetworkInfo, MAX_SCAN_RESULTS>;
+
+/// Set by the connection task whenever a scan finished, the MQTT client publishes it.
+pub(crate) static SCAN_DONE: Signal<CriticalSectionRawMutex, ScanResults> = Signal::new();
+
+#[derive(Debug, Format, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i8,
+    pub auth_method: Option<AuthMethod>,
+}
+
+impl From<&AccessPointInfo> for NetworkInfo {
+    fn from(ap: &AccessPointInfo) -> Self {
+        Self {
+            ssid: ap.ssid.clone(),
+            bssid: ap.bssid,
+            channel: ap.channel,
+            rssi: ap.signal_strength,
+            auth_method: ap.auth_method,
+        }
+    }
+}
+
+/// Keep the strongest networks, strongest first.
+pub fn strongest(access_points: &mut [AccessPointInfo]) -> ScanResults {
+    access_points.sort_unstable_by_key(|ap| core::cmp::Reverse(ap.signal_strength));
+    access_points
+        .iter()
+        .take(MAX_SCAN_RESULTS)
+        .map(NetworkInfo::from)
+        .collect()
+}
+
+/// Wait for the next finished scan (started at boot or via [`crate::wifi::request_scan`]).
+pub async fn wait_results() -> ScanResults {
+    SCAN_DONE.wait().await
+}
+
+/// Serialize the scan results as a JSON array:
+///
+/// `[{"ssid":"home","bssid":"aa:bb:cc:dd:ee:ff","channel":6,"rssi":-52,"auth":"wpa2-personal"}]`
+pub fn write_json<W: Write>(results: &[NetworkInfo], out: &mut W) -> core::fmt::Result {
+    out.write_char('[')?;
+
+    for (i, network) in results.iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+
+        out.write_str("{\"ssid\":\"")?;
+        for c in network.ssid.chars() {
+            match c {
+                '"' => out.write_str("\\\"")?,
+                '\\' => out.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+                c => out.write_char(c)?,
+            }
+        }
+
+        let b = network.bssid;
+        write!(
+            out,
+            "\",\"bssid\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\",\"channel\":{},\"rssi\":{},\"auth\":\"{}\"}}",
+            b[0],
+            b[1],
+            b[2],
+            b[3],
+            b[4],
+            b[5],
+            network.channel,
+            network.rssi,
+            auth_name(network.auth_method)
+        )?;
+    }
+
+    out.write_char(']')
+}
+
+fn auth_name(auth_method: Option<AuthMethod>) -> &'static str {
+    match auth_method {
+        None => "unknown",
+        Some(AuthMethod::None) => "open",
+        Some(AuthMethod::WEP) => "wep",
+        Some(AuthMethod::WPA) => "wpa",
+        Some(AuthMethod::WPA2Personal) => "wpa2-personal",
+        Some(AuthMethod::WPAWPA2Personal) => "wpa-wpa2-personal",
+        Some(AuthMethod::WPA2Enterprise) => "wpa2-enterprise",
+        Some(AuthMethod::WPA3Personal) => "wpa3-personal",
+        Some(AuthMethod::WPA2WPA3Personal) => "wpa2-wpa3-personal",
+        Some(AuthMethod::WAPIPersonal) => "wapi-personal",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_point(ssid: &str, signal_strength: i8) -> AccessPointInfo {
+        AccessPointInfo {
+            ssid: ssid.try_into().unwrap(),
+            signal_strength,
+            auth_method: Some(AuthMethod::WPA2Personal),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_the_strongest_networks() {
+        let mut access_points: Vec<AccessPointInfo, SCAN_CAPACITY> = (0..20)
+            .map(|i| access_point("net", -90 + i as i8))
+            .collect();
+
+        let results = strongest(&mut access_points);
+        assert_eq!(results.len(), MAX_SCAN_RESULTS);
+        assert_eq!(results[0].rssi, -71);
+        assert_eq!(results[MAX_SCAN_RESULTS - 1].rssi, -80);
+    }
+
+    #[test]
+    fn escapes_ssids() {
+        let results = strongest(&mut [access_point("a\"b\\c", -40)]);
+
+        let mut json: String<SCAN_PAYLOAD_SIZE> = String::new();
+        write_json(&results, &mut json).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"ssid":"a\"b\\c","bssid":"00:00:00:00:00:00","channel":0,"rssi":-40,"auth":"wpa2-personal"}]"#
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Socket counts and buffer sizes of all network services in one place.
+//!
+//! Every service adds an entry to [`SERVICES`], the number of sockets of the embassy-net stack
+//! is derived from it. Socket buffers live in the futures of the tasks, i.e. in the executor
+//! arena, so their sum is checked against [`TASK_ARENA_SIZE`] at compile time. The heap is
+//! used by esp-wifi and the Wi-Fi scan, see [`crate::scan::SCAN_CAPACITY`].
+//!
+//! The TCP buffers default to 4 KiB each, the `small-tcp-buffers` feature reduces them to
+//! 1.5 KiB (one full-sized segment) to make room for further services.
+
+use defmt::{info, Format};
+use embassy_net::StackResources;
+
+use crate::espnow_frame::MAX_FRAME_LEN;
+use crate::scan::SCAN_PAYLOAD_SIZE;
+use crate::sntp_packet::NTP_PACKET_LEN;
+
+/// Size of the esp-alloc heap, used by esp-wifi and the Wi-Fi scan.
+pub const HEAP_SIZE: usize = 72 * 1024;
+
+/// Has to match the `task-arena-size-*` feature of embassy-executor in Cargo.toml.
+pub const TASK_ARENA_SIZE: usize = 20480;
+
+/// Part of the arena kept free for the state of the futures besides the socket buffers.
+const TASK_ARENA_RESERVE: usize = 4096;
+
+pub const TCP_RX_BUFFER_SIZE: usize = if cfg!(feature = "small-tcp-buffers") { 1536 } else { 4096 };
+pub const TCP_TX_BUFFER_SIZE: usize = if cfg!(feature = "small-tcp-buffers") { 1536 } else { 4096 };
+
+/// Size of the MQTT packet buffers, large enough for a full scan result.
+pub const MQTT_BUFFER_SIZE: usize = SCAN_PAYLOAD_SIZE + 64;
+
+/// Largest mDNS packet sent or received, larger packets are dropped.
+pub const MDNS_BUFFER_SIZE: usize = 512;
+
+/// Largest router advertisement received, larger packets are dropped.
+pub const SLAAC_BUFFER_SIZE: usize = 512;
+
+/// Memory used by one network service.
+#[derive(Debug, Format, Clone, Copy)]
+pub struct Service {
+    pub name: &'static str,
+    /// Sockets of the embassy-net stack
+    pub sockets: usize,
+    /// Socket and packet buffers in the executor arena
+    pub buffers: usize,
+}
+
+pub const SERVICES: &[Service] = &[
+    Service {
+        name: "dhcp",
+        sockets: 1,
+        buffers: 0,
+    },
+    Service {
+        name: "dns",
+        sockets: 1,
+        buffers: 0,
+    },
+    Service {
+        name: "mqtt",
+        sockets: 1,
+        buffers: TCP_RX_BUFFER_SIZE + TCP_TX_BUFFER_SIZE + 2 * MQTT_BUFFER_SIZE + SCAN_PAYLOAD_SIZE,
+    },
+    // the one-shot resolver for `.local` brokers runs in the MQTT client
+    Service {
+        name: "mdns-resolver",
+        sockets: 1,
+        buffers: 3 * MDNS_BUFFER_SIZE,
+    },
+    Service {
+        name: "mdns-responder",
+        sockets: 1,
+        buffers: 3 * MDNS_BUFFER_SIZE,
+    },
+    Service {
+        name: "sntp",
+        sockets: 1,
+        buffers: 4 * NTP_PACKET_LEN,
+    },
+    // ESP-NOW uses the radio driver directly, frames are queued on the heap by esp-wifi
+    Service {
+        name: "espnow",
+        sockets: 0,
+        buffers: 2 * MAX_FRAME_LEN,
+    },
+    #[cfg(feature = "ipv6")]
+    Service {
+        name: "slaac",
+        sockets: 1,
+        buffers: 2 * SLAAC_BUFFER_SIZE + 64,
+    },
+];
+
+/// Sockets of the embassy-net stack.
+pub const STACK_SOCKETS: usize = {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < SERVICES.len() {
+        sum += SERVICES[i].sockets;
+        i += 1;
+    }
+    sum
+};
+
+/// Socket buffers of all services in the executor arena.
+pub const ARENA_BUFFERS: usize = {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < SERVICES.len() {
+        sum += SERVICES[i].buffers;
+        i += 1;
+    }
+    sum
+};
+
+const _: () = assert!(
+    ARENA_BUFFERS + TASK_ARENA_RESERVE <= TASK_ARENA_SIZE,
+    "network buffers exceed the executor arena, enable `small-tcp-buffers` or increase the arena"
+);
+
+/// Log the memory budget of the network services.
+pub fn log_budget() {
+    for service in SERVICES {
+        info!(
+            "Network service {}: {} socket(s), {} bytes of buffers",
+            service.name, service.sockets, service.buffers
+        );
+    }
+    info!(
+        "Network budget: {} sockets ({} bytes static), {}/{} bytes of arena ({} reserved), {} bytes of heap",
+        STACK_SOCKETS,
+        core::mem::size_of::<StackResources<STACK_SOCKETS>>(),
+        ARENA_BUFFERS,
+        TASK_ARENA_SIZE,
+        TASK_ARENA_RESERVE,
+        HEAP_SIZE,
+    );
+}
@@ -0,0 +1,230 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862) for the Wi-Fi station.
+//!
+//! smoltcp does not process router advertisements and embassy-net only keeps a single static
+//! IPv6 address per interface. The station therefore starts with its EUI-64 link-local address,
+//! solicits routers and replaces the link-local address with the SLAAC address of the first
+//! autonomous /64 prefix, using the advertising router as gateway and RDNSS servers for DNS.
+//! The link-local address is restored when the prefix expires.
+//!
+//! Both addresses run duplicate address detection (RFC 4862, section 5.4) before they are
+//! configured, again after every link up. smoltcp discards packets from the unspecified address, so only the neighbor
+//! advertisement of the owner reveals a duplicate, not the simultaneous detection of another
+//! node.
+
+use core::net::Ipv6Addr;
+
+use defmt::{debug, error, info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    raw::{PacketMetadata, RawSocket},
+    ConfigV6, HardwareAddress, Ipv6Cidr, Stack, StaticConfigV6,
+};
+use embassy_time::{with_deadline, with_timeout, Duration, Instant};
+use esp_wifi::wifi::WifiDevice;
+use heapless::Vec;
+use smoltcp::wire::{IpProtocol, IpVersion};
+
+use crate::nd_packet::{self, NdError};
+use crate::sizing::SLAAC_BUFFER_SIZE;
+
+const MAX_SOLICITATIONS: usize = 3;
+const SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+/// Time to wait for a conflicting advertisement, `RetransTimer` of RFC 4861
+const DAD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configures the link-local address after link up and the SLAAC address once a router
+/// advertised a prefix.
+#[embassy_executor::task]
+pub async fn slaac_task(stack: Stack<'static>) {
+    let mac = match stack.hardware_address() {
+        HardwareAddress::Ethernet(address) => address.0,
+        #[allow(unreachable_patterns)]
+        _ => {
+            warn!("SLAAC needs an Ethernet-like interface");
+            return;
+        }
+    };
+    let link_local = nd_packet::link_local_address(mac);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; SLAAC_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 64];
+    let socket = RawSocket::new::<WifiDevice<'static>>(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    let mut packet = [0; SLAAC_BUFFER_SIZE];
+
+    loop {
+        stack.wait_link_up().await;
+
+        if !is_unique(stack, &socket, link_local, &mut packet).await {
+            error!(
+                "IPv6 link-local address {} is in use, IPv6 stays disabled until the link goes down",
+                link_local
+            );
+            stack.wait_link_down().await;
+            continue;
+        }
+
+        info!("IPv6 link-local address: {}", link_local);
+        set_link_local(stack, link_local);
+
+        let mut current_prefix: Option<(Ipv6Addr, Instant)> = None;
+        // prefix whose SLAAC address is used by another node, ignored until the link goes down
+        let mut duplicate_prefix: Option<Ipv6Addr> = None;
+        let mut solicitations = 0;
+
+        loop {
+            // solicit until the first advertisement, afterwards rely on periodic advertisements
+            let timeout = if current_prefix.is_none() && solicitations < MAX_SOLICITATIONS {
+                let len = nd_packet::encode_router_solicitation(link_local, mac, &mut packet);
+                socket.send(&packet[..len]).await;
+                solicitations += 1;
+                SOLICITATION_INTERVAL
+            } else {
+                match current_prefix {
+                    Some((_, expires)) => expires.saturating_duration_since(Instant::now()),
+                    None => Duration::from_secs(3600),
+                }
+            };
+
+            let received = match select(
+                with_timeout(timeout, socket.recv(&mut packet)),
+                stack.wait_link_down(),
+            )
+            .await
+            {
+                Either::First(received) => received,
+                Either::Second(_) => {
+                    info!("Link down, dropping IPv6 configuration");
+                    stack.set_config_v6(ConfigV6::None);
+                    break;
+                }
+            };
+
+            let len = match received {
+                Ok(Ok(len)) => len,
+                Ok(Err(e)) => {
+                    debug!("Raw socket receive error: {:?}", e);
+                    continue;
+                }
+                Err(_) => {
+                    if let Some((prefix, expires)) = current_prefix {
+                        if Instant::now() >= expires {
+                            warn!("IPv6 prefix {} expired", prefix);
+                            current_prefix = None;
+                            solicitations = 0;
+                            set_link_local(stack, link_local);
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let advert = match nd_packet::parse_router_advert(&packet[..len]) {
+                Ok(advert) => advert,
+                Err(NdError::NotRouterAdvert) => continue,
+                Err(e) => {
+                    debug!("Ignoring router advertisement: {:?}", e);
+                    continue;
+                }
+            };
+
+            debug!("Router advertisement: {:?}", advert);
+
+            let Some(prefix) = advert.slaac_prefix() else {
+                continue;
+            };
+            // routers with lifetime 0 (e.g. Thread border routers only announcing routes)
+            // are used as gateway only if no default router was seen
+            if advert.router_lifetime == 0 && current_prefix.is_some() {
+                continue;
+            }
+            if duplicate_prefix == Some(prefix.prefix) {
+                continue;
+            }
+
+            let address = nd_packet::slaac_address(prefix.prefix, mac);
+            let expires = Instant::now() + Duration::from_secs(prefix.valid_lifetime as u64);
+
+            if current_prefix.map(|(current, _)| current) != Some(prefix.prefix) {
+                if !is_unique(stack, &socket, address, &mut packet).await {
+                    warn!(
+                        "IPv6 SLAAC address {} is in use, ignoring the prefix",
+                        address
+                    );
+                    duplicate_prefix = Some(prefix.prefix);
+                    continue;
+                }
+
+                info!("IPv6 SLAAC address {} via {}", address, advert.source);
+                for route in advert.routes.iter() {
+                    debug!("Router announces route {}/{}", route.prefix, route.len);
+                }
+
+                let mut dns_servers = Vec::new();
+                for server in advert.dns_servers.iter() {
+                    let _ = dns_servers.push(*server);
+                }
+
+                stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+                    address: Ipv6Cidr::new(address, 64),
+                    gateway: Some(advert.source),
+                    dns_servers,
+                }));
+            }
+
+            current_prefix = Some((prefix.prefix, expires));
+        }
+    }
+}
+
+fn set_link_local(stack: Stack<'static>, link_local: Ipv6Addr) {
+    stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local, 64),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+}
+
+/// Duplicate address detection for the tentative `address` with a single solicitation.
+async fn is_unique(
+    stack: Stack<'static>,
+    socket: &RawSocket<'_>,
+    address: Ipv6Addr,
+    packet: &mut [u8],
+) -> bool {
+    // the owner answers to all nodes, but RFC 4862 requires the membership in the
+    // solicited-node group of the tentative address during the detection
+    let group = nd_packet::solicited_node_address(address);
+    if let Err(e) = stack.join_multicast_group(group) {
+        warn!(
+            "Failed to join {} for duplicate address detection: {:?}",
+            group, e
+        );
+    }
+
+    let len = nd_packet::encode_dad_solicitation(address, packet);
+    socket.send(&packet[..len]).await;
+
+    let deadline = Instant::now() + DAD_TIMEOUT;
+    let unique = loop {
+        match with_deadline(deadline, socket.recv(packet)).await {
+            Ok(Ok(len)) if nd_packet::is_dad_conflict(&packet[..len], address) => break false,
+            Ok(_) => continue,
+            Err(_) => break true,
+        }
+    };
+
+    // smoltcp answers on the solicited-node groups of its own addresses by itself
+    let _ = stack.leave_multicast_group(group);
+    unique
+}
@@ -0,0 +1,281 @@
+use core::{cell::Cell, net::Ipv4Addr};
+
+use defmt::{debug, error, info, warn, Format};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpAddress, IpEndpoint, Stack,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use smoltcp::wire::DnsQueryType;
+
+use crate::connectivity;
+use crate::mdns;
+use crate::sntp_packet::{
+    self, decode_response, encode_request, offset_and_delay, NtpTimestamp, SntpError,
+    NTP_PACKET_LEN,
+};
+
+pub const NTP_PORT: u16 = 123;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Offsets are not used for drift estimation if the previous sync is more recent than this
+const MIN_DRIFT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Wall-clock time in microseconds since the unix epoch.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtcTime(pub u64);
+
+impl UtcTime {
+    pub fn unix_secs(&self) -> u64 {
+        self.0 / 1_000_000
+    }
+
+    pub fn unix_millis(&self) -> u64 {
+        self.0 / 1_000
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStatus {
+    /// No successful sync since boot
+    Unsynced,
+    /// Synced, the last sync is at most `3 * interval` old
+    Synced,
+    /// The last successful sync is older than `3 * interval`, the clock keeps running on the
+    /// last offset and drift estimate
+    Stale,
+}
+
+/// Clock state after the last successful sync.
+#[derive(Debug, Format, Clone, Copy)]
+pub struct ClockState {
+    /// Unix time minus uptime in microseconds at the last sync
+    pub offset_us: i64,
+    /// Estimated drift of the local clock in parts per million (positive if the local clock is slow)
+    pub drift_ppm: i64,
+    /// Round trip delay of the last sync in microseconds
+    pub delay_us: i64,
+    pub last_sync: Instant,
+}
+
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<Option<ClockState>>> =
+    Mutex::new(Cell::new(None));
+static SYNC_INTERVAL: Mutex<CriticalSectionRawMutex, Cell<Duration>> =
+    Mutex::new(Cell::new(Duration::from_secs(3600)));
+
+/// Current wall-clock time, `None` until the first successful sync.
+pub fn now_utc() -> Option<UtcTime> {
+    let state = CLOCK.lock(|clock| clock.get())?;
+    let now = Instant::now();
+
+    let micros = sntp_packet::local_to_unix(
+        now.as_micros() as i64,
+        state.offset_us,
+        state.drift_ppm,
+        (now - state.last_sync).as_micros() as i64,
+    );
+
+    Some(UtcTime(micros))
+}
+
+pub fn status() -> SyncStatus {
+    let interval = SYNC_INTERVAL.lock(|interval| interval.get());
+
+    match CLOCK.lock(|clock| clock.get()) {
+        None => SyncStatus::Unsynced,
+        Some(state) if state.last_sync.elapsed() <= interval * 3 => SyncStatus::Synced,
+        Some(_) => SyncStatus::Stale,
+    }
+}
+
+/// Offset, drift and delay of the last successful sync.
+pub fn clock_state() -> Option<ClockState> {
+    CLOCK.lock(|clock| clock.get())
+}
+
+#[derive(Debug, Format, Clone, Copy)]
+pub struct SntpConfig {
+    /// Comma separated list of NTP servers (IPv4 addresses or host names).
+    /// If empty, the DHCP gateway is asked, as embassy-net does not expose the DHCP NTP option.
+    pub servers: &'static str,
+    pub interval: Duration,
+}
+
+/// Periodically syncs the clock with the first responding server of the configuration.
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>, config: SntpConfig) {
+    info!("SNTP task started");
+    SYNC_INTERVAL.lock(|interval| interval.set(config.interval));
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * NTP_PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; NTP_PACKET_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(0) {
+        error!("Failed to bind SNTP socket: {:?}", e);
+        return;
+    }
+
+    loop {
+        if connectivity::wait_online().await.is_none() {
+            Timer::after(RETRY_INTERVAL).await;
+            continue;
+        }
+
+        let mut synced = false;
+
+        if config.servers.trim().is_empty() {
+            match stack.config_v4().and_then(|config| config.gateway) {
+                Some(gateway) => synced = sync_with(&mut socket, gateway).await,
+                None => warn!("No NTP server configured and no gateway known"),
+            }
+        }
+
+        for server in config
+            .servers
+            .split(',')
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+        {
+            if synced {
+                break;
+            }
+
+            let Some(address) = resolve_server(stack, server).await else {
+                continue;
+            };
+
+            debug!("Syncing with NTP server {} ({})", server, address);
+            synced = sync_with(&mut socket, address).await;
+        }
+
+        if synced {
+            Timer::after(config.interval).await;
+        } else {
+            warn!("SNTP sync failed, retrying in {} s", RETRY_INTERVAL.as_secs());
+            Timer::after(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn resolve_server(stack: Stack<'static>, server: &str) -> Option<Ipv4Addr> {
+    if let Ok(address) = server.parse::<Ipv4Addr>() {
+        return Some(address);
+    }
+
+    if mdns::is_local_name(server) {
+        return match mdns::resolve(stack, server).await {
+            Ok(address) => Some(address),
+            Err(e) => {
+                warn!("Failed to resolve NTP server {}: {:?}", server, e);
+                None
+            }
+        };
+    }
+
+    match stack.dns_query(server, DnsQueryType::A).await {
+        Ok(addresses) => addresses.iter().find_map(|address| match address {
+            IpAddress::Ipv4(address) => Some(*address),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }),
+        Err(e) => {
+            warn!("Failed to resolve NTP server {}: {:?}", server, e);
+            None
+        }
+    }
+}
+
+async fn sync_with(socket: &mut UdpSocket<'_>, server: Ipv4Addr) -> bool {
+    let endpoint = IpEndpoint::new(server.into(), NTP_PORT);
+
+    let mut packet = [0; NTP_PACKET_LEN];
+    let t1 = Instant::now();
+    // the transmit timestamp only has to be unique, the uptime is good enough and doesn't
+    // depend on a previous sync
+    let origin = NtpTimestamp::from_unix_micros(t1.as_micros());
+    encode_request(origin, &mut packet);
+
+    if let Err(e) = socket.send_to(&packet, endpoint).await {
+        warn!("Failed to send SNTP request: {:?}", e);
+        return false;
+    }
+
+    let response = with_timeout(REQUEST_TIMEOUT, async {
+        loop {
+            let Ok((len, meta)) = socket.recv_from(&mut packet).await else {
+                continue;
+            };
+            if meta.endpoint != endpoint {
+                continue;
+            }
+            match decode_response(&packet[..len], origin) {
+                Ok(response) => return Ok(response),
+                Err(SntpError::UnexpectedOrigin) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    })
+    .await;
+    let t4 = Instant::now();
+
+    let response = match response {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            warn!("Invalid SNTP response from {}: {:?}", server, e);
+            return false;
+        }
+        Err(_) => {
+            warn!("SNTP request to {} timed out", server);
+            return false;
+        }
+    };
+
+    let (offset_us, delay_us) = offset_and_delay(
+        t1.as_micros() as i64,
+        response.receive.to_unix_micros() as i64,
+        response.transmit.to_unix_micros() as i64,
+        t4.as_micros() as i64,
+    );
+
+    let drift_ppm = CLOCK.lock(|clock| {
+        let drift_ppm = match clock.get() {
+            Some(previous) if t4 - previous.last_sync >= MIN_DRIFT_INTERVAL => {
+                sntp_packet::estimate_drift(
+                    previous.offset_us,
+                    previous.drift_ppm,
+                    offset_us,
+                    (t4 - previous.last_sync).as_micros() as i64,
+                )
+            }
+            Some(previous) => previous.drift_ppm,
+            None => 0,
+        };
+
+        clock.set(Some(ClockState {
+            offset_us,
+            drift_ppm,
+            delay_us,
+            last_sync: t4,
+        }));
+
+        drift_ppm
+    });
+
+    info!(
+        "SNTP synced with {} (stratum {}): offset {} us, delay {} us, drift {} ppm",
+        server, response.stratum, offset_us, delay_us, drift_ppm
+    );
+
+    true
+}
@@ -0,0 +1,331 @@
+//! SNTP (RFC 4330) message encoding and decoding and the clock math of [`crate::sntp`].
+
+use defmt::Format;
+
+pub const NTP_PACKET_LEN: usize = 48;
+/// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+/// Drift estimates beyond this are treated as a time step on the server and discarded
+pub const MAX_DRIFT_PPM: i64 = 500;
+
+/// Clock offset and round trip delay (RFC 4330, section 5) from the client transmit (`t1`),
+/// server receive (`t2`), server transmit (`t3`) and client receive (`t4`) times.
+///
+/// `t1`/`t4` are in local clock microseconds, `t2`/`t3` in unix microseconds, so the offset
+/// converts the local clock to unix time.
+pub fn offset_and_delay(t1: i64, t2: i64, t3: i64, t4: i64) -> (i64, i64) {
+    let offset = ((t2 - t1) + (t3 - t4)) / 2;
+    let delay = (t4 - t1) - (t3 - t2);
+
+    (offset, delay)
+}
+
+/// Unix time in microseconds of the local clock time `local_us`, `since_sync_us` after a sync
+/// that measured `offset_us`, corrected by the drift estimate.
+pub fn local_to_unix(local_us: i64, offset_us: i64, drift_ppm: i64, since_sync_us: i64) -> u64 {
+    let micros = local_us + offset_us + since_sync_us * drift_ppm / 1_000_000;
+
+    micros.max(0) as u64
+}
+
+/// Drift of the local clock in parts per million from two offsets measured `elapsed_us` apart.
+///
+/// A drift beyond [`MAX_DRIFT_PPM`] means the server time jumped, the previous estimate is kept.
+pub fn estimate_drift(
+    previous_offset_us: i64,
+    previous_drift_ppm: i64,
+    offset_us: i64,
+    elapsed_us: i64,
+) -> i64 {
+    let drift = (offset_us - previous_offset_us) * 1_000_000 / elapsed_us;
+
+    if drift.abs() > MAX_DRIFT_PPM {
+        previous_drift_ppm
+    } else {
+        drift
+    }
+}
+
+/// 64 bit NTP timestamp: seconds since 1900 and a 32 bit binary fraction.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct NtpTimestamp {
+    pub seconds: u32,
+    pub fraction: u32,
+}
+
+impl NtpTimestamp {
+    pub fn from_unix_micros(micros: u64) -> Self {
+        let seconds = micros / 1_000_000 + NTP_UNIX_OFFSET_SECS;
+        let fraction = ((micros % 1_000_000) << 32) / 1_000_000;
+
+        Self {
+            seconds: seconds as u32,
+            fraction: fraction as u32,
+        }
+    }
+
+    /// Converts to unix time, NTP era 0 is assumed to wrap in 2036 to era 1.
+    pub fn to_unix_micros(&self) -> u64 {
+        // timestamps with the high bit cleared are in era 1 (from 2036 on)
+        let seconds = if self.seconds & 0x8000_0000 == 0 {
+            self.seconds as u64 + (1 << 32)
+        } else {
+            self.seconds as u64
+        };
+        let fraction = (self.fraction as u64 * 1_000_000) >> 32;
+
+        (seconds - NTP_UNIX_OFFSET_SECS) * 1_000_000 + fraction
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        Self {
+            seconds: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            fraction: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[..4].copy_from_slice(&self.seconds.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.fraction.to_be_bytes());
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct SntpResponse {
+    pub stratum: u8,
+    pub receive: NtpTimestamp,
+    pub transmit: NtpTimestamp,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum SntpError {
+    Truncated,
+    NotServerMode,
+    /// Leap indicator 3 or stratum 0 ("kiss of death")
+    Unsynchronized,
+    /// The originate timestamp doesn't match our request
+    UnexpectedOrigin,
+    ZeroTransmitTimestamp,
+}
+
+/// Encode a client request (version 4, mode 3) with the given transmit timestamp.
+pub fn encode_request(transmit: NtpTimestamp, buf: &mut [u8; NTP_PACKET_LEN]) {
+    buf.fill(0);
+    // LI 0, VN 4, mode 3 (client)
+    buf[0] = 0b00_100_011;
+    transmit.write(&mut buf[40..48]);
+}
+
+/// Decode and validate a server response to a request sent with `origin` as transmit timestamp.
+pub fn decode_response(packet: &[u8], origin: NtpTimestamp) -> Result<SntpResponse, SntpError> {
+    if packet.len() < NTP_PACKET_LEN {
+        return Err(SntpError::Truncated);
+    }
+
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0b111;
+    let stratum = packet[1];
+
+    if mode != 4 {
+        return Err(SntpError::NotServerMode);
+    }
+    if NtpTimestamp::read(&packet[24..32]) != origin {
+        return Err(SntpError::UnexpectedOrigin);
+    }
+    if leap == 3 || stratum == 0 || stratum > 15 {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    let transmit = NtpTimestamp::read(&packet[40..48]);
+    if transmit.seconds == 0 && transmit.fraction == 0 {
+        return Err(SntpError::ZeroTransmitTimestamp);
+    }
+
+    Ok(SntpResponse {
+        stratum,
+        receive: NtpTimestamp::read(&packet[32..40]),
+        transmit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00.5Z
+    const UNIX_MICROS: u64 = 1_704_067_200_500_000;
+
+    fn response(origin: NtpTimestamp) -> [u8; NTP_PACKET_LEN] {
+        let mut packet = [0; NTP_PACKET_LEN];
+        // LI 0, VN 4, mode 4 (server), stratum 2
+        packet[0] = 0b00_100_100;
+        packet[1] = 2;
+        origin.write(&mut packet[24..32]);
+        NtpTimestamp::from_unix_micros(UNIX_MICROS).write(&mut packet[32..40]);
+        NtpTimestamp::from_unix_micros(UNIX_MICROS + 250).write(&mut packet[40..48]);
+        packet
+    }
+
+    #[test]
+    fn converts_timestamps() {
+        let timestamp = NtpTimestamp::from_unix_micros(UNIX_MICROS);
+        assert_eq!(timestamp.seconds, 3_913_056_000);
+        assert_eq!(timestamp.fraction, 1 << 31);
+        assert_eq!(timestamp.to_unix_micros(), UNIX_MICROS);
+
+        assert_eq!(
+            NtpTimestamp::from_unix_micros(0),
+            NtpTimestamp {
+                seconds: NTP_UNIX_OFFSET_SECS as u32,
+                fraction: 0
+            }
+        );
+
+        // era 1 starts 2036-02-07T06:28:16Z
+        let era_1 = NtpTimestamp {
+            seconds: 0,
+            fraction: 0,
+        };
+        assert_eq!(era_1.to_unix_micros(), 2_085_978_496_000_000);
+        let next_century = 4_102_444_800_000_000;
+        assert_eq!(
+            NtpTimestamp::from_unix_micros(next_century).to_unix_micros(),
+            next_century
+        );
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let mut buf = [0xff; NTP_PACKET_LEN];
+        let transmit = NtpTimestamp {
+            seconds: 0x0102_0304,
+            fraction: 0x0506_0708,
+        };
+        encode_request(transmit, &mut buf);
+
+        assert_eq!(buf[0], 0x23);
+        assert!(buf[1..40].iter().all(|&byte| byte == 0));
+        assert_eq!(&buf[40..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn decodes_responses() {
+        let origin = NtpTimestamp::from_unix_micros(42);
+        let packet = response(origin);
+
+        assert_eq!(
+            decode_response(&packet, origin),
+            Ok(SntpResponse {
+                stratum: 2,
+                receive: NtpTimestamp::from_unix_micros(UNIX_MICROS),
+                transmit: NtpTimestamp::from_unix_micros(UNIX_MICROS + 250),
+            })
+        );
+
+        // extension fields or a MAC may follow
+        let mut longer = [0; NTP_PACKET_LEN + 20];
+        longer[..NTP_PACKET_LEN].copy_from_slice(&packet);
+        assert!(decode_response(&longer, origin).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        let origin = NtpTimestamp::from_unix_micros(42);
+
+        let packet = response(origin);
+        assert_eq!(
+            decode_response(&packet[..NTP_PACKET_LEN - 1], origin),
+            Err(SntpError::Truncated)
+        );
+        assert_eq!(
+            decode_response(&packet, NtpTimestamp::from_unix_micros(43)),
+            Err(SntpError::UnexpectedOrigin)
+        );
+
+        let mut request = [0; NTP_PACKET_LEN];
+        encode_request(origin, &mut request);
+        assert_eq!(
+            decode_response(&request, origin),
+            Err(SntpError::NotServerMode)
+        );
+
+        let mut alarm = response(origin);
+        alarm[0] |= 0b11 << 6;
+        assert_eq!(
+            decode_response(&alarm, origin),
+            Err(SntpError::Unsynchronized)
+        );
+
+        for stratum in [0, 16] {
+            let mut kiss_of_death = response(origin);
+            kiss_of_death[1] = stratum;
+            assert_eq!(
+                decode_response(&kiss_of_death, origin),
+                Err(SntpError::Unsynchronized)
+            );
+        }
+
+        let mut no_transmit = response(origin);
+        no_transmit[40..].fill(0);
+        assert_eq!(
+            decode_response(&no_transmit, origin),
+            Err(SntpError::ZeroTransmitTimestamp)
+        );
+    }
+
+    #[test]
+    fn computes_offset_and_delay() {
+        // local clock 1000 s behind, 10 ms to the server, 20 ms back, 5 ms on the server
+        let t1 = 5_000_000;
+        let t2 = t1 + 1_000_000_000 + 10_000;
+        let t3 = t2 + 5_000;
+        let t4 = t1 + 35_000;
+
+        let (offset, delay) = offset_and_delay(t1, t2, t3, t4);
+        // the asymmetry of the paths shows up as half of its difference
+        assert_eq!(offset, 1_000_000_000 - 5_000);
+        assert_eq!(delay, 30_000);
+
+        // local clock ahead of the server
+        assert_eq!(
+            offset_and_delay(2_000_000, 1_000_000, 1_000_000, 2_000_000),
+            (-1_000_000, 0)
+        );
+    }
+
+    #[test]
+    fn converts_local_time() {
+        assert_eq!(
+            local_to_unix(1_000_000, UNIX_MICROS as i64, 0, 0),
+            UNIX_MICROS + 1_000_000
+        );
+
+        // a slow local clock (positive drift) is corrected forward
+        assert_eq!(
+            local_to_unix(3_600_000_000, 1_000, 20, 3_600_000_000),
+            3_600_000_000 + 1_000 + 72_000
+        );
+        assert_eq!(
+            local_to_unix(3_600_000_000, 1_000, -20, 3_600_000_000),
+            3_600_000_000 + 1_000 - 72_000
+        );
+
+        assert_eq!(local_to_unix(1_000, -2_000, 0, 0), 0);
+    }
+
+    #[test]
+    fn estimates_drift() {
+        // the offset grew by 36 ms within an hour
+        let hour = 3_600_000_000;
+        assert_eq!(estimate_drift(1_000_000, 0, 1_036_000, hour), 10);
+        assert_eq!(estimate_drift(1_036_000, 0, 1_000_000, hour), -10);
+
+        // a server time step is no drift
+        assert_eq!(estimate_drift(1_000_000, 7, 3_000_000, hour), 7);
+        assert_eq!(
+            estimate_drift(0, 7, MAX_DRIFT_PPM * 3_600, hour),
+            MAX_DRIFT_PPM
+        );
+        assert_eq!(estimate_drift(0, 7, (MAX_DRIFT_PPM + 1) * 3_600, hour), 7);
+    }
+}
@@ -1,9 +1,12 @@
 use core::fmt::Display;
 
+use alloc::boxed::Box;
 use anyhow::{Error, Result};
 use defmt::{debug, error, info};
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
 use embassy_net::{Runner, Stack, StackResources};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 use embassy_time::{Duration, Timer};
 use esp_hal::{
     peripherals::{RADIO_CLK, TIMG0, WIFI},
@@ -11,11 +14,19 @@ use esp_hal::{
     timer::timg::TimerGroup,
 };
 use esp_wifi::{
+    esp_now::{self, EspNow, EspNowError},
     init,
-    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState},
+    wifi::{Configuration, WifiController, WifiDevice, WifiError, WifiEvent, WifiState},
     EspWifiController, InitializationError,
 };
 
+use crate::connectivity::{self, ConnectivityState, LostReason};
+use crate::espnow::{self, EspNowConfig};
+use crate::power::PowerProfile;
+use crate::scan::{self, ScanResults, SCAN_CAPACITY};
+use crate::sizing::STACK_SOCKETS;
+use crate::wifi_auth::WifiCredentials;
+
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -25,56 +36,97 @@ macro_rules! mk_static {
     }};
 }
 
+/// Requests handled by the connection task, which owns the `WifiController`.
+enum WifiCommand {
+    SetPowerProfile(PowerProfile),
+    Scan,
+}
+
+static WIFI_COMMANDS: Channel<CriticalSectionRawMutex, WifiCommand, 2> = Channel::new();
+
+/// Switch the radio power-save mode at runtime, see [`PowerProfile`].
+pub async fn set_power_profile(profile: PowerProfile) {
+    WIFI_COMMANDS
+        .send(WifiCommand::SetPowerProfile(profile))
+        .await;
+}
+
+/// Start a scan for visible networks, the results are available via [`scan::wait_results`].
+///
+/// Only handled while associated, a request made while disconnected is processed after the
+/// next successful connect.
+pub async fn request_scan() {
+    WIFI_COMMANDS.send(WifiCommand::Scan).await;
+}
+
+/// Settings of the station and the services sharing its radio, see [`create_wifi_stack`].
+#[derive(Debug, defmt::Format, Clone, Copy)]
+pub struct WifiConfig {
+    pub credentials: WifiCredentials,
+    /// Applied after every start of the controller, can be changed with [`set_power_profile`]
+    pub power_profile: PowerProfile,
+    /// Scan for networks right after starting the controller
+    pub scan_on_boot: bool,
+    /// ESP-NOW control link next to the station, `None` to disable it
+    pub espnow: Option<EspNowConfig>,
+}
+
 pub async fn create_wifi_stack(
-    wifi_ssid: &'static str,
-    wifi_psk: &'static str,
+    config: WifiConfig,
     spawner: Spawner,
     timer: TimerGroup<TIMG0>,
     mut rng: Rng,
     radio_clk: RADIO_CLK,
     wifi: WIFI,
 ) -> Result<Stack<'static>> {
+    let credentials = config.credentials;
+    let client_config = credentials.configuration().map_err(Error::msg)?;
+    info!("Wifi auth method: {:?}", credentials.auth_mode);
+
     let esp_wifi_controller: &EspWifiController<'static> = mk_static!(
         EspWifiController<'static>,
         init(timer.timer0, rng, radio_clk).map_err(|e| Error::msg(WrappedInitError(e)))?
     );
 
-    let (controller, interface) = esp_wifi::wifi::new(&esp_wifi_controller, wifi)
+    // ESP-NOW can only be added to a running station with the token taken before
+    let (wifi, espnow_token) = esp_now::enable_esp_now_with_wifi(wifi);
+    let (controller, interfaces) = esp_wifi::wifi::new(&esp_wifi_controller, wifi)
         .map_err(| e | { Error::msg(WrappedWifiError(e)) })?;
-    let wifi_interface = interface.sta;
+    let wifi_interface = interfaces.sta;
 
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    let net_config = embassy_net::Config::dhcpv4(Default::default());
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
     let (stack, runner) = embassy_net::new(
         wifi_interface,
-        config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        net_config,
+        mk_static!(StackResources<STACK_SOCKETS>, StackResources::<STACK_SOCKETS>::new()),
         seed,
     );
 
-    spawner.spawn(connection(controller, wifi_ssid, wifi_psk))?;
+    spawner.spawn(connection(
+        controller,
+        credentials.ssid,
+        client_config,
+        config.power_profile,
+        config.scan_on_boot,
+    ))?;
     spawner.spawn(net_task(runner))?;
+    if let Some(espnow_config) = config.espnow {
+        let esp_now = EspNow::new_with_wifi(esp_wifi_controller, espnow_token)
+            .map_err(|e| Error::msg(WrappedEspNowError(e)))?;
+        spawner.spawn(espnow::espnow_task(esp_now, espnow_config, rng))?;
+    }
+    spawner.spawn(connectivity::ip_task(stack))?;
+    #[cfg(feature = "ipv6")]
+    spawner.spawn(crate::slaac::slaac_task(stack))?;
 
     debug!("Wifi stack initialized");
 
-    debug!("Waiting for Link Up ...");
-    // wait until wifi is connected
-    loop {
-        if stack.is_link_up() {
-            break;
-        }
-        Timer::after(Duration::from_millis(100)).await;
-    }
-    debug!("Link Up!");
-
     info!("Waiting for IP address ...");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            info!("Got IP address: {}", config.address);
-            break;
-        }
-        Timer::after(Duration::from_millis(100)).await;
+    match connectivity::wait_online().await {
+        Some(address) => info!("Got IP address: {}", address),
+        None => return Err(Error::msg("No connectivity receiver available")),
     }
 
     Ok(stack)
@@ -84,7 +136,9 @@ pub async fn create_wifi_stack(
 async fn connection(
     mut controller: WifiController<'static>,
     ssid: &'static str,
-    psk: &'static str,
+    client_config: Configuration,
+    mut power_profile: PowerProfile,
+    scan_on_boot: bool,
 ) {
     info!("Connection Task started");
 
@@ -103,32 +157,59 @@ async fn connection(
     loop {
         match esp_wifi::wifi::wifi_state() {
             WifiState::StaConnected => {
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
+                loop {
+                    match select(
+                        controller.wait_for_event(WifiEvent::StaDisconnected),
+                        WIFI_COMMANDS.receive(),
+                    )
+                    .await
+                    {
+                        Either::First(_) => break,
+                        Either::Second(WifiCommand::SetPowerProfile(profile)) => {
+                            power_profile = profile;
+                            apply_power_profile(&mut controller, power_profile);
+                        }
+                        Either::Second(WifiCommand::Scan) => {
+                            if let Some(results) = scan_networks(&mut controller).await {
+                                scan::SCAN_DONE.signal(results);
+                            }
+                        }
+                    }
+                }
+                connectivity::publish(ConnectivityState::Lost(LostReason::LinkDown));
                 info!("Disconnected from AP, waiting for 5 seconds ...");
                 Timer::after(Duration::from_secs(5)).await;
+                connectivity::publish(ConnectivityState::Disconnected);
             }
             _ => {}
         }
 
         if !matches!(controller.is_started(), Ok(true)) {
-            info!("Trying to connect to {:?} with password {:?}", ssid, psk);
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: ssid.try_into().unwrap(),
-                password: psk.try_into().unwrap(),
-                ..Default::default()
-            });
+            info!("Trying to connect to {:?}", ssid);
             controller.set_configuration(&client_config).unwrap();
             info!("Starting WiFi controller...");
             controller.start_async().await.unwrap();
             info!("Wifi controller started");
+            apply_power_profile(&mut controller, power_profile);
+
+            if scan_on_boot {
+                if let Some(results) = scan_networks(&mut controller).await {
+                    scan::SCAN_DONE.signal(results);
+                }
+            }
         }
 
         info!("Connecting to AP...");
+        connectivity::publish(ConnectivityState::Associating);
 
         match controller.connect_async().await {
-            Ok(_) => info!("Wifi connected"),
+            Ok(_) => {
+                info!("Wifi connected");
+                connectivity::publish(ConnectivityState::LinkUp);
+            }
             Err(e) => {
                 error!("Failed to connect to AP: {:?}", e);
+                connectivity::publish(ConnectivityState::Lost(LostReason::ConnectFailed));
                 Timer::after(Duration::from_secs(5)).await;
                 info!("Retrying connection...");
             }
@@ -136,6 +217,45 @@ async fn connection(
     }
 }
 
+fn apply_power_profile(controller: &mut WifiController<'static>, profile: PowerProfile) {
+    match controller.set_power_saving(profile.power_save_mode()) {
+        Ok(()) => info!("Power profile set to {:?}", profile),
+        Err(e) => error!("Failed to set power profile {:?}: {:?}", profile, e),
+    }
+}
+
+async fn scan_networks(controller: &mut WifiController<'static>) -> Option<ScanResults> {
+    info!("Scanning for networks...");
+
+    // the records of all access points would not fit into the executor arena, the scan runs
+    // on the heap and only the strongest networks are kept
+    let scan = Box::pin(async {
+        let (mut access_points, total) = controller.scan_n_async::<SCAN_CAPACITY>().await?;
+        Ok::<_, WifiError>((scan::strongest(&mut access_points), total))
+    });
+
+    match scan.await {
+        Ok((results, total)) => {
+            info!("Found {} networks, keeping {}", total, results.len());
+            for network in results.iter() {
+                debug!(
+                    "{} ch {} rssi {} auth {:?}",
+                    network.ssid.as_str(),
+                    network.channel,
+                    network.rssi,
+                    network.auth_method
+                );
+            }
+
+            Some(results)
+        }
+        Err(e) => {
+            error!("Failed to scan for networks: {:?}", e);
+            None
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     debug!("Net task started");
@@ -161,3 +281,12 @@ impl Display for WrappedWifiError {
         write!(f, "Wifi error: {:?}", self.0)
     }
 }
+
+#[derive(Debug, defmt::Format)]
+pub struct WrappedEspNowError(EspNowError);
+
+impl Display for WrappedEspNowError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ESP-NOW error: {:?}", self.0)
+    }
+}
@@ -0,0 +1,291 @@
+use core::{fmt::Display, str::FromStr};
+
+use defmt::{warn, Format};
+use esp_wifi::wifi::{AuthMethod, ClientConfiguration, Configuration, EapClientConfiguration};
+
+use crate::secret::Secret;
+
+/// Length of a raw PSK, 32 bytes as hex digits, used as is instead of a passphrase
+const RAW_PSK_LEN: usize = 64;
+
+/// Authentication modes supported for the station interface.
+///
+/// The names accepted by [`WifiAuthMode::from_str`] are the ones used for `wifi_auth_method`
+/// in `cfg.toml`.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum WifiAuthMode {
+    /// `open`
+    Open,
+    /// `wpa2-personal` (default)
+    Wpa2Personal,
+    /// `wpa3-personal`, forces SAE
+    Wpa3Personal,
+    /// `wpa2-wpa3-personal`, transition mode
+    Wpa2Wpa3Personal,
+    /// `wpa2-enterprise`, PEAP/MSCHAPv2 (or EAP-TTLS) with username and password
+    Wpa2Enterprise,
+}
+
+impl WifiAuthMode {
+    fn auth_method(&self) -> AuthMethod {
+        match self {
+            WifiAuthMode::Open => AuthMethod::None,
+            WifiAuthMode::Wpa2Personal => AuthMethod::WPA2Personal,
+            WifiAuthMode::Wpa3Personal => AuthMethod::WPA3Personal,
+            WifiAuthMode::Wpa2Wpa3Personal => AuthMethod::WPA2WPA3Personal,
+            WifiAuthMode::Wpa2Enterprise => AuthMethod::WPA2Enterprise,
+        }
+    }
+
+    fn is_personal(&self) -> bool {
+        matches!(
+            self,
+            WifiAuthMode::Wpa2Personal | WifiAuthMode::Wpa3Personal | WifiAuthMode::Wpa2Wpa3Personal
+        )
+    }
+}
+
+impl FromStr for WifiAuthMode {
+    type Err = WifiConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(WifiAuthMode::Open),
+            "" | "wpa2-personal" => Ok(WifiAuthMode::Wpa2Personal),
+            "wpa3-personal" => Ok(WifiAuthMode::Wpa3Personal),
+            "wpa2-wpa3-personal" => Ok(WifiAuthMode::Wpa2Wpa3Personal),
+            "wpa2-enterprise" => Ok(WifiAuthMode::Wpa2Enterprise),
+            _ => Err(WifiConfigError::UnknownAuthMethod),
+        }
+    }
+}
+
+/// Credentials for WPA2-Enterprise networks.
+///
+/// Empty strings are treated as "not set", which matches the defaults of the `toml_cfg` config.
+#[derive(Debug, Format, Clone, Copy)]
+pub struct EapCredentials {
+    /// Outer (anonymous) identity, falls back to `username` if empty
+    pub identity: &'static str,
+    pub username: &'static str,
+    pub password: Secret<&'static str>,
+    /// PEM encoded CA certificate used to validate the RADIUS server.
+    /// Has to be NUL-terminated as it is handed to the ESP-IDF EAP client as is.
+    pub ca_cert: Option<&'static [u8]>,
+}
+
+impl EapCredentials {
+    fn is_empty(&self) -> bool {
+        self.identity.is_empty()
+            && self.username.is_empty()
+            && self.password.expose().is_empty()
+            && self.ca_cert.is_none()
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy)]
+pub struct WifiCredentials {
+    pub ssid: &'static str,
+    pub auth_mode: WifiAuthMode,
+    /// Pre-shared key for the personal modes, has to be empty otherwise
+    pub psk: Secret<&'static str>,
+    pub eap: EapCredentials,
+}
+
+impl WifiCredentials {
+    /// Check that the configured fields fit the selected auth mode.
+    pub fn validate(&self) -> Result<(), WifiConfigError> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err(WifiConfigError::InvalidSsid);
+        }
+
+        let psk = *self.psk.expose();
+
+        if self.auth_mode.is_personal() {
+            if psk.is_empty() {
+                return Err(WifiConfigError::PskMissing);
+            }
+            if is_raw_psk(psk) {
+                // SAE derives its keys from the passphrase itself
+                if self.auth_mode != WifiAuthMode::Wpa2Personal {
+                    return Err(WifiConfigError::RawPskNotAllowed);
+                }
+            } else if psk.len() < 8 || psk.len() > 63 {
+                return Err(WifiConfigError::InvalidPskLength);
+            }
+        } else if !psk.is_empty() {
+            return Err(WifiConfigError::PskNotAllowed);
+        }
+
+        match self.auth_mode {
+            WifiAuthMode::Wpa2Enterprise => {
+                if self.eap.username.is_empty() || self.eap.password.expose().is_empty() {
+                    return Err(WifiConfigError::EapCredentialsMissing);
+                }
+                if self.eap.identity.len() > 128 || self.eap.username.len() > 128 {
+                    return Err(WifiConfigError::EapFieldTooLong);
+                }
+                if self.eap.password.expose().len() > 64 {
+                    return Err(WifiConfigError::EapFieldTooLong);
+                }
+                if matches!(self.eap.ca_cert, Some(cert) if cert.last() != Some(&0)) {
+                    return Err(WifiConfigError::CaCertNotTerminated);
+                }
+            }
+            _ => {
+                if !self.eap.is_empty() {
+                    return Err(WifiConfigError::EapNotAllowed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the esp-wifi configuration for the station interface.
+    pub fn configuration(&self) -> Result<Configuration, WifiConfigError> {
+        self.validate()?;
+
+        let ssid = self
+            .ssid
+            .try_into()
+            .map_err(|_| WifiConfigError::InvalidSsid)?;
+
+        if self.auth_mode != WifiAuthMode::Wpa2Enterprise {
+            return Ok(Configuration::Client(ClientConfiguration {
+                ssid,
+                auth_method: self.auth_mode.auth_method(),
+                password: (*self.psk.expose())
+                    .try_into()
+                    .map_err(|_| WifiConfigError::InvalidPskLength)?,
+                ..Default::default()
+            }));
+        }
+
+        if self.eap.ca_cert.is_none() {
+            warn!("No CA certificate configured, the RADIUS server will not be validated");
+        }
+
+        let identity = if self.eap.identity.is_empty() {
+            self.eap.username
+        } else {
+            self.eap.identity
+        };
+
+        Ok(Configuration::EapClient(EapClientConfiguration {
+            ssid,
+            auth_method: self.auth_mode.auth_method(),
+            identity: Some(
+                identity
+                    .try_into()
+                    .map_err(|_| WifiConfigError::EapFieldTooLong)?,
+            ),
+            username: Some(
+                self.eap
+                    .username
+                    .try_into()
+                    .map_err(|_| WifiConfigError::EapFieldTooLong)?,
+            ),
+            password: Some(
+                (*self.eap.password.expose())
+                    .try_into()
+                    .map_err(|_| WifiConfigError::EapFieldTooLong)?,
+            ),
+            ca_cert: self.eap.ca_cert,
+            ..Default::default()
+        }))
+    }
+}
+
+/// ESP-IDF takes a 64 character password as the PSK in hex instead of a passphrase.
+fn is_raw_psk(psk: &str) -> bool {
+    psk.len() == RAW_PSK_LEN && psk.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum WifiConfigError {
+    UnknownAuthMethod,
+    InvalidSsid,
+    PskMissing,
+    InvalidPskLength,
+    RawPskNotAllowed,
+    PskNotAllowed,
+    EapCredentialsMissing,
+    EapFieldTooLong,
+    EapNotAllowed,
+    CaCertNotTerminated,
+}
+
+impl Display for WifiConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            WifiConfigError::UnknownAuthMethod => "unknown wifi_auth_method, expected one of open, wpa2-personal, wpa3-personal, wpa2-wpa3-personal, wpa2-enterprise",
+            WifiConfigError::InvalidSsid => "SSID must be between 1 and 32 bytes",
+            WifiConfigError::PskMissing => "the selected auth method requires a PSK",
+            WifiConfigError::InvalidPskLength => "PSK must be a passphrase of 8 to 63 characters or 64 hex digits",
+            WifiConfigError::RawPskNotAllowed => "a PSK of 64 hex digits can only be used with wpa2-personal, SAE needs the passphrase",
+            WifiConfigError::PskNotAllowed => "a PSK can only be used with the personal auth methods",
+            WifiConfigError::EapCredentialsMissing => "WPA2-Enterprise requires an EAP username and password",
+            WifiConfigError::EapFieldTooLong => "EAP identity/username must be at most 128 and password at most 64 bytes",
+            WifiConfigError::EapNotAllowed => "EAP identity, credentials and CA certificate can only be used with wpa2-enterprise",
+            WifiConfigError::CaCertNotTerminated => "CA certificate must be NUL-terminated PEM",
+        };
+
+        write!(f, "Wifi config error: {}", msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAW_PSK: &str = "0123456789abcdef0123456789ABCDEF0123456789abcdef0123456789abcdef";
+
+    fn personal(auth_mode: WifiAuthMode, psk: &'static str) -> WifiCredentials {
+        WifiCredentials {
+            ssid: "ssid",
+            auth_mode,
+            psk: Secret::new(psk),
+            eap: EapCredentials {
+                identity: "",
+                username: "",
+                password: Secret::new(""),
+                ca_cert: None,
+            },
+        }
+    }
+
+    #[test]
+    fn accepts_passphrases() {
+        assert_eq!(
+            personal(WifiAuthMode::Wpa2Personal, "hunter22").validate(),
+            Ok(())
+        );
+        assert_eq!(
+            personal(WifiAuthMode::Wpa3Personal, "hunter2").validate(),
+            Err(WifiConfigError::InvalidPskLength)
+        );
+        // 64 characters, but not all of them hex digits
+        let long = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdeg";
+        assert_eq!(
+            personal(WifiAuthMode::Wpa2Personal, long).validate(),
+            Err(WifiConfigError::InvalidPskLength)
+        );
+    }
+
+    #[test]
+    fn accepts_raw_psk_for_wpa2_only() {
+        assert_eq!(
+            personal(WifiAuthMode::Wpa2Personal, RAW_PSK).validate(),
+            Ok(())
+        );
+        assert_eq!(
+            personal(WifiAuthMode::Wpa3Personal, RAW_PSK).validate(),
+            Err(WifiConfigError::RawPskNotAllowed)
+        );
+        assert_eq!(
+            personal(WifiAuthMode::Wpa2Wpa3Personal, RAW_PSK).validate(),
+            Err(WifiConfigError::RawPskNotAllowed)
+        );
+    }
+}
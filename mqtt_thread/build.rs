@@ -1,25 +1,75 @@
 
+// no_std dataset codec shared with the firmware
+#[allow(dead_code)]
+#[path = "src/dataset.rs"]
+mod dataset;
+
 #[toml_cfg::toml_config]
 pub struct Config {
     #[default("")]
     thread_dataset: &'static str,
     #[default("")]
-    mqtt_ip: &'static str,
+    thread_pskd: &'static str,
+    #[default("")]
+    mqtt_fqdn: &'static str,
     #[default(1883)]
     mqtt_port: u16,
     #[default("")]
     mqtt_username: &'static str,
     #[default("")]
     mqtt_password: &'static str,
+    #[default("")]
+    nat64_prefix: &'static str,
+    #[default("")]
+    dns_server: &'static str,
+    #[default("mqtt-thread")]
+    hostname: &'static str,
+    #[default("")]
+    ieee_eui64: &'static str,
+    #[default("mtd")]
+    thread_device_type: &'static str,
+    #[default(0)]
+    sed_poll_period_ms: u32,
+    #[default(0)]
+    sed_csl_period_ms: u32,
+    #[default(5)]
+    publish_interval_s: u32,
+    #[default(60)]
+    diagnostics_interval_s: u32,
+    #[default("")]
+    coap_endpoint: &'static str,
+    #[default(5683)]
+    coap_port: u16,
+    #[default("telemetry")]
+    coap_telemetry_path: &'static str,
+    #[default("diagnostics")]
+    coap_diagnostics_path: &'static str,
+    #[default("commands")]
+    coap_command_path: &'static str,
 }
 
 fn main() {
+    validate_dataset();
     linker_be_nice();
     println!("cargo:rustc-link-arg=-Tdefmt.x");
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Fail the build instead of `set_active_dataset_tlv_hexstr` at runtime.
+fn validate_dataset() {
+    if CONFIG.thread_dataset.is_empty() {
+        // commissioned with thread_pskd
+        return;
+    }
+
+    if let Err(e) = dataset::Dataset::from_hex(CONFIG.thread_dataset)
+        .and_then(|dataset| dataset.validate_active())
+    {
+        panic!("Invalid thread_dataset in cfg.toml: {e}");
+    }
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
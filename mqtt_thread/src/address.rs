@@ -0,0 +1,281 @@
+//! Classification of the IPv6 addresses of a Thread interface and source address selection.
+//!
+//! A Thread device has several unicast addresses at the same time:
+//!
+//! - a link-local address (`fe80::/64`), only valid for one radio hop
+//! - the RLOC (`<mesh-local prefix>::ff:fe00:<rloc16>`), changes with the topology
+//! - ALOCs (`<mesh-local prefix>::ff:fe00:fcxx`) of services like the leader
+//! - the mesh-local EID, stable but only reachable inside the Thread network
+//! - OMR (off-mesh routable) addresses from prefixes advertised by border routers, usually ULA,
+//!   or global addresses if a border router advertises a global prefix
+
+use core::net::Ipv6Addr;
+
+use defmt::Format;
+
+/// IID of RLOCs and ALOCs is `0000:00ff:fe00:xxxx`
+const LOCATOR_IID_PREFIX: [u16; 3] = [0x0000, 0x00ff, 0xfe00];
+/// Locators with a 16 bit part of `0xfc00` or above are ALOCs
+const ALOC16_MIN: u16 = 0xfc00;
+/// ALOC16 of the leader
+const ALOC16_LEADER: u16 = 0xfc00;
+
+/// Unicast addresses of a Thread interface: link-local, RLOC, ML-EID, a few ALOCs and OMR
+/// addresses of multiple border routers
+pub const MAX_ADDRESSES: usize = 8;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddressKind {
+    LinkLocal,
+    Aloc,
+    Rloc,
+    MeshLocalEid,
+    /// Off-mesh routable address from a ULA prefix of a border router
+    Omr,
+    /// Off-mesh routable address from a global prefix
+    Global,
+}
+
+impl AddressKind {
+    /// Whether the address can be used to reach hosts outside of the Thread network.
+    pub fn is_off_mesh_routable(&self) -> bool {
+        matches!(self, AddressKind::Omr | AddressKind::Global)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct SourceAddress {
+    pub address: Ipv6Addr,
+    pub prefix_len: u8,
+    pub kind: AddressKind,
+    /// Default route to hand all off-mesh traffic to OpenThread, see [`default_gateway`]
+    pub gateway: Option<Ipv6Addr>,
+}
+
+/// Mesh-local prefix, recognized by the RLOC which every attached device has.
+pub fn mesh_local_prefix(addresses: &[(Ipv6Addr, u8)]) -> Option<[u16; 4]> {
+    addresses
+        .iter()
+        .find(|(address, _)| is_locator(address) && !is_link_local(address))
+        .map(|(address, _)| prefix64(address))
+}
+
+/// RLOC16 of the device, the router ID of the parent is in its upper 6 bits if the device is a
+/// child.
+pub fn rloc16(addresses: &[(Ipv6Addr, u8)]) -> Option<u16> {
+    let mesh_local_prefix = mesh_local_prefix(addresses);
+
+    addresses
+        .iter()
+        .find(|(address, _)| classify(address, mesh_local_prefix) == Some(AddressKind::Rloc))
+        .map(|(address, _)| address.segments()[7])
+}
+
+/// Classify `address` given the mesh-local prefix of the interface. `None` for multicast and
+/// other addresses that are never used as source.
+pub fn classify(address: &Ipv6Addr, mesh_local_prefix: Option<[u16; 4]>) -> Option<AddressKind> {
+    if address.is_multicast() || address.is_unspecified() || address.is_loopback() {
+        return None;
+    }
+    if is_link_local(address) {
+        return Some(AddressKind::LinkLocal);
+    }
+
+    if Some(prefix64(address)) == mesh_local_prefix {
+        let kind = if !is_locator(address) {
+            AddressKind::MeshLocalEid
+        } else if address.segments()[7] >= ALOC16_MIN {
+            AddressKind::Aloc
+        } else {
+            AddressKind::Rloc
+        };
+        return Some(kind);
+    }
+
+    if is_unique_local(address) {
+        Some(AddressKind::Omr)
+    } else {
+        Some(AddressKind::Global)
+    }
+}
+
+/// Select the source address for the embassy-net interface.
+///
+/// Prefers global over OMR addresses, falls back to the mesh-local EID which only reaches
+/// hosts inside the Thread network. RLOCs, ALOCs and link-local addresses are never selected.
+/// Among addresses of the same kind the first one reported by OpenThread wins.
+pub fn select_source(addresses: &[(Ipv6Addr, u8)]) -> Option<SourceAddress> {
+    let mesh_local_prefix = mesh_local_prefix(addresses);
+
+    let (address, prefix_len, kind) = addresses
+        .iter()
+        .filter_map(|(address, prefix_len)| {
+            let kind = classify(address, mesh_local_prefix)?;
+            matches!(
+                kind,
+                AddressKind::MeshLocalEid | AddressKind::Omr | AddressKind::Global
+            )
+            .then_some((*address, *prefix_len, kind))
+        })
+        .rev()
+        .max_by_key(|(_, _, kind)| *kind)?;
+
+    Some(SourceAddress {
+        address,
+        prefix_len,
+        kind,
+        gateway: default_gateway(mesh_local_prefix),
+    })
+}
+
+/// Default route for off-mesh destinations such as NAT64 addresses.
+///
+/// The Thread interface hands every packet to OpenThread which routes it through a border
+/// router, the next hop is never resolved. The leader ALOC is used as it is always reachable
+/// inside the mesh.
+pub fn default_gateway(mesh_local_prefix: Option<[u16; 4]>) -> Option<Ipv6Addr> {
+    let [a, b, c, d] = mesh_local_prefix?;
+    let [e, f, g] = LOCATOR_IID_PREFIX;
+    Some(Ipv6Addr::new(a, b, c, d, e, f, g, ALOC16_LEADER))
+}
+
+fn prefix64(address: &Ipv6Addr) -> [u16; 4] {
+    let segments = address.segments();
+    [segments[0], segments[1], segments[2], segments[3]]
+}
+
+fn is_locator(address: &Ipv6Addr) -> bool {
+    address.segments()[4..7] == LOCATOR_IID_PREFIX
+}
+
+fn is_link_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+fn is_unique_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0xfe00 == 0xfc00
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK_LOCAL: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1c4a, 0x2b77, 0x51d0, 0x8e3f),
+        64,
+    );
+    const RLOC: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0, 0, 0xff, 0xfe00, 0x8001),
+        64,
+    );
+    const LEADER_ALOC: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0, 0, 0xff, 0xfe00, 0xfc00),
+        64,
+    );
+    const ML_EID: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0, 0x6f2e, 0x91a4, 0x0c1b, 0x7d55),
+        64,
+    );
+    const OMR: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0xfd42, 0x4696, 0xc9c, 1, 0x3b1a, 0x5e20, 0xa7c4, 0x19f2),
+        64,
+    );
+    const GLOBAL: (Ipv6Addr, u8) = (
+        Ipv6Addr::new(0x2001, 0xdb8, 0x1234, 1, 0x3b1a, 0x5e20, 0xa7c4, 0x19f2),
+        64,
+    );
+    const MESH_LOCAL_PREFIX: [u16; 4] = [0xfdb4, 0x4e7f, 0x4e8d, 0];
+
+    #[test]
+    fn classifies_thread_addresses() {
+        let prefix = Some(MESH_LOCAL_PREFIX);
+        assert_eq!(
+            classify(&LINK_LOCAL.0, prefix),
+            Some(AddressKind::LinkLocal)
+        );
+        assert_eq!(classify(&RLOC.0, prefix), Some(AddressKind::Rloc));
+        assert_eq!(classify(&LEADER_ALOC.0, prefix), Some(AddressKind::Aloc));
+        assert_eq!(classify(&ML_EID.0, prefix), Some(AddressKind::MeshLocalEid));
+        assert_eq!(classify(&OMR.0, prefix), Some(AddressKind::Omr));
+        assert_eq!(classify(&GLOBAL.0, prefix), Some(AddressKind::Global));
+        assert_eq!(
+            classify(&Ipv6Addr::new(0xff03, 0, 0, 0, 0, 0, 0, 1), prefix),
+            None
+        );
+    }
+
+    #[test]
+    fn finds_mesh_local_prefix_from_rloc() {
+        assert_eq!(
+            mesh_local_prefix(&[LINK_LOCAL, OMR, ML_EID, RLOC]),
+            Some(MESH_LOCAL_PREFIX)
+        );
+        assert_eq!(mesh_local_prefix(&[LINK_LOCAL, OMR]), None);
+    }
+
+    #[test]
+    fn child_without_border_router_uses_ml_eid() {
+        let selected = select_source(&[LINK_LOCAL, RLOC, ML_EID]).unwrap();
+        assert_eq!(selected.address, ML_EID.0);
+        assert_eq!(selected.kind, AddressKind::MeshLocalEid);
+        assert!(!selected.kind.is_off_mesh_routable());
+    }
+
+    #[test]
+    fn prefers_omr_address() {
+        // order as reported by OpenThread on a child behind a border router
+        let selected = select_source(&[OMR, ML_EID, RLOC, LINK_LOCAL]).unwrap();
+        assert_eq!(selected.address, OMR.0);
+        assert_eq!(selected.prefix_len, 64);
+        assert_eq!(selected.kind, AddressKind::Omr);
+
+        let selected = select_source(&[LINK_LOCAL, RLOC, ML_EID, OMR]).unwrap();
+        assert_eq!(selected.address, OMR.0);
+    }
+
+    #[test]
+    fn prefers_global_over_omr() {
+        let selected = select_source(&[OMR, GLOBAL, ML_EID, RLOC, LINK_LOCAL]).unwrap();
+        assert_eq!(selected.address, GLOBAL.0);
+        assert_eq!(selected.kind, AddressKind::Global);
+    }
+
+    #[test]
+    fn first_address_of_a_kind_wins() {
+        let second_omr = (
+            Ipv6Addr::new(0xfd42, 0x4696, 0xc9c, 2, 0x3b1a, 0x5e20, 0xa7c4, 0x19f2),
+            64,
+        );
+        let selected = select_source(&[second_omr, OMR, ML_EID, RLOC]).unwrap();
+        assert_eq!(selected.address, second_omr.0);
+    }
+
+    #[test]
+    fn leader_never_selects_locators() {
+        let selected = select_source(&[LEADER_ALOC, RLOC, LINK_LOCAL, ML_EID]).unwrap();
+        assert_eq!(selected.address, ML_EID.0);
+    }
+
+    #[test]
+    fn nothing_usable_before_attaching() {
+        assert_eq!(select_source(&[LINK_LOCAL]), None);
+        assert_eq!(select_source(&[]), None);
+    }
+
+    #[test]
+    fn rloc16_ignores_alocs() {
+        assert_eq!(
+            rloc16(&[LEADER_ALOC, ML_EID, RLOC, LINK_LOCAL]),
+            Some(0x8001)
+        );
+        assert_eq!(rloc16(&[LINK_LOCAL, OMR]), None);
+    }
+
+    #[test]
+    fn gateway_is_leader_aloc() {
+        let selected = select_source(&[OMR, ML_EID, RLOC, LINK_LOCAL]).unwrap();
+        assert_eq!(selected.gateway, Some(LEADER_ALOC.0));
+        // without an RLOC the mesh-local prefix is unknown
+        assert_eq!(select_source(&[OMR]).unwrap().gateway, None);
+    }
+}
@@ -1,17 +1,18 @@
 #![no_std]
 #![no_main]
 
-use core::net::{Ipv4Addr, Ipv6Addr};
+use core::cell::RefCell;
+use core::net::Ipv6Addr;
 
 use core::fmt::Write;
-use defmt::{error, info};
+use defmt::{error, info, warn};
 use embassy_executor::Spawner;
-use embassy_net::{
-    tcp::TcpSocket,
-    ConfigV6, Ipv6Cidr, Runner, StackResources, StaticConfigV6,
-};
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select3, select4, Either3, Either4};
+use embassy_net::{tcp::TcpSocket, Runner, StackResources};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::rng::Rng;
 use esp_hal::timer::systimer::SystemTimer;
 
@@ -21,9 +22,28 @@ fn panic(_: &core::panic::PanicInfo) -> ! {
 }
 
 use esp_ieee802154::Ieee802154;
-use heapless::{String, Vec};
+use esp_storage::FlashStorage;
+use heapless::String;
+use mqtt_thread::coap_client::{self, CoapConfig, Resource};
+use mqtt_thread::connectivity;
+use mqtt_thread::dataset::Dataset;
+use mqtt_thread::device_type::{self, DeviceType};
+use mqtt_thread::diagnostics::{self, DUMP_COMMAND};
+use mqtt_thread::identity::Identity;
+use mqtt_thread::joiner::{self, Provisioning};
+use mqtt_thread::mqtt_transport::{wait_read_ready, Transport};
+use mqtt_thread::nat64::Nat64Prefix;
+use mqtt_thread::nat64_discovery;
+use mqtt_thread::resolver;
+use mqtt_thread::settings_store::{self, SettingsStore, PARTITION_OFFSET};
+use mqtt_thread::sizing::{
+    self, IPV6_PACKET_SIZE, MQTT_BUFFER_SIZE, STACK_SOCKETS, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE,
+};
+use mqtt_thread::sleepy::{self, DutyCycle, SleepyConfig};
+use mqtt_thread::srp::{self, ServiceRegistration, SRP_BUFFER_SIZE, SRP_SERVICES};
+use mqtt_thread::supervisor;
 use openthread::{
-    enet::{self, EnetDriver, EnetDriverState, EnetRunner}, esp::EspRadio, DeviceRole, OpenThread, OtResources, OtRngCore, SimpleRamSettings
+    enet::{self, EnetDriver, EnetDriverState, EnetRunner}, esp::EspRadio, OpenThread, OtResources, OtRngCore, OtSrpResources
 };
 use rust_mqtt::{
     client::{client::MqttClient, client_config::ClientConfig},
@@ -38,15 +58,59 @@ pub struct Config {
     #[default("")]
     thread_dataset: &'static str,
     #[default("")]
-    mqtt_ip: &'static str,
+    thread_pskd: &'static str,
+    #[default("")]
+    mqtt_fqdn: &'static str,
     #[default(1883)]
     mqtt_port: u16,
     #[default("")]
     mqtt_username: &'static str,
     #[default("")]
     mqtt_password: &'static str,
+    #[default("")]
+    nat64_prefix: &'static str,
+    #[default("")]
+    dns_server: &'static str,
+    #[default("mqtt-thread")]
+    hostname: &'static str,
+    #[default("")]
+    ieee_eui64: &'static str,
+    #[default("mtd")]
+    thread_device_type: &'static str,
+    #[default(0)]
+    sed_poll_period_ms: u32,
+    #[default(0)]
+    sed_csl_period_ms: u32,
+    #[default(5)]
+    publish_interval_s: u32,
+    #[default(60)]
+    diagnostics_interval_s: u32,
+    #[default("")]
+    coap_endpoint: &'static str,
+    #[default(5683)]
+    coap_port: u16,
+    #[default("telemetry")]
+    coap_telemetry_path: &'static str,
+    #[default("diagnostics")]
+    coap_diagnostics_path: &'static str,
+    #[default("commands")]
+    coap_command_path: &'static str,
 }
 
+mqtt_thread::secrets!(thread_dataset, thread_pskd, mqtt_password);
+
+/// DNS-SD service type registered via SRP
+const SERVICE_TYPE: &str = "_mqtt-device._tcp";
+/// Topic the diagnostics are published on
+const DIAGNOSTICS_TOPIC: &str = "telemetry/diagnostics";
+/// Any message on this topic publishes the diagnostics right away
+const DIAGNOSTICS_REQUEST_TOPIC: &str = "telemetry/diagnostics/get";
+/// How long a factory reset waits for the network to remove the SRP registration
+const FACTORY_RESET_ATTACH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set by the BOOT button, the publishing loop sends the diagnostics right away
+static DUMP_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 macro_rules! mk_static {
     ($t:ty) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -62,9 +126,6 @@ macro_rules! mk_static {
     }};
 }
 
-const IPV6_PACKET_SIZE: usize = 1280;
-const ENET_MAX_SOCKETS: usize = 2;
-
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     // generator version: 0.3.1
@@ -73,54 +134,139 @@ async fn main(spawner: Spawner) {
 
     info!("Starting...");
 
-    let nat64_prefix = Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0x2, 0, 0, 0, 0);
-    let net64_prefix_length = 96;
-
-    // Prefixes:
-    // fd42:4696:c9c:1::/64 paos low a800
-    // Routes:
-    // fdb4:4e7f:4e8d:2:0:0::/96 
-    // fc00::/7 s med a800
-
-    // Goal: fd42:4696:c9c:2:0:0:c0a8:1e4 for 192.168.1.228
-    // Got:  fd42:1258:0c9c:0002:0000:0000:c0a8:01e4
-    // let prefix = Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0x2, 0, 0, 0, 0);
-    // let prefix_len = 96;
-    // let prefix = Ipv6Addr::new(0xfd42, 4696, 0xc9c, 1, 0, 0, 0, 0);
-    // let prefix_len = 64;
-    // let ipv4 = Ipv4Addr::new(192, 168, 1, 228);
-
-    // let net64_ipv6 = synthesize_nat64(prefix, prefix_len, ipv4);
-
-    // info!("Synthesized IPv6 address: {:?}", net64_ipv6);
-
     let app_config = CONFIG;
+    let Some(provisioning) =
+        Provisioning::from_config(SECRETS.thread_dataset, SECRETS.thread_pskd)
+    else {
+        error!("Neither thread_dataset nor thread_pskd is configured");
+        return;
+    };
+
+    let configured_nat64_prefix = if app_config.nat64_prefix.is_empty() {
+        None
+    } else {
+        match app_config.nat64_prefix.parse::<Nat64Prefix>() {
+            Ok(prefix) => Some(prefix),
+            Err(e) => {
+                error!("Invalid NAT64 prefix: {:?}", e);
+                return;
+            }
+        }
+    };
+    let dns_server = if app_config.dns_server.is_empty() {
+        None
+    } else {
+        match app_config.dns_server.parse::<Ipv6Addr>() {
+            Ok(address) => Some(address),
+            Err(_) => {
+                error!("Invalid DNS server: {}", app_config.dns_server);
+                return;
+            }
+        }
+    };
+
+    let sleepy_config =
+        SleepyConfig::from_config(app_config.sed_poll_period_ms, app_config.sed_csl_period_ms);
+    let device_type = match DeviceType::from_config(app_config.thread_device_type) {
+        Ok(device_type) => device_type,
+        Err(e) => {
+            error!("Invalid thread_device_type: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = device_type.validate(sleepy_config.is_some()) {
+        error!("Unsupported thread_device_type: {:?}", e);
+        return;
+    }
+    let publish_interval = Duration::from_secs(app_config.publish_interval_s as u64);
+    let diagnostics_interval = (app_config.diagnostics_interval_s > 0)
+        .then(|| Duration::from_secs(app_config.diagnostics_interval_s as u64));
+    let coap_config = CoapConfig::from_config(
+        app_config.coap_endpoint,
+        app_config.coap_port,
+        app_config.coap_telemetry_path,
+        app_config.coap_diagnostics_path,
+        app_config.coap_command_path,
+    );
 
-    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    // a sleepy end device is idle most of the time, a lower clock saves power while awake
+    let cpu_clock = if sleepy_config.is_some() {
+        CpuClock::_80MHz
+    } else {
+        CpuClock::max()
+    };
+    let config = esp_hal::Config::default().with_cpu_clock(cpu_clock);
     let peripherals = esp_hal::init(config);
 
-    esp_alloc::heap_allocator!(size: 72 * 1024);
+    esp_alloc::heap_allocator!(size: sizing::HEAP_SIZE);
 
     let timer0 = SystemTimer::new(peripherals.SYSTIMER);
     esp_hal_embassy::init(timer0.alarm0);
 
     info!("Embassy initialized!");
 
+    sizing::log_budget();
+
     let rng = mk_static!(Rng, Rng::new(peripherals.RNG));
 
     let enet_seed = rng.next_u64();
+    // message IDs and tokens must not repeat across reboots
+    let coap_seed = rng.random();
+
+    let ieee_eui64 = match Identity::load(app_config.ieee_eui64) {
+        Ok(identity) => identity.eui64.0,
+        Err(e) => {
+            error!("Invalid ieee_eui64: {:?}", e);
+            return;
+        }
+    };
 
-    let mut ieee_eui64 = [0; 8];
-    rng.fill_bytes(&mut ieee_eui64);
+    let host_name = match srp::host_name(app_config.hostname, &ieee_eui64) {
+        Ok(host_name) => host_name,
+        Err(e) => {
+            error!("Invalid hostname: {:?}", e);
+            return;
+        }
+    };
 
     let ot_resources = mk_static!(OtResources, OtResources::new());
-    let ot_settings_buf = mk_static!([u8; 1024], [0; 1024]);
+    let ot_srp_resources = mk_static!(
+        OtSrpResources<SRP_SERVICES, SRP_BUFFER_SIZE>,
+        OtSrpResources::new()
+    );
     let enet_driver_state =
         mk_static!(EnetDriverState<IPV6_PACKET_SIZE, 1, 1>, EnetDriverState::new());
 
-    let ot_settings = mk_static!(SimpleRamSettings, SimpleRamSettings::new(ot_settings_buf));
+    let flash = FlashStorage::new();
+
+    // holding the BOOT button while resetting forgets the Thread network, the network is joined
+    // once more with the stored settings to remove the SRP registration first
+    let boot_button = Input::new(peripherals.GPIO9, InputConfig::default().with_pull(Pull::Up));
+    let factory_reset = boot_button.is_low();
 
-    let ot = OpenThread::new(ieee_eui64, rng, ot_settings, ot_resources).unwrap();
+    let ot_settings = match SettingsStore::new(flash, PARTITION_OFFSET) {
+        Ok(store) => {
+            info!(
+                "Loaded {} bytes of Thread settings (generation {})",
+                store.used(),
+                store.generation()
+            );
+            mk_static!(SettingsStore<FlashStorage>, store)
+        }
+        Err(e) => {
+            error!("Failed to load the Thread settings: {:?}", e);
+            return;
+        }
+    };
+
+    let ot = OpenThread::new_with_srp(
+        ieee_eui64,
+        rng,
+        ot_settings,
+        ot_resources,
+        ot_srp_resources,
+    )
+    .unwrap();
 
     let (_enet_controller, enet_driver_runner, enet_driver) =
         enet::new(ot.clone(), enet_driver_state);
@@ -135,7 +281,7 @@ async fn main(spawner: Spawner) {
         ))
         .unwrap();
 
-    let enet_resources = mk_static!(StackResources<ENET_MAX_SOCKETS>, StackResources::new());
+    let enet_resources = mk_static!(StackResources<STACK_SOCKETS>, StackResources::new());
 
     let (stack, enet_runner) = embassy_net::new(
         enet_driver,
@@ -146,75 +292,193 @@ async fn main(spawner: Spawner) {
 
     spawner.spawn(run_enet(enet_runner)).unwrap();
 
-    info!("Thread dataset: {:?}", app_config.thread_dataset);
-
-    ot.set_active_dataset_tlv_hexstr(app_config.thread_dataset)
-        .unwrap();
     ot.enable_ipv6(true).unwrap();
+    match provisioning {
+        Provisioning::Dataset(dataset) => {
+            // validated by build.rs
+            if let Ok(decoded) = Dataset::from_hex(dataset.expose()) {
+                info!(
+                    "Thread network {:?} on channel {:?}, PAN ID {:?}",
+                    decoded.network_name,
+                    decoded.channel.map(|channel| channel.channel),
+                    decoded.pan_id
+                );
+            }
+            ot.set_active_dataset_tlv_hexstr(dataset.expose()).unwrap();
+        }
+        Provisioning::Joiner(_) if joiner::is_commissioned() => {
+            info!("Using the Thread dataset received during commissioning");
+        }
+        Provisioning::Joiner(pskd) => {
+            info!(
+                "Waiting for a commissioner to admit EUI-64 {:02x}",
+                ieee_eui64
+            );
+            if let Err(e) = joiner::join(pskd).await {
+                error!("Thread commissioning failed: {:?}", e);
+                return;
+            }
+        }
+    }
+    if let Err(e) = device_type::configure(&ot, device_type) {
+        error!("Failed to configure the device type: {:?}", e);
+        return;
+    }
+    if let Some(sleepy_config) = &sleepy_config {
+        if let Err(e) = sleepy::enable(sleepy_config) {
+            error!("Failed to configure the sleepy end device: {:?}", e);
+            return;
+        }
+    }
     ot.enable_thread(true).unwrap();
 
-    info!("Waiting for child role");
-    loop {
-        ot.wait_changed().await;
+    // the device only connects out, port 0 advertises it without a reachable service
+    let service = ServiceRegistration {
+        service_type: SERVICE_TYPE,
+        instance_name: &host_name,
+        port: 0,
+    };
+    if let Err(e) = srp::register(&ot, &host_name, Some(&service)) {
+        error!("SRP registration failed: {:?}", e);
+    }
 
-        if ot.net_status().role == DeviceRole::Child {
-            info!("OT -> Role: Child");
-            break;
+    spawner
+        .spawn(supervisor::supervisor_task(ot.clone(), stack, dns_server))
+        .unwrap();
+
+    if factory_reset {
+        info!("BOOT button held, erasing the Thread settings");
+        if with_timeout(FACTORY_RESET_ATTACH_TIMEOUT, connectivity::wait_attached())
+            .await
+            .is_err()
+        {
+            warn!("Not attached, the SRP registration expires with its lease");
+        }
+        if let Err(e) = srp::deregister(&ot).await {
+            error!("SRP deregistration failed: {:?}", e);
         }
+        // OpenThread owns the settings store, erase the partition behind its back and restart
+        // before it writes again
+        if let Err(e) = settings_store::factory_reset(&mut FlashStorage::new(), PARTITION_OFFSET) {
+            error!("Factory reset failed: {:?}", e);
+        }
+        esp_hal::system::software_reset();
     }
 
-    loop {
-        info!("Waiting for IPv6 address from OpenThread...");
+    info!("Waiting to attach to the Thread network");
+    connectivity::wait_attached().await.unwrap();
+    // polls start with the attachment, close enough to align the application wake-ups
+    let poll_epoch = Instant::now();
+    let role = ot.net_status().role;
+    info!("OT -> Role: {:?}", role);
+    if !device_type.accepts(role) {
+        warn!("Attached as {:?}, unexpected for a {:?} device", role, device_type);
+    }
 
-        let mut addrs = heapless::Vec::<(Ipv6Addr, u8), 4>::new();
-        ot.ipv6_addrs(|addr| {
-            if let Some(addr) = addr {
-                let _ = addrs.push(addr);
-            }
+    info!("Waiting for an IPv6 address from OpenThread...");
+    let address = connectivity::wait_online().await.unwrap();
+    info!("Using IPv6 address {:?}", address);
 
-            Ok(())
-        })
+    spawner
+        .spawn(nat64_discovery::nat64_task(
+            stack,
+            dns_server,
+            configured_nat64_prefix,
+        ))
         .unwrap();
 
-        if !addrs.is_empty() {
-            info!("Got IPv6 addres(es) from OpenThread: {:?}", addrs);
+    // the BOOT button dumps the diagnostics from now on
+    spawner.spawn(dump_on_button(boot_button)).unwrap();
+
+    let mut duty_cycle = DutyCycle::new(Instant::now());
+    let mut next_diagnostics = diagnostics_interval.map(|interval| Instant::now() + interval);
+    let mut dump_requested = false;
+
+    if let Some(coap_config) = coap_config {
+        spawner
+            .spawn(coap_client::coap_client_task(stack, coap_config, coap_seed))
+            .unwrap();
+
+        let mut next_reading = Instant::now();
+        loop {
+            duty_cycle.wake(Instant::now());
+
+            if Instant::now() >= next_reading {
+                // one confirmable request per reading, the duty cycle is part of it
+                let stats = duty_cycle.measure(Instant::now());
+                let mut reading: String<96> = String::new();
+                write!(
+                    reading,
+                    "{{\"value\":{},\"awake\":{},\"wakeups\":{},\"polls\":{}}}",
+                    123456,
+                    stats.awake_permille(),
+                    stats.wakeups,
+                    stats.data_polls
+                )
+                .expect("write! failed");
+                info!("Sending reading: {}", reading.as_str());
+                if !coap_client::publish(Resource::Telemetry, &reading) {
+                    error!("CoAP queue full, dropping the reading");
+                }
+                next_reading = Instant::now() + publish_interval;
+            }
 
-            let (linklocal_addr, linklocal_prefix) = addrs
-                .iter()
-                .find(|(addr, _)| addr.segments()[0] == 0xfdb4) // .is_unicast_link_local()) //segments()[0] == 0xfdeb)
-                .expect("No link-local address found");
+            if dump_requested || next_diagnostics.is_some_and(|at| Instant::now() >= at) {
+                dump_requested = false;
+                let report = diagnostics::dump();
+                if !coap_client::publish(Resource::Diagnostics, &report) {
+                    error!("CoAP queue full, dropping the diagnostics");
+                }
+                next_diagnostics = diagnostics_interval.map(|interval| Instant::now() + interval);
+            }
 
-            info!("Will bind to link-local {:?} Ipv6 addr", linklocal_addr);
-            info!(
-                "Will bind to link-local {:?} Ipv6 prefix",
-                linklocal_prefix
-            );
+            duty_cycle.sleep(Instant::now());
 
-            stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
-                address: Ipv6Cidr::new(*linklocal_addr, *linklocal_prefix),
-                gateway: None,           // TODO (not needed asof now any trafic outside of thread needs to use nat64 addresses that need to be self generated) should be the otbr address?
-                dns_servers: Vec::new(), // TODO (can be any address using nat64 synthesis)
-            }));
+            let mut deadline = next_diagnostics.map_or(next_reading, |at| at.min(next_reading));
+            if let Some(sleepy_config) = &sleepy_config {
+                deadline = sleepy::next_window(deadline, poll_epoch, sleepy_config.poll_period);
+            }
 
-            break;
+            match select3(
+                Timer::at(deadline),
+                coap_client::wait_command(),
+                DUMP_REQUESTED.wait(),
+            )
+            .await
+            {
+                Either3::First(()) => {}
+                Either3::Second(command) => {
+                    info!("Received command: {=[u8]:a}", &command[..]);
+                    dump_requested = &command[..] == DUMP_COMMAND;
+                }
+                Either3::Third(()) => dump_requested = true,
+            }
         }
     }
 
-    spawner.spawn(run_ot_ip_info(ot.clone())).unwrap();
-
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
+    let mut rx_buffer = [0; TCP_RX_BUFFER_SIZE];
+    let mut tx_buffer = [0; TCP_TX_BUFFER_SIZE];
 
     loop {
         Timer::after(Duration::from_secs(1)).await;
 
+        supervisor::reset_reconnect();
+        if connectivity::wait_online().await.is_none() {
+            error!("No connectivity receiver available");
+            continue;
+        }
+
         let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
         socket.set_timeout(Some(Duration::from_secs(15)));
 
-        // let mqtt_ip = core::net::Ipv4Addr::new(192, 168, 1, 228);
-        // let mqtt_ip = core::net::Ipv4Addr::new(1,1,1,1);
-        let mqtt_ip = synthesize_nat64(nat64_prefix, net64_prefix_length, app_config.mqtt_ip.parse::<Ipv4Addr>().unwrap());
-        info!("Synthesized MQTT-Broker IPv6 address: {:?}", mqtt_ip);
+        let mqtt_ip = match resolver::resolve(stack, app_config.mqtt_fqdn).await {
+            Ok(address) => address,
+            Err(e) => {
+                error!("Failed to resolve {}: {:?}", app_config.mqtt_fqdn, e);
+                continue;
+            }
+        };
+        info!("Resolved MQTT-Broker IPv6 address: {:?}", mqtt_ip);
         let mqtt_endpoint = (mqtt_ip, app_config.mqtt_port);
         info!("Connection to MQTT-Broker on {:?}", mqtt_endpoint);
         let connection = socket.connect(mqtt_endpoint).await;
@@ -225,24 +489,25 @@ async fn main(spawner: Spawner) {
         }
 
         info!("Connected");
+        let socket = RefCell::new(socket);
 
         let mut mqtt_client_config = ClientConfig::new(
             rust_mqtt::client::client_config::MqttVersion::MQTTv5,
             CountingRng(20000),
         );
         mqtt_client_config.add_username(app_config.mqtt_username);
-        mqtt_client_config.add_password(app_config.mqtt_password);
+        mqtt_client_config.add_password(SECRETS.mqtt_password.expose());
         mqtt_client_config.add_client_id("clientId-2m3km334gd");
-        mqtt_client_config.max_packet_size = 100;
-        let mut recv_buffer = [0; 80];
-        let mut write_buffer = [0; 80];
+        mqtt_client_config.max_packet_size = MQTT_BUFFER_SIZE as u32;
+        let mut recv_buffer = [0; MQTT_BUFFER_SIZE];
+        let mut write_buffer = [0; MQTT_BUFFER_SIZE];
 
         let mut mqtt_client = MqttClient::<_, 5, _>::new(
-            socket,
+            Transport(&socket),
             &mut write_buffer,
-            80,
+            MQTT_BUFFER_SIZE,
             &mut recv_buffer,
-            80,
+            MQTT_BUFFER_SIZE,
             mqtt_client_config,
         );
 
@@ -260,33 +525,140 @@ async fn main(spawner: Spawner) {
                 }
             },
         }
-        let random_number = 123456;
-        info!("Sending number: {}", random_number);
-
-        let mut number_string: String<32> = String::new();
-        write!(number_string, "{:.2}", random_number).expect("write! failed");
-
-        match mqtt_client
-            .send_message(
-                "random/1",
-                number_string.as_bytes(),
-                rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
-                true,
-            )
+        if let Err(mqtt_error) = mqtt_client
+            .subscribe_to_topic(DIAGNOSTICS_REQUEST_TOPIC)
             .await
         {
-            Ok(()) => {}
-            Err(mqtt_error) => match mqtt_error {
-                ReasonCode::NetworkError => {
-                    error!("MQTT NEtwork error")
+            error!(
+                "Failed to subscribe to {}: {:?}",
+                DIAGNOSTICS_REQUEST_TOPIC, mqtt_error
+            );
+            continue;
+        }
+        let mut next_reading = Instant::now();
+        loop {
+            duty_cycle.wake(Instant::now());
+
+            if Instant::now() >= next_reading {
+                let random_number = 123456;
+                info!("Sending number: {}", random_number);
+
+                let mut number_string: String<32> = String::new();
+                write!(number_string, "{:.2}", random_number).expect("write! failed");
+
+                match mqtt_client
+                    .send_message(
+                        "random/1",
+                        number_string.as_bytes(),
+                        rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
+                        true,
+                    )
+                    .await
+                {
+                    Ok(()) => {}
+                    Err(mqtt_error) => match mqtt_error {
+                        ReasonCode::NetworkError => {
+                            error!("MQTT NEtwork error");
+                            break;
+                        }
+                        _ => {
+                            error!("Other MQTT error: {:?}", mqtt_error)
+                        }
+                    },
                 }
-                _ => {
-                    error!("Other MQTT error: {:?}", mqtt_error)
+
+                // batched into the same wake-up as the measurement
+                let stats = duty_cycle.measure(Instant::now());
+                info!("Duty cycle: {:?}", stats);
+                let mut duty_cycle_string: String<64> = String::new();
+                write!(
+                    duty_cycle_string,
+                    "{{\"awake\":{},\"wakeups\":{},\"polls\":{}}}",
+                    stats.awake_permille(),
+                    stats.wakeups,
+                    stats.data_polls
+                )
+                .expect("write! failed");
+
+                if let Err(mqtt_error) = mqtt_client
+                    .send_message(
+                        "telemetry/duty_cycle",
+                        duty_cycle_string.as_bytes(),
+                        rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS0,
+                        false,
+                    )
+                    .await
+                {
+                    error!("Failed to send the duty cycle: {:?}", mqtt_error);
+                    if matches!(mqtt_error, ReasonCode::NetworkError) {
+                        break;
+                    }
                 }
-            },
+                next_reading = Instant::now() + publish_interval;
+            }
+
+            if dump_requested || next_diagnostics.is_some_and(|at| Instant::now() >= at) {
+                dump_requested = false;
+                let report = diagnostics::dump();
+                if let Err(mqtt_error) = mqtt_client
+                    .send_message(
+                        DIAGNOSTICS_TOPIC,
+                        report.as_bytes(),
+                        rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS0,
+                        false,
+                    )
+                    .await
+                {
+                    error!("Failed to send the diagnostics: {:?}", mqtt_error);
+                    if matches!(mqtt_error, ReasonCode::NetworkError) {
+                        break;
+                    }
+                }
+                next_diagnostics = diagnostics_interval.map(|interval| Instant::now() + interval);
+            }
+
+            duty_cycle.sleep(Instant::now());
+
+            let mut deadline = next_diagnostics.map_or(next_reading, |at| at.min(next_reading));
+            if let Some(sleepy_config) = &sleepy_config {
+                deadline = sleepy::next_window(deadline, poll_epoch, sleepy_config.poll_period);
+            }
+
+            match select4(
+                Timer::at(deadline),
+                supervisor::wait_reconnect(),
+                DUMP_REQUESTED.wait(),
+                wait_read_ready(&socket),
+            )
+            .await
+            {
+                Either4::First(()) => {}
+                Either4::Second(()) => {
+                    info!("Thread network changed, reconnecting to the MQTT-Broker");
+                    break;
+                }
+                Either4::Third(()) => dump_requested = true,
+                // data (or the end of the connection) is pending, the read is not interrupted
+                Either4::Fourth(()) => match mqtt_client.receive_message().await {
+                    Ok((topic, _)) => {
+                        info!("Received message on {}", topic);
+                        dump_requested = topic == DIAGNOSTICS_REQUEST_TOPIC;
+                    }
+                    Err(mqtt_error) => {
+                        error!("Failed to receive a message: {:?}", mqtt_error);
+                        break;
+                    }
+                },
+            }
         }
+    }
+}
 
-        Timer::after(Duration::from_secs(5)).await
+#[embassy_executor::task]
+async fn dump_on_button(mut button: Input<'static>) -> ! {
+    loop {
+        button.wait_for_falling_edge().await;
+        DUMP_REQUESTED.signal(());
     }
 }
 
@@ -302,57 +674,3 @@ async fn run_enet_driver(
 async fn run_enet(mut runner: Runner<'static, EnetDriver<'static, IPV6_PACKET_SIZE>>) -> ! {
     runner.run().await
 }
-
-#[embassy_executor::task]
-async fn run_ot_ip_info(ot: OpenThread<'static>) -> ! {
-    let mut curr_addrs = heapless::Vec::<(Ipv6Addr, u8), 4>::new();
-
-    loop {
-        let mut addrs = heapless::Vec::<(Ipv6Addr, u8), 4>::new();
-        ot.ipv6_addrs(|addr| {
-            if let Some(addr) = addr {
-                let _ = addrs.push(addr);
-            }
-
-            Ok(())
-        })
-        .unwrap();
-
-        if curr_addrs != addrs {
-            info!("Got new IPv6 address(es) from OpenThread: {:?}", addrs);
-
-            curr_addrs = addrs;
-
-            info!("Waiting for OpenThread changes signal...");
-        }
-
-        ot.wait_changed().await;
-    }
-}
-
-
-/// This function mimics the NAT64 synthesis function Address::SynthesizeFromIp4Address
-/// from the OpenThread codebase.
-fn synthesize_nat64(prefix: Ipv6Addr, prefix_len: u8, ipv4: Ipv4Addr) -> Ipv6Addr {
-    
-    assert!([32, 40, 48, 56, 64, 96].contains(&prefix_len), "Invalid prefix length");
-
-    let mut ipv6_bytes = prefix.octets();
-    let ipv4_bytes = ipv4.octets();
-
-    let skip_index = 8; // kSkipIndex
-    let mut ip6_index = (prefix_len / 8) as usize; // aPrefix.GetLength() / kBitsPerByte;
-
-    for &b in ipv4_bytes.iter() {
-        if ip6_index == skip_index {
-            ip6_index += 1;
-        }
-        if ip6_index >= 16 {
-            break;
-        }
-        ipv6_bytes[ip6_index] = b;
-        ip6_index += 1;
-    }
-
-    Ipv6Addr::from(ipv6_bytes)
-}
\ No newline at end of file
@@ -0,0 +1,615 @@
+//! CoAP (Constrained Application Protocol, RFC 7252) message format.
+//!
+//! Messages are parsed in place, the options are validated once by [`Message::parse`] and
+//! iterated with [`Message::options`]. [`MessageWriter`] builds messages in a caller provided
+//! buffer, options have to be added in ascending order of their numbers. Block-wise transfers
+//! (RFC 7959) and observe (RFC 7641) only need their option values, see [`Block`].
+
+use defmt::Format;
+
+pub const VERSION: u8 = 1;
+/// Default UDP port of CoAP
+pub const PORT: u16 = 5683;
+
+pub const MAX_TOKEN_LEN: usize = 8;
+
+const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum CoapError {
+    /// Shorter than the header, token or an option announces
+    Truncated,
+    InvalidVersion,
+    /// Token lengths 9 to 15 are reserved
+    InvalidTokenLength,
+    /// Reserved option delta or length, or a payload marker without payload
+    InvalidOption,
+    /// Empty messages (code 0.00) have no token, options or payload
+    InvalidEmptyMessage,
+    /// Options have to be written in ascending order
+    OptionOrder,
+    BufferTooSmall,
+}
+
+impl core::fmt::Display for CoapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CoAP message error: {:?}", self)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        }
+    }
+}
+
+/// Request method or response code as `class.detail`
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Code(pub u8);
+
+impl Code {
+    pub const EMPTY: Code = Code::new(0, 0);
+    pub const GET: Code = Code::new(0, 1);
+    pub const POST: Code = Code::new(0, 2);
+    pub const PUT: Code = Code::new(0, 3);
+    pub const DELETE: Code = Code::new(0, 4);
+
+    pub const CREATED: Code = Code::new(2, 1);
+    pub const CHANGED: Code = Code::new(2, 4);
+    pub const CONTENT: Code = Code::new(2, 5);
+
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    pub const BAD_OPTION: Code = Code::new(4, 2);
+    pub const FORBIDDEN: Code = Code::new(4, 3);
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    pub const REQUEST_ENTITY_INCOMPLETE: Code = Code::new(4, 8);
+    pub const REQUEST_ENTITY_TOO_LARGE: Code = Code::new(4, 13);
+    pub const UNSUPPORTED_CONTENT_FORMAT: Code = Code::new(4, 15);
+
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+    pub const SERVICE_UNAVAILABLE: Code = Code::new(5, 3);
+
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Code((class << 5) | (detail & 0x1f))
+    }
+
+    pub fn class(&self) -> u8 {
+        self.0 >> 5
+    }
+
+    pub fn detail(&self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.class() == 0 && *self != Code::EMPTY
+    }
+}
+
+/// Option numbers used by the server and client
+pub mod option {
+    pub const URI_HOST: u16 = 3;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+
+    /// Unknown critical options have to be rejected
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+/// Content-Format registry values
+pub mod content_format {
+    pub const TEXT_PLAIN: u16 = 0;
+    pub const LINK_FORMAT: u16 = 40;
+    pub const JSON: u16 = 50;
+}
+
+/// Block1 or Block2 option value (RFC 7959)
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    /// Block size is `16 << szx`
+    pub szx: u8,
+}
+
+impl Block {
+    /// 1024 bytes
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn decode(value: u32) -> Option<Self> {
+        let szx = (value & 0x7) as u8;
+        if szx > Self::MAX_SZX || value >> 24 != 0 {
+            return None;
+        }
+
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx,
+        })
+    }
+
+    pub fn encode(&self) -> u32 {
+        (self.num << 4) | ((self.more as u32) << 3) | self.szx as u32
+    }
+
+    pub fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Offset of the block in the representation
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Value of an unsigned integer option, at most 4 bytes in network byte order.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, byte| (acc << 8) | *byte as u32))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub message_type: MessageType,
+    pub code: Code,
+    pub message_id: u16,
+    pub token: &'a [u8],
+    options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, CoapError> {
+        let (message_type, code, message_id, token_len) = parse_header(data)?;
+        let rest = &data[4..];
+        if rest.len() < token_len {
+            return Err(CoapError::Truncated);
+        }
+        let (token, rest) = rest.split_at(token_len);
+
+        // validate the options and find the payload
+        let mut iter = OptionIter::new(rest);
+        for option in iter.by_ref() {
+            option?;
+        }
+        let options_len = rest.len() - iter.data.len();
+        let (options, payload) = rest.split_at(options_len);
+        let payload = match payload {
+            [] => payload,
+            [PAYLOAD_MARKER] => return Err(CoapError::InvalidOption),
+            [_marker, payload @ ..] => payload,
+        };
+
+        if code == Code::EMPTY && (!token.is_empty() || !data[4..].is_empty()) {
+            return Err(CoapError::InvalidEmptyMessage);
+        }
+
+        Ok(Message {
+            message_type,
+            code,
+            message_id,
+            token,
+            options,
+            payload,
+        })
+    }
+
+    /// Options in ascending order of their numbers
+    pub fn options(&self) -> impl Iterator<Item = (u16, &'a [u8])> {
+        // validated in parse
+        OptionIter::new(self.options).map_while(Result::ok)
+    }
+
+    /// First value of the option
+    pub fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options()
+            .find(|(option, _)| *option == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    /// `Uri-Path` segments, invalid UTF-8 yields an empty segment
+    pub fn path(&self) -> impl Iterator<Item = &'a str> {
+        self.options()
+            .filter(|(number, _)| *number == option::URI_PATH)
+            .map(|(_, value)| core::str::from_utf8(value).unwrap_or(""))
+    }
+}
+
+/// Type, code, message ID and token length of a message, without validating the rest.
+pub fn parse_header(data: &[u8]) -> Result<(MessageType, Code, u16, usize), CoapError> {
+    let [first, code, id_high, id_low, ..] = *data else {
+        return Err(CoapError::Truncated);
+    };
+    if first >> 6 != VERSION {
+        return Err(CoapError::InvalidVersion);
+    }
+    let token_len = (first & 0x0f) as usize;
+    if token_len > MAX_TOKEN_LEN {
+        return Err(CoapError::InvalidTokenLength);
+    }
+
+    Ok((
+        MessageType::from_bits(first >> 4),
+        Code(code),
+        u16::from_be_bytes([id_high, id_low]),
+        token_len,
+    ))
+}
+
+struct OptionIter<'a> {
+    data: &'a [u8],
+    number: u16,
+}
+
+impl<'a> OptionIter<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        OptionIter { data, number: 0 }
+    }
+
+    /// Extended delta or length of the 4 bit `nibble`
+    fn extended(&mut self, nibble: u8) -> Result<u16, CoapError> {
+        match nibble {
+            0..=12 => Ok(nibble as u16),
+            13 => {
+                let [byte, rest @ ..] = self.data else {
+                    return Err(CoapError::Truncated);
+                };
+                self.data = rest;
+                Ok(*byte as u16 + 13)
+            }
+            14 => {
+                let [high, low, rest @ ..] = self.data else {
+                    return Err(CoapError::Truncated);
+                };
+                self.data = rest;
+                u16::from_be_bytes([*high, *low])
+                    .checked_add(269)
+                    .ok_or(CoapError::InvalidOption)
+            }
+            _ => Err(CoapError::InvalidOption),
+        }
+    }
+
+    fn parse_next(&mut self) -> Result<(u16, &'a [u8]), CoapError> {
+        let [first, rest @ ..] = self.data else {
+            return Err(CoapError::Truncated);
+        };
+        self.data = rest;
+
+        let delta = self.extended(first >> 4)?;
+        let len = self.extended(first & 0x0f)? as usize;
+        if self.data.len() < len {
+            return Err(CoapError::Truncated);
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        self.number = self
+            .number
+            .checked_add(delta)
+            .ok_or(CoapError::InvalidOption)?;
+        Ok((self.number, value))
+    }
+}
+
+impl<'a> Iterator for OptionIter<'a> {
+    type Item = Result<(u16, &'a [u8]), CoapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.data.first() {
+            None | Some(&PAYLOAD_MARKER) => None,
+            Some(_) => {
+                let option = self.parse_next();
+                if option.is_err() {
+                    self.data = &[];
+                }
+                Some(option)
+            }
+        }
+    }
+}
+
+/// Builds a message in `buf`.
+pub struct MessageWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    last_option: u16,
+}
+
+impl<'a> MessageWriter<'a> {
+    pub fn new(
+        buf: &'a mut [u8],
+        message_type: MessageType,
+        code: Code,
+        message_id: u16,
+        token: &[u8],
+    ) -> Result<Self, CoapError> {
+        if token.len() > MAX_TOKEN_LEN {
+            return Err(CoapError::InvalidTokenLength);
+        }
+        let len = 4 + token.len();
+        if buf.len() < len {
+            return Err(CoapError::BufferTooSmall);
+        }
+
+        buf[0] = (VERSION << 6) | (message_type.bits() << 4) | token.len() as u8;
+        buf[1] = code.0;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[4..len].copy_from_slice(token);
+
+        Ok(MessageWriter {
+            buf,
+            len,
+            last_option: 0,
+        })
+    }
+
+    pub fn option(&mut self, number: u16, value: &[u8]) -> Result<(), CoapError> {
+        if number < self.last_option {
+            return Err(CoapError::OptionOrder);
+        }
+
+        let mut header = [0; 5];
+        let mut header_len = 1;
+        let mut nibble = |field: usize, header: &mut [u8; 5]| -> u8 {
+            match field {
+                0..=12 => field as u8,
+                13..=268 => {
+                    header[header_len] = (field - 13) as u8;
+                    header_len += 1;
+                    13
+                }
+                _ => {
+                    let extended = ((field - 269) as u16).to_be_bytes();
+                    header[header_len..header_len + 2].copy_from_slice(&extended);
+                    header_len += 2;
+                    14
+                }
+            }
+        };
+        let delta = nibble((number - self.last_option) as usize, &mut header);
+        let len = nibble(value.len(), &mut header);
+        header[0] = (delta << 4) | len;
+
+        self.extend(&header[..header_len])?;
+        self.extend(value)?;
+        self.last_option = number;
+        Ok(())
+    }
+
+    /// Unsigned integer option in the shortest encoding
+    pub fn uint_option(&mut self, number: u16, value: u32) -> Result<(), CoapError> {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..])
+    }
+
+    pub fn payload(&mut self, payload: &[u8]) -> Result<(), CoapError> {
+        if payload.is_empty() {
+            return Ok(());
+        }
+        self.extend(&[PAYLOAD_MARKER])?;
+        self.extend(payload)
+    }
+
+    /// Length of the message
+    pub fn finish(self) -> usize {
+        self.len
+    }
+
+    fn extend(&mut self, data: &[u8]) -> Result<(), CoapError> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(CoapError::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GET /sys/info, CON, message ID 0x1234, token 0xcafe, Block2 num 1 with 64 bytes
+    const GET_SYS_INFO: &[u8] = &[
+        0x42, 0x01, 0x12, 0x34, 0xca, 0xfe, 0xb3, b's', b'y', b's', 0x04, b'i', b'n', b'f', b'o',
+        0xc1, 0x12,
+    ];
+
+    #[test]
+    fn parses_request() {
+        let message = Message::parse(GET_SYS_INFO).unwrap();
+
+        assert_eq!(message.message_type, MessageType::Confirmable);
+        assert_eq!(message.code, Code::GET);
+        assert_eq!(message.message_id, 0x1234);
+        assert_eq!(message.token, &[0xca, 0xfe]);
+        assert!(message.path().eq(["sys", "info"]));
+        assert_eq!(
+            message.uint_option(option::BLOCK2).and_then(Block::decode),
+            Some(Block {
+                num: 1,
+                more: false,
+                szx: 2
+            })
+        );
+        assert!(message.payload.is_empty());
+    }
+
+    #[test]
+    fn writes_request() {
+        let mut buf = [0; 64];
+        let mut writer = MessageWriter::new(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            0x1234,
+            &[0xca, 0xfe],
+        )
+        .unwrap();
+        writer.option(option::URI_PATH, b"sys").unwrap();
+        writer.option(option::URI_PATH, b"info").unwrap();
+        writer
+            .uint_option(
+                option::BLOCK2,
+                Block {
+                    num: 1,
+                    more: false,
+                    szx: 2,
+                }
+                .encode(),
+            )
+            .unwrap();
+        let len = writer.finish();
+
+        assert_eq!(&buf[..len], GET_SYS_INFO);
+    }
+
+    #[test]
+    fn round_trips_extended_options_and_payload() {
+        let long_value = [0xab; 300];
+        let mut buf = [0; 400];
+        let mut writer =
+            MessageWriter::new(&mut buf, MessageType::NonConfirmable, Code::PUT, 7, &[]).unwrap();
+        writer.option(option::URI_PATH, b"led").unwrap();
+        writer.uint_option(option::CONTENT_FORMAT, 0).unwrap();
+        writer.option(option::SIZE1, &long_value).unwrap();
+        writer.payload(b"on").unwrap();
+        let len = writer.finish();
+
+        let message = Message::parse(&buf[..len]).unwrap();
+        let mut options = message.options();
+        assert_eq!(options.next(), Some((option::URI_PATH, &b"led"[..])));
+        assert_eq!(options.next(), Some((option::CONTENT_FORMAT, &[][..])));
+        assert_eq!(options.next(), Some((option::SIZE1, &long_value[..])));
+        assert_eq!(options.next(), None);
+        assert_eq!(message.uint_option(option::CONTENT_FORMAT), Some(0));
+        assert_eq!(message.payload, b"on");
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0x00]),
+            Err(CoapError::Truncated)
+        );
+        assert_eq!(
+            Message::parse(&[0x80, 0x01, 0x00, 0x01]),
+            Err(CoapError::InvalidVersion)
+        );
+        assert_eq!(
+            Message::parse(&[0x49, 0x01, 0x00, 0x01]),
+            Err(CoapError::InvalidTokenLength)
+        );
+        // token longer than the message
+        assert_eq!(
+            Message::parse(&[0x42, 0x01, 0x00, 0x01, 0xca]),
+            Err(CoapError::Truncated)
+        );
+        // option value beyond the end
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xb3, b'l']),
+            Err(CoapError::Truncated)
+        );
+        // reserved delta 15
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xf1, 0x00]),
+            Err(CoapError::InvalidOption)
+        );
+        // payload marker without payload
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0x00, 0x01, 0xff]),
+            Err(CoapError::InvalidOption)
+        );
+        // empty message with a token
+        assert_eq!(
+            Message::parse(&[0x41, 0x00, 0x00, 0x01, 0x01]),
+            Err(CoapError::InvalidEmptyMessage)
+        );
+    }
+
+    #[test]
+    fn writer_enforces_option_order() {
+        let mut buf = [0; 16];
+        let mut writer =
+            MessageWriter::new(&mut buf, MessageType::Confirmable, Code::GET, 1, &[]).unwrap();
+        writer.option(option::URI_PATH, b"led").unwrap();
+
+        assert_eq!(
+            writer.option(option::OBSERVE, &[]),
+            Err(CoapError::OptionOrder)
+        );
+        assert_eq!(
+            writer.option(option::URI_PATH, b"too long for the buffer"),
+            Err(CoapError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn block_values() {
+        let block = Block {
+            num: 3,
+            more: true,
+            szx: 6,
+        };
+        assert_eq!(block.encode(), 0x3e);
+        assert_eq!(Block::decode(0x3e), Some(block));
+        assert_eq!(block.size(), 1024);
+        assert_eq!(block.offset(), 3072);
+        // reserved szx 7
+        assert_eq!(Block::decode(0x07), None);
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(Code::CONTENT.0, 0x45);
+        assert_eq!(Code::NOT_FOUND.class(), 4);
+        assert_eq!(Code::NOT_FOUND.detail(), 4);
+        assert!(Code::PUT.is_request());
+        assert!(!Code::EMPTY.is_request());
+        assert!(!Code::CHANGED.is_request());
+    }
+}
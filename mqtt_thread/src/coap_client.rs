@@ -0,0 +1,253 @@
+//! CoAP client publishing telemetry instead of MQTT.
+//!
+//! Readings are POSTed as confirmable requests to `coap://<coap_endpoint>/<coap_telemetry_path>`,
+//! a single UDP exchange instead of a TCP connection through NAT64. Lost requests are
+//! retransmitted with the exponential backoff of RFC 7252: the first timeout is random between
+//! `ACK_TIMEOUT` and `ACK_TIMEOUT * ACK_RANDOM_FACTOR` and doubles with each of the
+//! `MAX_RETRANSMIT` retransmissions. Like `NSTART = 1` demands, only one confirmable request is
+//! outstanding at a time.
+//!
+//! Commands are received by observing (RFC 7641) `coap_command_path`, every notification is
+//! handed to [`wait_command`]. The observation is registered again after failures and network
+//! changes.
+//!
+//! Diagnostics reports (see [`crate::diagnostics`]) are POSTed to `coap_diagnostics_path` the
+//! same way.
+//!
+//! [`Client`] in [`crate::coap_exchange`] is the protocol state without I/O,
+//! [`coap_client_task`] owns the socket.
+
+use defmt::{debug, info, warn, Format};
+use embassy_futures::select::{select4, Either4};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::coap::{content_format, PORT};
+use crate::coap_exchange::{Client, Event, Poll};
+use crate::sizing::{COAP_REQUEST_SIZE, COAP_RESPONSE_SIZE, DIAGNOSTICS_SIZE};
+use crate::{connectivity, resolver, supervisor};
+
+/// Retry interval of the observe registration and of the endpoint resolution
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Largest payload of a request
+pub const PAYLOAD_SIZE: usize = DIAGNOSTICS_SIZE;
+pub const COMMAND_SIZE: usize = 64;
+
+static OUTGOING: Channel<CriticalSectionRawMutex, (Resource, String<PAYLOAD_SIZE>), 4> =
+    Channel::new();
+static COMMAND: Signal<CriticalSectionRawMutex, Vec<u8, COMMAND_SIZE>> = Signal::new();
+
+/// Where the client sends to, from the configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct CoapConfig {
+    pub host: &'static str,
+    pub port: u16,
+    pub telemetry_path: &'static str,
+    pub diagnostics_path: &'static str,
+    /// Empty to receive no commands
+    pub command_path: &'static str,
+}
+
+/// Resource of the server a payload is POSTed to.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Telemetry,
+    Diagnostics,
+}
+
+impl CoapConfig {
+    /// `None` to publish with MQTT if `host` is empty.
+    pub fn from_config(
+        host: &'static str,
+        port: u16,
+        telemetry_path: &'static str,
+        diagnostics_path: &'static str,
+        command_path: &'static str,
+    ) -> Option<Self> {
+        if host.is_empty() {
+            return None;
+        }
+        Some(CoapConfig {
+            host,
+            port: if port == 0 { PORT } else { port },
+            telemetry_path,
+            diagnostics_path,
+            command_path,
+        })
+    }
+
+    fn path(&self, resource: Resource) -> &'static str {
+        match resource {
+            Resource::Telemetry => self.telemetry_path,
+            Resource::Diagnostics => self.diagnostics_path,
+        }
+    }
+}
+
+/// Queue a JSON payload for [`coap_client_task`], `false` if the queue is full.
+pub fn publish(resource: Resource, payload: &str) -> bool {
+    let Ok(payload) = String::try_from(payload) else {
+        warn!("Payload exceeds {} bytes", PAYLOAD_SIZE);
+        return false;
+    };
+    OUTGOING.try_send((resource, payload)).is_ok()
+}
+
+/// Wait for the next notification of the command resource.
+pub async fn wait_command() -> Vec<u8, COMMAND_SIZE> {
+    COMMAND.wait().await
+}
+
+#[embassy_executor::task]
+pub async fn coap_client_task(stack: Stack<'static>, config: CoapConfig, seed: u32) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; COAP_RESPONSE_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; COAP_REQUEST_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(0) {
+        warn!("Failed to bind the CoAP socket: {:?}", e);
+        return;
+    }
+
+    let mut client = Client::new(seed);
+    let mut packet = [0; COAP_RESPONSE_SIZE];
+    let mut reply = [0; 4];
+
+    loop {
+        supervisor::reset_reconnect();
+        client.reset();
+        let _ = connectivity::wait_online().await;
+
+        let endpoint = match resolver::resolve(stack, config.host).await {
+            Ok(address) => IpEndpoint::new(address.into(), config.port),
+            Err(e) => {
+                warn!("Failed to resolve CoAP endpoint {}: {:?}", config.host, e);
+                Timer::after(RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        info!(
+            "Publishing telemetry to coap://{}/{}",
+            endpoint, config.telemetry_path
+        );
+
+        let mut observe_at = (!config.command_path.is_empty()).then(Instant::now);
+
+        loop {
+            if observe_at.is_some_and(|at| at <= Instant::now()) && !client.is_busy() {
+                observe_at = None;
+                if let Ok(request) = client.observe(Instant::now(), config.command_path) {
+                    send(&socket, request, endpoint).await;
+                }
+            }
+
+            let idle = !client.is_busy();
+            let deadline = client
+                .deadline()
+                .into_iter()
+                .chain(observe_at)
+                .min()
+                .unwrap_or(Instant::MAX);
+
+            let event = select4(
+                socket.recv_from(&mut packet),
+                Timer::at(deadline),
+                async {
+                    if idle {
+                        OUTGOING.receive().await
+                    } else {
+                        core::future::pending().await
+                    }
+                },
+                supervisor::wait_reconnect(),
+            )
+            .await;
+
+            match event {
+                Either4::First(Ok((len, meta))) => {
+                    if meta.endpoint != endpoint {
+                        continue;
+                    }
+                    let (event, reply_len) =
+                        client.receive(Instant::now(), &packet[..len], &mut reply);
+                    if let Some(reply_len) = reply_len {
+                        send(&socket, &reply[..reply_len], endpoint).await;
+                    }
+                    if let Some(event) = event {
+                        handle_event(event, &client, &mut observe_at);
+                    }
+                }
+                Either4::First(Err(e)) => debug!("CoAP receive error: {:?}", e),
+                Either4::Second(()) => match client.poll(Instant::now()) {
+                    Poll::Idle => (),
+                    Poll::Retransmit(request) => {
+                        debug!("Retransmitting CoAP request");
+                        send(&socket, request, endpoint).await;
+                    }
+                    Poll::Failed(event) => handle_event(event, &client, &mut observe_at),
+                },
+                Either4::Third((resource, payload)) => {
+                    match client.post(
+                        Instant::now(),
+                        config.path(resource),
+                        content_format::JSON,
+                        payload.as_bytes(),
+                    ) {
+                        Ok(request) => send(&socket, request, endpoint).await,
+                        Err(e) => warn!("Failed to build the {:?} request: {:?}", resource, e),
+                    }
+                }
+                Either4::Fourth(()) => {
+                    info!("Thread network changed, resolving the CoAP endpoint again");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn handle_event(
+    event: Event,
+    client: &Client<COAP_REQUEST_SIZE>,
+    observe_at: &mut Option<Instant>,
+) {
+    match event {
+        Event::Response(code) if code.class() == 2 => debug!("POST accepted: {:?}", code),
+        Event::Response(code) => warn!("POST refused: {:?}", code),
+        Event::ObservationEnded(code) => warn!("Observing commands failed: {:?}", code),
+        Event::Notification(payload) => {
+            info!("Command: {=[u8]:a}", payload);
+            let len = payload.len().min(COMMAND_SIZE);
+            // cannot fail, the length is limited
+            let command = Vec::from_slice(&payload[..len]).unwrap_or_default();
+            COMMAND.signal(command);
+        }
+        Event::Rejected => warn!("CoAP request rejected with a reset"),
+        Event::Timeout => warn!("CoAP request timed out"),
+    }
+
+    if !client.is_observing() && observe_at.is_none() {
+        *observe_at = Some(Instant::now() + RETRY_INTERVAL);
+    }
+}
+
+async fn send(socket: &UdpSocket<'_>, message: &[u8], endpoint: IpEndpoint) {
+    if let Err(e) = socket.send_to(message, endpoint).await {
+        warn!("Failed to send CoAP message: {:?}", e);
+    }
+}
@@ -0,0 +1,721 @@
+//! Protocol state of the CoAP client without I/O, see [`crate::coap_client`].
+//!
+//! [`Client`] builds the confirmable requests, tells when to retransmit them and matches
+//! acknowledgements, separate responses and notifications to them.
+
+use defmt::{debug, Format};
+use embassy_time::{Duration, Instant};
+
+use crate::coap::{option, CoapError, Code, Message, MessageType, MessageWriter};
+
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// `ACK_RANDOM_FACTOR` of 1.5
+const ACK_RANDOM_SPREAD: Duration = Duration::from_secs(1);
+pub const MAX_RETRANSMIT: u32 = 4;
+/// Wait for a separate response after an empty acknowledgement
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const TOKEN_LEN: usize = 4;
+
+/// Observe sequence numbers wrap at 24 bits
+const OBSERVE_SEQUENCE_HALF: u32 = 1 << 23;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ClientError {
+    /// A confirmable request is outstanding
+    Busy,
+    Coap(CoapError),
+}
+
+impl From<CoapError> for ClientError {
+    fn from(e: CoapError) -> Self {
+        ClientError::Coap(e)
+    }
+}
+
+impl core::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CoAP client error: {:?}", self)
+    }
+}
+
+/// Retransmission timeouts of a confirmable request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    timeout: Duration,
+    retransmissions: u32,
+}
+
+impl Backoff {
+    pub fn new(random: u32) -> Self {
+        let spread = random as u64 % ACK_RANDOM_SPREAD.as_ticks();
+        Backoff {
+            timeout: ACK_TIMEOUT + Duration::from_ticks(spread),
+            retransmissions: 0,
+        }
+    }
+
+    /// Timeout before the next retransmission
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Double the timeout for a retransmission, `None` after `MAX_RETRANSMIT`.
+    pub fn retransmit(&mut self) -> Option<Duration> {
+        if self.retransmissions >= MAX_RETRANSMIT {
+            return None;
+        }
+        self.retransmissions += 1;
+        self.timeout *= 2;
+        Some(self.timeout)
+    }
+}
+
+/// Whether `new` is newer than `old` (RFC 7641, section 3.4).
+pub fn is_fresher(new: u32, old: u32) -> bool {
+    (old < new && new - old < OBSERVE_SEQUENCE_HALF)
+        || (old > new && old - new > OBSERVE_SEQUENCE_HALF)
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+enum RequestKind {
+    Post,
+    Observe,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    kind: RequestKind,
+    message_id: u16,
+    token: [u8; TOKEN_LEN],
+    backoff: Backoff,
+    deadline: Instant,
+    /// An empty acknowledgement was received, the response follows separately
+    acknowledged: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    token: [u8; TOKEN_LEN],
+    sequence: Option<u32>,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Response to a POST
+    Response(Code),
+    /// The server refused or ended the observation
+    ObservationEnded(Code),
+    /// Payload of the observed resource, the first one with the registration
+    Notification(&'a [u8]),
+    /// The server answered the request with a reset
+    Rejected,
+    /// No acknowledgement after `MAX_RETRANSMIT` retransmissions
+    Timeout,
+}
+
+/// Result of [`Client::poll`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poll<'a> {
+    Idle,
+    Retransmit(&'a [u8]),
+    Failed(Event<'static>),
+}
+
+/// Confirmable requests and the observation of one server, `N` is the size of the request
+/// kept for retransmissions.
+pub struct Client<const N: usize> {
+    random: u32,
+    next_message_id: u16,
+    pending: Option<Pending>,
+    observation: Option<Observation>,
+    request: [u8; N],
+    request_len: usize,
+}
+
+impl<const N: usize> Client<N> {
+    pub fn new(seed: u32) -> Self {
+        let mut client = Client {
+            // xorshift needs a non-zero state
+            random: seed | 1,
+            next_message_id: 0,
+            pending: None,
+            observation: None,
+            request: [0; N],
+            request_len: 0,
+        };
+        client.next_message_id = client.random() as u16;
+        client
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift32
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// An observation is registered or its registration is outstanding.
+    pub fn is_observing(&self) -> bool {
+        self.observation.is_some()
+    }
+
+    /// Forget the observation, e.g. after the network changed.
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.observation = None;
+    }
+
+    /// Deadline of the outstanding request
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|pending| pending.deadline)
+    }
+
+    /// Confirmable POST of `payload` to `path`, returns the message to send.
+    pub fn post(
+        &mut self,
+        now: Instant,
+        path: &str,
+        content_format: u16,
+        payload: &[u8],
+    ) -> Result<&[u8], ClientError> {
+        self.request(now, RequestKind::Post, Code::POST, path, |writer| {
+            writer.uint_option(option::CONTENT_FORMAT, content_format as u32)?;
+            writer.payload(payload)
+        })
+    }
+
+    /// Confirmable GET with `Observe: 0` for `path`, returns the message to send.
+    pub fn observe(&mut self, now: Instant, path: &str) -> Result<&[u8], ClientError> {
+        self.request(now, RequestKind::Observe, Code::GET, path, |_| Ok(()))?;
+        self.observation = self.pending.map(|pending| Observation {
+            token: pending.token,
+            sequence: None,
+        });
+        Ok(&self.request[..self.request_len])
+    }
+
+    fn request(
+        &mut self,
+        now: Instant,
+        kind: RequestKind,
+        code: Code,
+        path: &str,
+        write_rest: impl FnOnce(&mut MessageWriter) -> Result<(), CoapError>,
+    ) -> Result<&[u8], ClientError> {
+        if self.pending.is_some() {
+            return Err(ClientError::Busy);
+        }
+
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        let token = self.random().to_be_bytes();
+
+        let mut writer = MessageWriter::new(
+            &mut self.request,
+            MessageType::Confirmable,
+            code,
+            message_id,
+            &token,
+        )?;
+        if kind == RequestKind::Observe {
+            writer.uint_option(option::OBSERVE, 0)?;
+        }
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            writer.option(option::URI_PATH, segment.as_bytes())?;
+        }
+        write_rest(&mut writer)?;
+        self.request_len = writer.finish();
+
+        let backoff = Backoff::new(self.random());
+        self.pending = Some(Pending {
+            kind,
+            message_id,
+            token,
+            backoff,
+            deadline: now + backoff.timeout(),
+            acknowledged: false,
+        });
+        Ok(&self.request[..self.request_len])
+    }
+
+    /// Retransmit or give up the outstanding request once its deadline passed.
+    pub fn poll(&mut self, now: Instant) -> Poll<'_> {
+        let Some(pending) = &mut self.pending else {
+            return Poll::Idle;
+        };
+        if now < pending.deadline {
+            return Poll::Idle;
+        }
+
+        let timeout = if pending.acknowledged {
+            None
+        } else {
+            pending.backoff.retransmit()
+        };
+        match timeout {
+            Some(timeout) => {
+                pending.deadline = now + timeout;
+                Poll::Retransmit(&self.request[..self.request_len])
+            }
+            None => {
+                if pending.kind == RequestKind::Observe {
+                    self.observation = None;
+                }
+                self.pending = None;
+                Poll::Failed(Event::Timeout)
+            }
+        }
+    }
+
+    /// Handle a datagram of the server. Returns the event and the length of an acknowledgement
+    /// or reset to send in `out`.
+    pub fn receive<'a>(
+        &mut self,
+        now: Instant,
+        data: &'a [u8],
+        out: &mut [u8],
+    ) -> (Option<Event<'a>>, Option<usize>) {
+        let Ok(message) = Message::parse(data) else {
+            return (None, None);
+        };
+
+        match message.message_type {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                let Some(pending) = &mut self.pending else {
+                    return (None, None);
+                };
+                if pending.message_id != message.message_id {
+                    return (None, None);
+                }
+
+                if message.message_type == MessageType::Reset {
+                    if pending.kind == RequestKind::Observe {
+                        self.observation = None;
+                    }
+                    self.pending = None;
+                    return (Some(Event::Rejected), None);
+                }
+                if message.code == Code::EMPTY {
+                    pending.acknowledged = true;
+                    pending.deadline = now + RESPONSE_TIMEOUT;
+                    return (None, None);
+                }
+                if message.token != pending.token {
+                    return (None, None);
+                }
+
+                (Some(self.complete(&message)), None)
+            }
+            MessageType::Confirmable | MessageType::NonConfirmable => {
+                let confirmable = message.message_type == MessageType::Confirmable;
+
+                if message.code.is_request() || message.code == Code::EMPTY {
+                    // we serve nothing, pings are answered with a reset
+                    let reset =
+                        confirmable.then(|| empty(out, MessageType::Reset, message.message_id));
+                    return (None, reset.flatten());
+                }
+
+                let separate = self
+                    .pending
+                    .filter(|pending| pending.acknowledged && pending.token == message.token);
+                let observed = self
+                    .observation
+                    .filter(|observation| observation.token == message.token);
+
+                let event = if separate.is_some() {
+                    Some(self.complete(&message))
+                } else if observed.is_some() {
+                    self.notification(&message)
+                } else {
+                    // unknown token, e.g. of an observation we forgot, tells the server to stop
+                    return (None, empty(out, MessageType::Reset, message.message_id));
+                };
+
+                let ack = confirmable
+                    .then(|| empty(out, MessageType::Acknowledgement, message.message_id));
+                (event, ack.flatten())
+            }
+        }
+    }
+
+    /// Response to the outstanding request
+    fn complete<'a>(&mut self, response: &Message<'a>) -> Event<'a> {
+        let kind = self.pending.take().map(|pending| pending.kind);
+        if kind != Some(RequestKind::Observe) {
+            return Event::Response(response.code);
+        }
+
+        let sequence = response.uint_option(option::OBSERVE);
+        match (response.code.class(), sequence, &mut self.observation) {
+            (2, Some(sequence), Some(observation)) => {
+                observation.sequence = Some(sequence);
+                Event::Notification(response.payload)
+            }
+            // without an Observe option the server does not support observing the resource
+            _ => {
+                self.observation = None;
+                Event::ObservationEnded(response.code)
+            }
+        }
+    }
+
+    fn notification<'a>(&mut self, message: &Message<'a>) -> Option<Event<'a>> {
+        let observation = self.observation.as_mut()?;
+        if message.code.class() != 2 {
+            // the server ended the observation
+            self.observation = None;
+            return Some(Event::ObservationEnded(message.code));
+        }
+
+        let Some(sequence) = message.uint_option(option::OBSERVE) else {
+            // a response without the option ends the observation as well
+            self.observation = None;
+            return Some(Event::ObservationEnded(message.code));
+        };
+        if observation
+            .sequence
+            .is_some_and(|last| !is_fresher(sequence, last))
+        {
+            debug!("Dropping reordered notification {}", sequence);
+            return None;
+        }
+        observation.sequence = Some(sequence);
+        Some(Event::Notification(message.payload))
+    }
+}
+
+/// Empty acknowledgement or reset of `message_id`
+fn empty(out: &mut [u8], message_type: MessageType, message_id: u16) -> Option<usize> {
+    MessageWriter::new(out, message_type, Code::EMPTY, message_id, &[])
+        .ok()
+        .map(MessageWriter::finish)
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::coap::content_format;
+
+    const PATH: &str = "sensors/telemetry";
+    const REQUEST_SIZE: usize = 128;
+
+    fn response(
+        message_type: MessageType,
+        code: Code,
+        message_id: u16,
+        token: &[u8],
+        observe: Option<u32>,
+        payload: &[u8],
+        buf: &mut [u8],
+    ) -> usize {
+        let mut writer = MessageWriter::new(buf, message_type, code, message_id, token).unwrap();
+        if let Some(sequence) = observe {
+            writer.uint_option(option::OBSERVE, sequence).unwrap();
+        }
+        if !payload.is_empty() {
+            writer.payload(payload).unwrap();
+        }
+        writer.finish()
+    }
+
+    /// Message ID and token of the outstanding request
+    fn request_ids(request: &[u8]) -> (u16, [u8; TOKEN_LEN]) {
+        let message = Message::parse(request).unwrap();
+        (message.message_id, message.token.try_into().unwrap())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_retransmit() {
+        let mut backoff = Backoff::new(u32::MAX);
+        let initial = backoff.timeout();
+        assert!(initial >= ACK_TIMEOUT && initial < ACK_TIMEOUT + ACK_RANDOM_SPREAD);
+
+        let mut expected = initial;
+        for _ in 0..MAX_RETRANSMIT {
+            expected *= 2;
+            assert_eq!(backoff.retransmit(), Some(expected));
+        }
+        assert_eq!(backoff.retransmit(), None);
+    }
+
+    #[test]
+    fn observe_sequence_wraps() {
+        assert!(is_fresher(2, 1));
+        assert!(!is_fresher(1, 2));
+        assert!(!is_fresher(5, 5));
+        assert!(is_fresher(0, (1 << 24) - 1));
+        assert!(!is_fresher((1 << 24) - 1, 0));
+    }
+
+    #[test]
+    fn post_is_confirmable_with_path_and_payload() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let request = client
+            .post(
+                Instant::from_secs(0),
+                PATH,
+                content_format::JSON,
+                b"{\"value\":1}",
+            )
+            .unwrap();
+
+        let message = Message::parse(request).unwrap();
+        assert_eq!(message.message_type, MessageType::Confirmable);
+        assert_eq!(message.code, Code::POST);
+        assert_eq!(message.token.len(), TOKEN_LEN);
+        assert!(message.path().eq(["sensors", "telemetry"]));
+        assert_eq!(
+            message.uint_option(option::CONTENT_FORMAT),
+            Some(content_format::JSON as u32)
+        );
+        assert_eq!(message.payload, b"{\"value\":1}");
+
+        assert_eq!(
+            client.post(Instant::from_secs(0), PATH, content_format::JSON, b"{}"),
+            Err(ClientError::Busy)
+        );
+    }
+
+    #[test]
+    fn piggybacked_response_completes_post() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let request = client
+            .post(Instant::from_secs(0), PATH, content_format::JSON, b"{}")
+            .unwrap();
+        let (message_id, token) = request_ids(request);
+
+        let mut buf = [0; 64];
+        let mut out = [0; 8];
+        // other message ID
+        let len = response(
+            MessageType::Acknowledgement,
+            Code::CHANGED,
+            message_id.wrapping_add(1),
+            &token,
+            None,
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(1), &buf[..len], &mut out),
+            (None, None)
+        );
+        assert!(client.is_busy());
+
+        let len = response(
+            MessageType::Acknowledgement,
+            Code::CHANGED,
+            message_id,
+            &token,
+            None,
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(1), &buf[..len], &mut out),
+            (Some(Event::Response(Code::CHANGED)), None)
+        );
+        assert!(!client.is_busy());
+    }
+
+    #[test]
+    fn retransmits_until_timeout() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let sent: Vec<u8, REQUEST_SIZE> = Vec::from_slice(
+            client
+                .post(Instant::from_secs(0), PATH, content_format::JSON, b"{}")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let mut now = Instant::from_secs(0);
+        assert_eq!(client.poll(now), Poll::Idle);
+        for _ in 0..MAX_RETRANSMIT {
+            now = client.deadline().unwrap();
+            assert_eq!(client.poll(now), Poll::Retransmit(&sent));
+        }
+        // 2 to 3 s times 2^5 - 1
+        assert!(now >= Instant::from_secs(30) && now < Instant::from_secs(45));
+
+        assert_eq!(
+            client.poll(client.deadline().unwrap()),
+            Poll::Failed(Event::Timeout)
+        );
+        assert!(!client.is_busy());
+    }
+
+    #[test]
+    fn separate_response_is_acknowledged() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let request = client
+            .post(Instant::from_secs(0), PATH, content_format::JSON, b"{}")
+            .unwrap();
+        let (message_id, token) = request_ids(request);
+
+        let mut buf = [0; 64];
+        let mut out = [0; 8];
+        let len = response(
+            MessageType::Acknowledgement,
+            Code::EMPTY,
+            message_id,
+            &[],
+            None,
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(1), &buf[..len], &mut out),
+            (None, None)
+        );
+        // no retransmissions after the empty acknowledgement
+        assert_eq!(
+            client.deadline(),
+            Some(Instant::from_secs(1) + RESPONSE_TIMEOUT)
+        );
+
+        let len = response(
+            MessageType::Confirmable,
+            Code::CREATED,
+            0x4242,
+            &token,
+            None,
+            b"",
+            &mut buf,
+        );
+        let (event, ack) = client.receive(Instant::from_secs(2), &buf[..len], &mut out);
+        assert_eq!(event, Some(Event::Response(Code::CREATED)));
+        let ack = Message::parse(&out[..ack.unwrap()]).unwrap();
+        assert_eq!(ack.message_type, MessageType::Acknowledgement);
+        assert_eq!(ack.code, Code::EMPTY);
+        assert_eq!(ack.message_id, 0x4242);
+        assert!(!client.is_busy());
+    }
+
+    #[test]
+    fn reset_rejects_request() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let request = client.observe(Instant::from_secs(0), "commands").unwrap();
+        let (message_id, _) = request_ids(request);
+
+        let mut buf = [0; 64];
+        let mut out = [0; 8];
+        let len = response(
+            MessageType::Reset,
+            Code::EMPTY,
+            message_id,
+            &[],
+            None,
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(1), &buf[..len], &mut out),
+            (Some(Event::Rejected), None)
+        );
+        assert!(!client.is_busy());
+        assert!(!client.is_observing());
+    }
+
+    #[test]
+    fn observe_delivers_fresh_notifications() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let request = client.observe(Instant::from_secs(0), "commands").unwrap();
+        let message = Message::parse(request).unwrap();
+        assert_eq!(message.code, Code::GET);
+        assert_eq!(message.uint_option(option::OBSERVE), Some(0));
+        let (message_id, token) = request_ids(request);
+
+        let mut buf = [0; 64];
+        let mut out = [0; 8];
+        let len = response(
+            MessageType::Acknowledgement,
+            Code::CONTENT,
+            message_id,
+            &token,
+            Some(10),
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(1), &buf[..len], &mut out),
+            (Some(Event::Notification(b"")), None)
+        );
+        assert!(!client.is_busy());
+        assert!(client.is_observing());
+
+        let len = response(
+            MessageType::NonConfirmable,
+            Code::CONTENT,
+            1,
+            &token,
+            Some(12),
+            b"relay on",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(2), &buf[..len], &mut out),
+            (Some(Event::Notification(b"relay on")), None)
+        );
+
+        // reordered
+        let len = response(
+            MessageType::Confirmable,
+            Code::CONTENT,
+            2,
+            &token,
+            Some(11),
+            b"relay off",
+            &mut buf,
+        );
+        let (event, ack) = client.receive(Instant::from_secs(3), &buf[..len], &mut out);
+        assert_eq!(event, None);
+        assert!(ack.is_some());
+
+        // the server ends the observation
+        let len = response(
+            MessageType::NonConfirmable,
+            Code::NOT_FOUND,
+            3,
+            &token,
+            None,
+            b"",
+            &mut buf,
+        );
+        assert_eq!(
+            client.receive(Instant::from_secs(4), &buf[..len], &mut out),
+            (Some(Event::ObservationEnded(Code::NOT_FOUND)), None)
+        );
+        assert!(!client.is_observing());
+    }
+
+    #[test]
+    fn unknown_notification_is_reset() {
+        let mut client = Client::<REQUEST_SIZE>::new(7);
+        let mut buf = [0; 64];
+        let mut out = [0; 8];
+        let len = response(
+            MessageType::Confirmable,
+            Code::CONTENT,
+            0x1234,
+            &[1, 2, 3, 4],
+            Some(5),
+            b"x",
+            &mut buf,
+        );
+
+        let (event, reset) = client.receive(Instant::from_secs(0), &buf[..len], &mut out);
+        assert_eq!(event, None);
+        let reset = Message::parse(&out[..reset.unwrap()]).unwrap();
+        assert_eq!(reset.message_type, MessageType::Reset);
+        assert_eq!(reset.message_id, 0x1234);
+    }
+}
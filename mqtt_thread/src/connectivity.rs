@@ -0,0 +1,113 @@
+use core::net::IpAddr;
+
+use defmt::{debug, Format};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    watch::{Receiver, Watch},
+};
+use openthread::DeviceRole;
+
+/// Maximum number of tasks that can subscribe to connectivity changes at the same time
+/// (e.g. MQTT client, status LED, telemetry and the stack setup itself).
+pub const MAX_SUBSCRIBERS: usize = 4;
+
+static CONNECTIVITY: Watch<CriticalSectionRawMutex, ConnectivityState, MAX_SUBSCRIBERS> =
+    Watch::new();
+
+pub type ConnectivityReceiver =
+    Receiver<'static, CriticalSectionRawMutex, ConnectivityState, MAX_SUBSCRIBERS>;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityState {
+    /// Thread is disabled
+    Disconnected,
+    /// Thread is enabled but the device is not attached to a partition yet
+    Associating,
+    /// Attached to a Thread partition (child, router or leader) but embassy-net has no address yet
+    LinkUp,
+    /// Network is usable with the given address
+    IpAcquired(IpAddr),
+    /// A previously working connection went away
+    Lost(LostReason),
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum LostReason {
+    /// The device detached from its Thread partition
+    Detached,
+    /// Still attached but the configured address is no longer valid
+    AddressLost,
+}
+
+impl ConnectivityState {
+    pub fn is_online(&self) -> bool {
+        matches!(self, ConnectivityState::IpAcquired(_))
+    }
+
+    pub fn is_attached(&self) -> bool {
+        matches!(
+            self,
+            ConnectivityState::LinkUp | ConnectivityState::IpAcquired(_)
+        )
+    }
+}
+
+/// Subscribe to connectivity changes.
+///
+/// Returns `None` if all `MAX_SUBSCRIBERS` receivers are already in use.
+pub fn subscribe() -> Option<ConnectivityReceiver> {
+    CONNECTIVITY.receiver()
+}
+
+/// Last published connectivity state, `Disconnected` if nothing was published yet.
+pub fn current() -> ConnectivityState {
+    CONNECTIVITY
+        .try_get()
+        .unwrap_or(ConnectivityState::Disconnected)
+}
+
+/// Wait until the device is attached to a Thread partition.
+pub async fn wait_attached() -> Option<ConnectivityState> {
+    let mut receiver = subscribe()?;
+
+    Some(receiver.get_and(|state| state.is_attached()).await)
+}
+
+/// Wait until the network is usable and return the configured address.
+pub async fn wait_online() -> Option<IpAddr> {
+    let mut receiver = subscribe()?;
+
+    match receiver.get_and(|state| state.is_online()).await {
+        ConnectivityState::IpAcquired(address) => Some(address),
+        _ => None,
+    }
+}
+
+pub fn publish(state: ConnectivityState) {
+    CONNECTIVITY.sender().send_if_modified(|current| {
+        if *current == Some(state) {
+            return false;
+        }
+
+        debug!("Connectivity: {:?} -> {:?}", current, state);
+        *current = Some(state);
+        true
+    });
+}
+
+/// Map an OpenThread role change onto the connectivity state.
+///
+/// An already configured address is kept as long as the device stays attached.
+pub fn publish_role(role: DeviceRole) {
+    let attached = current().is_attached();
+
+    let state = match role {
+        DeviceRole::Disabled => ConnectivityState::Disconnected,
+        DeviceRole::Detached if attached => ConnectivityState::Lost(LostReason::Detached),
+        DeviceRole::Detached => ConnectivityState::Associating,
+        _ if attached => return,
+        _ => ConnectivityState::LinkUp,
+    };
+
+    publish(state);
+}
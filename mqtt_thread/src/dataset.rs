@@ -0,0 +1,665 @@
+//! Thread operational dataset in the MeshCoP TLV format.
+//!
+//! This is the format of `ot-ctl dataset active -x`, of `thread_dataset` and of the
+//! `THREAD_DATASET` of thread-connect. Decoding it before handing it to OpenThread reports a bad
+//! dataset with a reason instead of an `unwrap` on the device. The module has no dependencies
+//! besides `core`, the `build.rs` of both firmwares includes it to validate the configured
+//! dataset at compile time and the `thread-dataset` host tool uses it as well.
+//!
+//! Only the TLVs of an active or pending dataset that are commonly set are decoded, other TLVs
+//! are skipped and are not encoded again.
+
+use core::fmt;
+
+/// `OT_OPERATIONAL_DATASET_MAX_LENGTH`
+pub const MAX_DATASET_LEN: usize = 254;
+
+pub const NETWORK_NAME_MAX_LEN: usize = 16;
+
+/// TLV types of the Thread specification, chapter 8.10
+pub mod tlv {
+    pub const CHANNEL: u8 = 0;
+    pub const PAN_ID: u8 = 1;
+    pub const EXTENDED_PAN_ID: u8 = 2;
+    pub const NETWORK_NAME: u8 = 3;
+    pub const PSKC: u8 = 4;
+    pub const NETWORK_KEY: u8 = 5;
+    pub const MESH_LOCAL_PREFIX: u8 = 7;
+    pub const SECURITY_POLICY: u8 = 12;
+    pub const ACTIVE_TIMESTAMP: u8 = 14;
+    pub const PENDING_TIMESTAMP: u8 = 51;
+    pub const DELAY_TIMER: u8 = 52;
+    pub const CHANNEL_MASK: u8 = 53;
+
+    /// Name of a TLV type for messages
+    pub fn name(tlv_type: u8) -> &'static str {
+        match tlv_type {
+            CHANNEL => "Channel",
+            PAN_ID => "PAN ID",
+            EXTENDED_PAN_ID => "Extended PAN ID",
+            NETWORK_NAME => "Network Name",
+            PSKC => "PSKc",
+            NETWORK_KEY => "Network Key",
+            MESH_LOCAL_PREFIX => "Mesh Local Prefix",
+            SECURITY_POLICY => "Security Policy",
+            ACTIVE_TIMESTAMP => "Active Timestamp",
+            PENDING_TIMESTAMP => "Pending Timestamp",
+            DELAY_TIMER => "Delay Timer",
+            CHANNEL_MASK => "Channel Mask",
+            _ => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
+pub enum DatasetError {
+    /// Odd number of digits or a character that is no hex digit
+    InvalidHex,
+    /// More than [`MAX_DATASET_LEN`] bytes
+    TooLong,
+    /// A TLV extends beyond the end of the dataset
+    Truncated,
+    /// The value of the TLV type has the wrong length
+    InvalidLength(u8),
+    /// Empty, longer than 16 bytes or no UTF-8
+    InvalidNetworkName,
+    /// Not one of the 2.4 GHz channels 11 to 26 on channel page 0
+    InvalidChannel,
+    /// `0xffff` is the broadcast PAN ID
+    InvalidPanId,
+    /// Mesh-local prefixes are ULA prefixes in `fd00::/8`
+    InvalidMeshLocalPrefix,
+    /// An active dataset needs the TLV type
+    Missing(u8),
+    /// The encode buffer is too small
+    BufferTooSmall,
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetError::InvalidHex => write!(f, "dataset is no hex string"),
+            DatasetError::TooLong => write!(f, "dataset exceeds {} bytes", MAX_DATASET_LEN),
+            DatasetError::Truncated => write!(f, "dataset ends within a TLV"),
+            DatasetError::InvalidLength(t) => write!(f, "{} TLV has a wrong length", tlv::name(*t)),
+            DatasetError::InvalidNetworkName => {
+                write!(f, "network name must be 1 to 16 bytes of UTF-8")
+            }
+            DatasetError::InvalidChannel => write!(f, "channel must be 11 to 26 on page 0"),
+            DatasetError::InvalidPanId => write!(f, "PAN ID 0xffff is reserved for broadcast"),
+            DatasetError::InvalidMeshLocalPrefix => {
+                write!(f, "mesh-local prefix must start with fd")
+            }
+            DatasetError::Missing(t) => write!(f, "{} TLV is missing", tlv::name(*t)),
+            DatasetError::BufferTooSmall => write!(f, "buffer too small for the dataset"),
+        }
+    }
+}
+
+/// A TLV of the dataset, the value is not interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tlv<'a> {
+    pub tlv_type: u8,
+    pub value: &'a [u8],
+}
+
+/// Iterates over the TLVs of an encoded dataset, stops after the first error.
+pub struct TlvIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TlvIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        TlvIter { data }
+    }
+}
+
+impl<'a> Iterator for TlvIter<'a> {
+    type Item = Result<Tlv<'a>, DatasetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.data {
+            [] => None,
+            [tlv_type, len, rest @ ..] if rest.len() >= *len as usize => {
+                let (value, rest) = rest.split_at(*len as usize);
+                self.data = rest;
+                Some(Ok(Tlv {
+                    tlv_type: *tlv_type,
+                    value,
+                }))
+            }
+            _ => {
+                self.data = &[];
+                Some(Err(DatasetError::Truncated))
+            }
+        }
+    }
+}
+
+/// Active or pending timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
+pub struct Timestamp {
+    /// 48 bits of seconds
+    pub seconds: u64,
+    /// 15 bits of 1/32768 seconds
+    pub ticks: u16,
+    pub authoritative: bool,
+}
+
+impl Timestamp {
+    const SECONDS_MAX: u64 = (1 << 48) - 1;
+
+    fn decode(tlv_type: u8, value: &[u8]) -> Result<Self, DatasetError> {
+        let value: [u8; 8] = value
+            .try_into()
+            .map_err(|_| DatasetError::InvalidLength(tlv_type))?;
+        let mut seconds = [0; 8];
+        seconds[2..].copy_from_slice(&value[..6]);
+        let ticks = u16::from_be_bytes([value[6], value[7]]);
+
+        Ok(Timestamp {
+            seconds: u64::from_be_bytes(seconds),
+            ticks: ticks >> 1,
+            authoritative: ticks & 1 == 1,
+        })
+    }
+
+    fn encode(&self) -> [u8; 8] {
+        let mut value = [0; 8];
+        value[..6].copy_from_slice(&(self.seconds & Self::SECONDS_MAX).to_be_bytes()[2..]);
+        let ticks = (self.ticks << 1) | self.authoritative as u16;
+        value[6..].copy_from_slice(&ticks.to_be_bytes());
+        value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
+pub struct Channel {
+    pub page: u8,
+    pub channel: u16,
+}
+
+/// Channels of page 0, channel `n` is bit `31 - n`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
+pub struct ChannelMask(pub u32);
+
+impl ChannelMask {
+    /// All 2.4 GHz channels, 11 to 26
+    pub const ALL: ChannelMask = ChannelMask(0x001f_ffe0);
+
+    pub fn contains(&self, channel: u16) -> bool {
+        channel < 32 && self.0 & (1 << (31 - channel)) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(target_os = "none", derive(defmt::Format))]
+pub struct SecurityPolicy {
+    /// Key rotation time in hours
+    pub rotation_time: u16,
+    /// Policy flags, the second byte is 0 for Thread 1.1 datasets which only have one
+    pub flags: [u8; 2],
+}
+
+impl SecurityPolicy {
+    /// Defaults of `ot-ctl dataset init new`
+    pub const DEFAULT: SecurityPolicy = SecurityPolicy {
+        rotation_time: 672,
+        flags: [0xf7, 0xf8],
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NetworkName {
+    bytes: [u8; NETWORK_NAME_MAX_LEN],
+    len: u8,
+}
+
+impl NetworkName {
+    pub fn new(name: &str) -> Result<Self, DatasetError> {
+        Self::from_bytes(name.as_bytes())
+    }
+
+    fn from_bytes(name: &[u8]) -> Result<Self, DatasetError> {
+        if name.is_empty()
+            || name.len() > NETWORK_NAME_MAX_LEN
+            || core::str::from_utf8(name).is_err()
+        {
+            return Err(DatasetError::InvalidNetworkName);
+        }
+
+        let mut bytes = [0; NETWORK_NAME_MAX_LEN];
+        bytes[..name.len()].copy_from_slice(name);
+        Ok(NetworkName {
+            bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // validated in from_bytes
+        core::str::from_utf8(self.as_bytes()).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for NetworkName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(target_os = "none")]
+impl defmt::Format for NetworkName {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
+
+/// Decoded dataset, every TLV is optional.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Dataset {
+    pub active_timestamp: Option<Timestamp>,
+    pub pending_timestamp: Option<Timestamp>,
+    /// Milliseconds until a pending dataset becomes active
+    pub delay_timer: Option<u32>,
+    pub channel: Option<Channel>,
+    pub channel_mask: Option<ChannelMask>,
+    pub extended_pan_id: Option<[u8; 8]>,
+    pub mesh_local_prefix: Option<[u8; 8]>,
+    pub network_key: Option<[u8; 16]>,
+    pub network_name: Option<NetworkName>,
+    pub pan_id: Option<u16>,
+    pub pskc: Option<[u8; 16]>,
+    pub security_policy: Option<SecurityPolicy>,
+}
+
+fn array<const N: usize>(tlv_type: u8, value: &[u8]) -> Result<[u8; N], DatasetError> {
+    value
+        .try_into()
+        .map_err(|_| DatasetError::InvalidLength(tlv_type))
+}
+
+impl Dataset {
+    pub fn decode(data: &[u8]) -> Result<Self, DatasetError> {
+        if data.len() > MAX_DATASET_LEN {
+            return Err(DatasetError::TooLong);
+        }
+
+        let mut dataset = Dataset::default();
+        for tlv in TlvIter::new(data) {
+            let Tlv { tlv_type, value } = tlv?;
+            match tlv_type {
+                tlv::ACTIVE_TIMESTAMP => {
+                    dataset.active_timestamp = Some(Timestamp::decode(tlv_type, value)?)
+                }
+                tlv::PENDING_TIMESTAMP => {
+                    dataset.pending_timestamp = Some(Timestamp::decode(tlv_type, value)?)
+                }
+                tlv::DELAY_TIMER => {
+                    dataset.delay_timer = Some(u32::from_be_bytes(array(tlv_type, value)?))
+                }
+                tlv::CHANNEL => {
+                    let [page, high, low] = array(tlv_type, value)?;
+                    dataset.channel = Some(Channel {
+                        page,
+                        channel: u16::from_be_bytes([high, low]),
+                    });
+                }
+                tlv::CHANNEL_MASK => dataset.channel_mask = decode_channel_mask(value)?,
+                tlv::EXTENDED_PAN_ID => dataset.extended_pan_id = Some(array(tlv_type, value)?),
+                tlv::MESH_LOCAL_PREFIX => dataset.mesh_local_prefix = Some(array(tlv_type, value)?),
+                tlv::NETWORK_KEY => dataset.network_key = Some(array(tlv_type, value)?),
+                tlv::NETWORK_NAME => dataset.network_name = Some(NetworkName::from_bytes(value)?),
+                tlv::PAN_ID => dataset.pan_id = Some(u16::from_be_bytes(array(tlv_type, value)?)),
+                tlv::PSKC => dataset.pskc = Some(array(tlv_type, value)?),
+                tlv::SECURITY_POLICY => {
+                    let (rotation_time, flags) = match value {
+                        [r0, r1, f0] => ([*r0, *r1], [*f0, 0]),
+                        [r0, r1, f0, f1, ..] => ([*r0, *r1], [*f0, *f1]),
+                        _ => return Err(DatasetError::InvalidLength(tlv_type)),
+                    };
+                    dataset.security_policy = Some(SecurityPolicy {
+                        rotation_time: u16::from_be_bytes(rotation_time),
+                        flags,
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        Ok(dataset)
+    }
+
+    /// Decode a hex string like `thread_dataset`.
+    pub fn from_hex(hex: &str) -> Result<Self, DatasetError> {
+        let mut buf = [0; MAX_DATASET_LEN];
+        let len = decode_hex(hex, &mut buf)?;
+        Self::decode(&buf[..len])
+    }
+
+    /// Check the values and that the TLVs OpenThread needs to attach are present.
+    pub fn validate_active(&self) -> Result<(), DatasetError> {
+        self.active_timestamp
+            .ok_or(DatasetError::Missing(tlv::ACTIVE_TIMESTAMP))?;
+        self.extended_pan_id
+            .ok_or(DatasetError::Missing(tlv::EXTENDED_PAN_ID))?;
+        self.network_key
+            .ok_or(DatasetError::Missing(tlv::NETWORK_KEY))?;
+        self.network_name
+            .ok_or(DatasetError::Missing(tlv::NETWORK_NAME))?;
+
+        let channel = self.channel.ok_or(DatasetError::Missing(tlv::CHANNEL))?;
+        if channel.page != 0 || !(11..=26).contains(&channel.channel) {
+            return Err(DatasetError::InvalidChannel);
+        }
+
+        let pan_id = self.pan_id.ok_or(DatasetError::Missing(tlv::PAN_ID))?;
+        if pan_id == 0xffff {
+            return Err(DatasetError::InvalidPanId);
+        }
+
+        let prefix = self
+            .mesh_local_prefix
+            .ok_or(DatasetError::Missing(tlv::MESH_LOCAL_PREFIX))?;
+        if prefix[0] != 0xfd {
+            return Err(DatasetError::InvalidMeshLocalPrefix);
+        }
+
+        Ok(())
+    }
+
+    /// Encode the decoded TLVs into `buf`, returns the length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, DatasetError> {
+        let mut writer = TlvWriter { buf, len: 0 };
+
+        if let Some(timestamp) = self.active_timestamp {
+            writer.write(tlv::ACTIVE_TIMESTAMP, &timestamp.encode())?;
+        }
+        if let Some(timestamp) = self.pending_timestamp {
+            writer.write(tlv::PENDING_TIMESTAMP, &timestamp.encode())?;
+        }
+        if let Some(delay) = self.delay_timer {
+            writer.write(tlv::DELAY_TIMER, &delay.to_be_bytes())?;
+        }
+        if let Some(channel) = self.channel {
+            let [high, low] = channel.channel.to_be_bytes();
+            writer.write(tlv::CHANNEL, &[channel.page, high, low])?;
+        }
+        if let Some(mask) = self.channel_mask {
+            let [m0, m1, m2, m3] = mask.0.to_be_bytes();
+            writer.write(tlv::CHANNEL_MASK, &[0, 4, m0, m1, m2, m3])?;
+        }
+        if let Some(extended_pan_id) = self.extended_pan_id {
+            writer.write(tlv::EXTENDED_PAN_ID, &extended_pan_id)?;
+        }
+        if let Some(prefix) = self.mesh_local_prefix {
+            writer.write(tlv::MESH_LOCAL_PREFIX, &prefix)?;
+        }
+        if let Some(key) = self.network_key {
+            writer.write(tlv::NETWORK_KEY, &key)?;
+        }
+        if let Some(name) = &self.network_name {
+            writer.write(tlv::NETWORK_NAME, name.as_bytes())?;
+        }
+        if let Some(pan_id) = self.pan_id {
+            writer.write(tlv::PAN_ID, &pan_id.to_be_bytes())?;
+        }
+        if let Some(pskc) = self.pskc {
+            writer.write(tlv::PSKC, &pskc)?;
+        }
+        if let Some(policy) = self.security_policy {
+            let [r0, r1] = policy.rotation_time.to_be_bytes();
+            let [f0, f1] = policy.flags;
+            writer.write(tlv::SECURITY_POLICY, &[r0, r1, f0, f1])?;
+        }
+
+        Ok(writer.len)
+    }
+}
+
+/// Page 0 entry of the channel mask, other pages are not used in 2.4 GHz networks.
+fn decode_channel_mask(value: &[u8]) -> Result<Option<ChannelMask>, DatasetError> {
+    let mut entries = value;
+    while let [page, len, rest @ ..] = entries {
+        let len = *len as usize;
+        if rest.len() < len {
+            return Err(DatasetError::InvalidLength(tlv::CHANNEL_MASK));
+        }
+        if *page == 0 && len == 4 {
+            return Ok(Some(ChannelMask(u32::from_be_bytes(array(
+                tlv::CHANNEL_MASK,
+                &rest[..4],
+            )?))));
+        }
+        entries = &rest[len..];
+    }
+
+    if entries.is_empty() {
+        Ok(None)
+    } else {
+        Err(DatasetError::InvalidLength(tlv::CHANNEL_MASK))
+    }
+}
+
+struct TlvWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl TlvWriter<'_> {
+    fn write(&mut self, tlv_type: u8, value: &[u8]) -> Result<(), DatasetError> {
+        let end = self.len + 2 + value.len();
+        if end > self.buf.len() || end > MAX_DATASET_LEN {
+            return Err(DatasetError::BufferTooSmall);
+        }
+
+        self.buf[self.len] = tlv_type;
+        self.buf[self.len + 1] = value.len() as u8;
+        self.buf[self.len + 2..end].copy_from_slice(value);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Decode `hex` into `buf`, returns the length.
+pub fn decode_hex(hex: &str, buf: &mut [u8]) -> Result<usize, DatasetError> {
+    let hex = hex.trim().as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        return Err(DatasetError::InvalidHex);
+    }
+    let len = hex.len() / 2;
+    if len > buf.len() {
+        return Err(DatasetError::TooLong);
+    }
+
+    let digit = |d: u8| {
+        (d as char)
+            .to_digit(16)
+            .map(|value| value as u8)
+            .ok_or(DatasetError::InvalidHex)
+    };
+    for (byte, [high, low]) in buf.iter_mut().zip(hex.as_chunks::<2>().0) {
+        *byte = (digit(*high)? << 4) | digit(*low)?;
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ot-ctl dataset active -x` of a network created with `dataset init new`
+    const DATASET: &str = concat!(
+        "0e080000000000010000",
+        "000300000f",
+        "35060004001fffe0",
+        "0208dead00beef00cafe",
+        "0708fd000db800a00000",
+        "051000112233445566778899aabbccddeeff",
+        "030f4f70656e5468726561642d31323334",
+        "01021234",
+        "0410a1b2c3d4e5f60718293a4b5c6d7e8f90",
+        "0c0402a0f7f8",
+    );
+
+    #[test]
+    fn decodes_dataset() {
+        let dataset = Dataset::from_hex(DATASET).unwrap();
+
+        assert_eq!(
+            dataset.active_timestamp,
+            Some(Timestamp {
+                seconds: 1,
+                ticks: 0,
+                authoritative: false
+            })
+        );
+        assert_eq!(
+            dataset.channel,
+            Some(Channel {
+                page: 0,
+                channel: 15
+            })
+        );
+        assert_eq!(dataset.channel_mask, Some(ChannelMask::ALL));
+        assert_eq!(
+            dataset.extended_pan_id,
+            Some([0xde, 0xad, 0x00, 0xbe, 0xef, 0x00, 0xca, 0xfe])
+        );
+        assert_eq!(
+            dataset.mesh_local_prefix,
+            Some([0xfd, 0x00, 0x0d, 0xb8, 0x00, 0xa0, 0x00, 0x00])
+        );
+        assert_eq!(dataset.network_key.unwrap()[15], 0xff);
+        assert_eq!(dataset.network_name.unwrap().as_str(), "OpenThread-1234");
+        assert_eq!(dataset.pan_id, Some(0x1234));
+        assert_eq!(dataset.pskc.unwrap()[0], 0xa1);
+        assert_eq!(dataset.security_policy, Some(SecurityPolicy::DEFAULT));
+        assert_eq!(dataset.validate_active(), Ok(()));
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let mut expected = [0; MAX_DATASET_LEN];
+        let expected_len = decode_hex(DATASET, &mut expected).unwrap();
+
+        let mut buf = [0; MAX_DATASET_LEN];
+        let len = Dataset::from_hex(DATASET)
+            .unwrap()
+            .encode(&mut buf)
+            .unwrap();
+
+        assert_eq!(&buf[..len], &expected[..expected_len]);
+    }
+
+    #[test]
+    fn timestamp_round_trips() {
+        let timestamp = Timestamp {
+            seconds: 0x0123_4567_89ab,
+            ticks: 0x7fff,
+            authoritative: true,
+        };
+        let value = timestamp.encode();
+
+        assert_eq!(value, [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xff, 0xff]);
+        assert_eq!(
+            Timestamp::decode(tlv::ACTIVE_TIMESTAMP, &value),
+            Ok(timestamp)
+        );
+    }
+
+    #[test]
+    fn skips_unknown_tlvs() {
+        let dataset = Dataset::from_hex("4a0300000f01021234").unwrap();
+
+        assert_eq!(dataset.pan_id, Some(0x1234));
+        assert_eq!(dataset.channel, None);
+    }
+
+    #[test]
+    fn rejects_malformed_datasets() {
+        assert_eq!(Dataset::from_hex("0e0"), Err(DatasetError::InvalidHex));
+        assert_eq!(Dataset::from_hex("0e0x"), Err(DatasetError::InvalidHex));
+        assert_eq!(Dataset::from_hex("010212"), Err(DatasetError::Truncated));
+        assert_eq!(Dataset::from_hex("01"), Err(DatasetError::Truncated));
+        assert_eq!(
+            Dataset::from_hex("0103123456"),
+            Err(DatasetError::InvalidLength(tlv::PAN_ID))
+        );
+        assert_eq!(
+            Dataset::from_hex("0300"),
+            Err(DatasetError::InvalidNetworkName)
+        );
+        let too_long = [b'0'; 2 * (MAX_DATASET_LEN + 1)];
+        assert_eq!(
+            Dataset::from_hex(core::str::from_utf8(&too_long).unwrap()),
+            Err(DatasetError::TooLong)
+        );
+    }
+
+    #[test]
+    fn validates_active_dataset() {
+        let dataset = Dataset::from_hex(DATASET).unwrap();
+
+        let missing_key = Dataset {
+            network_key: None,
+            ..dataset
+        };
+        assert_eq!(
+            missing_key.validate_active(),
+            Err(DatasetError::Missing(tlv::NETWORK_KEY))
+        );
+
+        let channel_5ghz = Dataset {
+            channel: Some(Channel {
+                page: 0,
+                channel: 36,
+            }),
+            ..dataset
+        };
+        assert_eq!(
+            channel_5ghz.validate_active(),
+            Err(DatasetError::InvalidChannel)
+        );
+
+        let broadcast = Dataset {
+            pan_id: Some(0xffff),
+            ..dataset
+        };
+        assert_eq!(broadcast.validate_active(), Err(DatasetError::InvalidPanId));
+
+        let global_prefix = Dataset {
+            mesh_local_prefix: Some([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0]),
+            ..dataset
+        };
+        assert_eq!(
+            global_prefix.validate_active(),
+            Err(DatasetError::InvalidMeshLocalPrefix)
+        );
+    }
+
+    #[test]
+    fn channel_mask_bits() {
+        assert!(ChannelMask::ALL.contains(11));
+        assert!(ChannelMask::ALL.contains(26));
+        assert!(!ChannelMask::ALL.contains(10));
+        assert!(!ChannelMask::ALL.contains(27));
+    }
+
+    #[test]
+    fn encode_fails_on_small_buffer() {
+        let dataset = Dataset::from_hex(DATASET).unwrap();
+        let mut buf = [0; 16];
+
+        assert_eq!(dataset.encode(&mut buf), Err(DatasetError::BufferTooSmall));
+    }
+}
@@ -0,0 +1,243 @@
+//! Thread device type: minimal end device, router-eligible or leader-capable full device.
+//!
+//! A Minimal Thread Device (MTD) only ever attaches as a child. A Full Thread Device (FTD)
+//! keeps the full network data and a neighbor table, attaches as a child first and upgrades to
+//! a router when the leader hands out a router ID, which strengthens the mesh of mains-powered
+//! nodes. Any router-eligible device becomes the leader of a partition of its own if it finds
+//! no other; OpenThread has no switch to forbid that, the leader weight decides instead which
+//! partition survives a merge. [`DeviceType::RouterEligible`] uses a weight of 0 and gives way
+//! to every [`DeviceType::LeaderCapable`] partition.
+//!
+//! The router APIs are only part of an OpenThread build with `OPENTHREAD_FTD`, the `ftd`
+//! feature selects it in the openthread crate. Routers buffer frames for their children, the
+//! child table is sized per device type, see [`DeviceType::max_children`]. Like
+//! [`crate::joiner`], the OpenThread C API is used directly.
+
+use defmt::{info, Format};
+use openthread::sys::{
+    otError, otError_OT_ERROR_NONE, otInstance, otInstanceInitSingle, otLinkModeConfig,
+    otThreadSetLinkMode,
+};
+use openthread::{DeviceRole, OpenThread};
+
+/// Leader weight of leader-capable devices, the OpenThread default that border routers use
+pub const LEADER_WEIGHT: u8 = 64;
+/// Leader weight of router-eligible devices, loses against every other partition
+pub const ROUTER_WEIGHT: u8 = 0;
+
+/// Children of a leader-capable device, `OPENTHREAD_CONFIG_MLE_MAX_CHILDREN` of the build
+pub const LEADER_MAX_CHILDREN: u16 = 10;
+/// Children of a router-eligible device, leaves message buffers for forwarding
+pub const ROUTER_MAX_CHILDREN: u16 = 4;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceTypeError {
+    /// Not one of `mtd`, `router` or `leader`
+    Unknown,
+    /// Built without the `ftd` feature
+    FtdUnsupported,
+    /// Only a minimal end device can be sleepy
+    SleepyFtd,
+    LinkMode(otError),
+    RouterEligible(otError),
+    MaxChildren(otError),
+}
+
+impl core::fmt::Display for DeviceTypeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Device type error: {:?}", self)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    /// MTD, always a child
+    Minimal,
+    /// FTD that upgrades to a router, but gives way to leader-capable partitions
+    RouterEligible,
+    /// FTD that upgrades to a router and may lead the network
+    LeaderCapable,
+}
+
+/// Flags of the link mode, `otLinkModeConfig` of OpenThread
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct LinkMode {
+    pub rx_on_when_idle: bool,
+    pub full_device: bool,
+    pub full_network_data: bool,
+}
+
+impl DeviceType {
+    pub fn from_config(device_type: &str) -> Result<Self, DeviceTypeError> {
+        match device_type {
+            "" | "mtd" => Ok(DeviceType::Minimal),
+            "router" => Ok(DeviceType::RouterEligible),
+            "leader" => Ok(DeviceType::LeaderCapable),
+            _ => Err(DeviceTypeError::Unknown),
+        }
+    }
+
+    pub fn is_ftd(&self) -> bool {
+        *self != DeviceType::Minimal
+    }
+
+    /// Check the device type against the build and the sleepy end device configuration.
+    pub fn validate(&self, sleepy: bool) -> Result<(), DeviceTypeError> {
+        if !self.is_ftd() {
+            return Ok(());
+        }
+        if sleepy {
+            return Err(DeviceTypeError::SleepyFtd);
+        }
+        if !cfg!(feature = "ftd") {
+            return Err(DeviceTypeError::FtdUnsupported);
+        }
+        Ok(())
+    }
+
+    /// Link mode of an always-on device, a sleepy end device overrides it.
+    pub fn link_mode(&self) -> LinkMode {
+        LinkMode {
+            rx_on_when_idle: true,
+            full_device: self.is_ftd(),
+            full_network_data: self.is_ftd(),
+        }
+    }
+
+    /// Size of the child table, `None` for an MTD, which never has children.
+    pub fn max_children(&self) -> Option<u16> {
+        match self {
+            DeviceType::Minimal => None,
+            DeviceType::RouterEligible => Some(ROUTER_MAX_CHILDREN),
+            DeviceType::LeaderCapable => Some(LEADER_MAX_CHILDREN),
+        }
+    }
+
+    /// `None` for an MTD, which never leads a partition.
+    pub fn leader_weight(&self) -> Option<u8> {
+        match self {
+            DeviceType::Minimal => None,
+            DeviceType::RouterEligible => Some(ROUTER_WEIGHT),
+            DeviceType::LeaderCapable => Some(LEADER_WEIGHT),
+        }
+    }
+
+    /// Whether a device of this type can take the role when attached.
+    pub fn accepts(&self, role: DeviceRole) -> bool {
+        match role {
+            DeviceRole::Child => true,
+            DeviceRole::Router | DeviceRole::Leader => self.is_ftd(),
+            DeviceRole::Disabled | DeviceRole::Detached => false,
+        }
+    }
+}
+
+/// Configure the link mode, router eligibility and child table, has to be called before Thread
+/// is enabled.
+pub fn configure(ot: &OpenThread<'_>, device_type: DeviceType) -> Result<(), DeviceTypeError> {
+    let instance = instance(ot);
+
+    let link_mode = device_type.link_mode();
+    let mut mode: otLinkModeConfig = unsafe { core::mem::zeroed() };
+    mode.set_mRxOnWhenIdle(link_mode.rx_on_when_idle);
+    mode.set_mDeviceType(link_mode.full_device);
+    mode.set_mNetworkData(link_mode.full_network_data);
+    check(unsafe { otThreadSetLinkMode(instance, mode) }).map_err(DeviceTypeError::LinkMode)?;
+
+    #[cfg(feature = "ftd")]
+    {
+        use openthread::sys::{
+            otThreadSetLocalLeaderWeight, otThreadSetMaxAllowedChildren, otThreadSetRouterEligible,
+        };
+
+        check(unsafe { otThreadSetRouterEligible(instance, device_type.is_ftd()) })
+            .map_err(DeviceTypeError::RouterEligible)?;
+        if let Some(weight) = device_type.leader_weight() {
+            unsafe { otThreadSetLocalLeaderWeight(instance, weight) };
+        }
+        if let Some(max_children) = device_type.max_children() {
+            check(unsafe { otThreadSetMaxAllowedChildren(instance, max_children) })
+                .map_err(DeviceTypeError::MaxChildren)?;
+        }
+    }
+
+    info!(
+        "Thread device type {:?}, leader weight {:?}, up to {:?} children",
+        device_type,
+        device_type.leader_weight(),
+        device_type.max_children()
+    );
+    Ok(())
+}
+
+/// The instance of `ot` for the C API. `OpenThread::new` initialised the single instance,
+/// `otInstanceInitSingle` only returns it once it is initialised.
+fn instance(_ot: &OpenThread<'_>) -> *mut otInstance {
+    unsafe { otInstanceInitSingle() }
+}
+
+fn check(error: otError) -> Result<(), otError> {
+    if error == otError_OT_ERROR_NONE {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        assert_eq!(DeviceType::from_config(""), Ok(DeviceType::Minimal));
+        assert_eq!(DeviceType::from_config("mtd"), Ok(DeviceType::Minimal));
+        assert_eq!(
+            DeviceType::from_config("router"),
+            Ok(DeviceType::RouterEligible)
+        );
+        assert_eq!(
+            DeviceType::from_config("leader"),
+            Ok(DeviceType::LeaderCapable)
+        );
+        assert_eq!(
+            DeviceType::from_config("ftd"),
+            Err(DeviceTypeError::Unknown)
+        );
+    }
+
+    #[test]
+    fn sleepy_devices_are_minimal() {
+        assert_eq!(DeviceType::Minimal.validate(true), Ok(()));
+        assert_eq!(
+            DeviceType::RouterEligible.validate(true),
+            Err(DeviceTypeError::SleepyFtd)
+        );
+        let ftd = if cfg!(feature = "ftd") {
+            Ok(())
+        } else {
+            Err(DeviceTypeError::FtdUnsupported)
+        };
+        assert_eq!(DeviceType::LeaderCapable.validate(false), ftd);
+    }
+
+    #[test]
+    fn roles_and_link_modes() {
+        assert!(DeviceType::Minimal.accepts(DeviceRole::Child));
+        assert!(!DeviceType::Minimal.accepts(DeviceRole::Router));
+        assert!(DeviceType::RouterEligible.accepts(DeviceRole::Router));
+        assert!(DeviceType::RouterEligible.accepts(DeviceRole::Leader));
+        assert!(!DeviceType::LeaderCapable.accepts(DeviceRole::Detached));
+
+        assert!(!DeviceType::Minimal.link_mode().full_device);
+        assert!(DeviceType::RouterEligible.link_mode().full_network_data);
+        assert!(
+            DeviceType::LeaderCapable.leader_weight() > DeviceType::RouterEligible.leader_weight()
+        );
+
+        assert_eq!(DeviceType::Minimal.max_children(), None);
+        assert!(
+            DeviceType::LeaderCapable.max_children() > DeviceType::RouterEligible.max_children()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Thread network diagnostics for troubleshooting misbehaving devices.
+//!
+//! [`collect`] reads the state of the Thread interface: role, RLOC16, partition and leader,
+//! channel, the link to the parent and the frame counters of the MAC. The report is logged and
+//! formatted as JSON (see [`Diagnostics::write_json`]) by [`dump`]. mqtt_thread publishes it
+//! periodically and on demand: on the BOOT button, a message on `telemetry/diagnostics/get` or
+//! the [`DUMP_COMMAND`] from the observed CoAP command resource. thread-connect serves it as the
+//! `/sys/diagnostics` CoAP resource.
+//!
+//! The openthread bindings only expose the role, the OpenThread C API is used directly like in
+//! [`crate::joiner`]. The report itself is in [`crate::diagnostics_report`].
+
+use defmt::info;
+use heapless::String;
+use openthread::sys::{
+    otDeviceRole, otDeviceRole_OT_DEVICE_ROLE_CHILD, otDeviceRole_OT_DEVICE_ROLE_DETACHED,
+    otDeviceRole_OT_DEVICE_ROLE_LEADER, otDeviceRole_OT_DEVICE_ROLE_ROUTER, otError_OT_ERROR_NONE,
+    otInstanceInitSingle, otLinkGetChannel, otLinkGetCounters, otRouterInfo, otThreadGetDeviceRole,
+    otThreadGetLeaderRouterId, otThreadGetParentAverageRssi, otThreadGetParentInfo,
+    otThreadGetParentLastRssi, otThreadGetPartitionId, otThreadGetRloc16,
+};
+
+pub use crate::diagnostics_report::{
+    Diagnostics, FrameCounters, Parent, Partition, Role, DIAGNOSTICS_SIZE, DUMP_COMMAND,
+};
+
+fn role_from_raw(role: otDeviceRole) -> Role {
+    #[allow(non_upper_case_globals)]
+    match role {
+        otDeviceRole_OT_DEVICE_ROLE_DETACHED => Role::Detached,
+        otDeviceRole_OT_DEVICE_ROLE_CHILD => Role::Child,
+        otDeviceRole_OT_DEVICE_ROLE_ROUTER => Role::Router,
+        otDeviceRole_OT_DEVICE_ROLE_LEADER => Role::Leader,
+        _ => Role::Disabled,
+    }
+}
+
+/// Read the current state of the Thread interface.
+pub fn collect() -> Diagnostics {
+    let instance = unsafe { otInstanceInitSingle() };
+
+    let role = role_from_raw(unsafe { otThreadGetDeviceRole(instance) });
+    let partition = role.is_attached().then(|| Partition {
+        id: unsafe { otThreadGetPartitionId(instance) },
+        leader_router_id: unsafe { otThreadGetLeaderRouterId(instance) },
+    });
+
+    let parent = if role == Role::Child {
+        let mut info: otRouterInfo = unsafe { core::mem::zeroed() };
+        let mut average_rssi = 0;
+        let mut last_rssi = 0;
+        // the RSSI stays 0 if OpenThread has no measurement yet
+        unsafe {
+            otThreadGetParentAverageRssi(instance, &mut average_rssi);
+            otThreadGetParentLastRssi(instance, &mut last_rssi);
+        }
+        (unsafe { otThreadGetParentInfo(instance, &mut info) } == otError_OT_ERROR_NONE).then(
+            || Parent {
+                rloc16: info.mRloc16,
+                average_rssi,
+                last_rssi,
+                link_quality_in: info.mLinkQualityIn(),
+                link_quality_out: info.mLinkQualityOut(),
+                age_s: info.mAge(),
+            },
+        )
+    } else {
+        None
+    };
+
+    let counters = unsafe { &*otLinkGetCounters(instance) };
+
+    Diagnostics {
+        role,
+        rloc16: unsafe { otThreadGetRloc16(instance) },
+        channel: unsafe { otLinkGetChannel(instance) },
+        partition,
+        parent,
+        frames: FrameCounters {
+            tx_total: counters.mTxTotal,
+            tx_retry: counters.mTxRetry,
+            tx_err_cca: counters.mTxErrCca,
+            tx_err_abort: counters.mTxErrAbort,
+            tx_err_busy_channel: counters.mTxErrBusyChannel,
+            rx_total: counters.mRxTotal,
+            rx_err_no_frame: counters.mRxErrNoFrame,
+            rx_err_unknown_neighbor: counters.mRxErrUnknownNeighbor,
+            rx_err_invalid_src_addr: counters.mRxErrInvalidSrcAddr,
+            rx_err_security: counters.mRxErrSec,
+            rx_err_fcs: counters.mRxErrFcs,
+            rx_err_other: counters.mRxErrOther,
+        },
+    }
+}
+
+/// Collect and log the diagnostics, returns the JSON report.
+pub fn dump() -> String<DIAGNOSTICS_SIZE> {
+    let diagnostics = collect();
+    info!(
+        "Thread diagnostics: {:?}, {} tx and {} rx errors",
+        diagnostics,
+        diagnostics.frames.tx_errors(),
+        diagnostics.frames.rx_errors()
+    );
+
+    let mut report = String::new();
+    // the largest report fits, see the tests of `crate::diagnostics_report`
+    let _ = diagnostics.write_json(&mut report);
+    report
+}
@@ -0,0 +1,281 @@
+//! Thread network diagnostics report and its JSON format.
+//!
+//! Filled by [`crate::diagnostics::collect`], kept apart from the OpenThread calls to build on
+//! the host.
+
+use core::fmt::{self, Write};
+
+use defmt::Format;
+
+/// Largest report
+pub const DIAGNOSTICS_SIZE: usize = 512;
+
+/// Notification of the CoAP command resource requesting a dump
+pub const DUMP_COMMAND: &[u8] = b"diagnostics";
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Disabled,
+    Detached,
+    Child,
+    Router,
+    Leader,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Disabled => "disabled",
+            Role::Detached => "detached",
+            Role::Child => "child",
+            Role::Router => "router",
+            Role::Leader => "leader",
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        matches!(self, Role::Child | Role::Router | Role::Leader)
+    }
+}
+
+/// Thread partition the device is attached to.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub id: u32,
+    pub leader_router_id: u8,
+}
+
+/// Link of a child to its parent router.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Parent {
+    pub rloc16: u16,
+    /// dBm, averaged by OpenThread
+    pub average_rssi: i8,
+    pub last_rssi: i8,
+    /// Link quality 0 to 3 of received and sent frames
+    pub link_quality_in: u8,
+    pub link_quality_out: u8,
+    /// Seconds since the last frame of the parent
+    pub age_s: u8,
+}
+
+/// MAC frame counters since boot.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameCounters {
+    pub tx_total: u32,
+    pub tx_retry: u32,
+    pub tx_err_cca: u32,
+    pub tx_err_abort: u32,
+    pub tx_err_busy_channel: u32,
+    pub rx_total: u32,
+    pub rx_err_no_frame: u32,
+    pub rx_err_unknown_neighbor: u32,
+    pub rx_err_invalid_src_addr: u32,
+    pub rx_err_security: u32,
+    pub rx_err_fcs: u32,
+    pub rx_err_other: u32,
+}
+
+impl FrameCounters {
+    pub fn tx_errors(&self) -> u32 {
+        self.tx_err_cca
+            .saturating_add(self.tx_err_abort)
+            .saturating_add(self.tx_err_busy_channel)
+    }
+
+    pub fn rx_errors(&self) -> u32 {
+        [
+            self.rx_err_no_frame,
+            self.rx_err_unknown_neighbor,
+            self.rx_err_invalid_src_addr,
+            self.rx_err_security,
+            self.rx_err_fcs,
+            self.rx_err_other,
+        ]
+        .into_iter()
+        .fold(0, u32::saturating_add)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub role: Role,
+    pub rloc16: u16,
+    pub channel: u8,
+    /// `None` while not attached
+    pub partition: Option<Partition>,
+    /// Only children have a parent
+    pub parent: Option<Parent>,
+    pub frames: FrameCounters,
+}
+
+impl Diagnostics {
+    /// Compact JSON object, at most [`DIAGNOSTICS_SIZE`] bytes.
+    pub fn write_json(&self, w: &mut dyn Write) -> fmt::Result {
+        write!(
+            w,
+            "{{\"role\":\"{}\",\"rloc16\":\"0x{:04x}\",\"channel\":{},\"partition\":",
+            self.role.name(),
+            self.rloc16,
+            self.channel
+        )?;
+        match self.partition {
+            Some(partition) => write!(
+                w,
+                "{{\"id\":{},\"leader\":{}}}",
+                partition.id, partition.leader_router_id
+            )?,
+            None => w.write_str("null")?,
+        }
+
+        w.write_str(",\"parent\":")?;
+        match self.parent {
+            Some(parent) => write!(
+                w,
+                "{{\"rloc16\":\"0x{:04x}\",\"rssi\":{},\"last_rssi\":{},\"lqi_in\":{},\"lqi_out\":{},\"age\":{}}}",
+                parent.rloc16,
+                parent.average_rssi,
+                parent.last_rssi,
+                parent.link_quality_in,
+                parent.link_quality_out,
+                parent.age_s
+            )?,
+            None => w.write_str("null")?,
+        }
+
+        let frames = &self.frames;
+        write!(
+            w,
+            ",\"tx\":{{\"total\":{},\"retry\":{},\"cca\":{},\"abort\":{},\"busy\":{}}}",
+            frames.tx_total,
+            frames.tx_retry,
+            frames.tx_err_cca,
+            frames.tx_err_abort,
+            frames.tx_err_busy_channel
+        )?;
+        write!(
+            w,
+            ",\"rx\":{{\"total\":{},\"no_frame\":{},\"unknown_neighbor\":{},\"invalid_src\":{},\"security\":{},\"fcs\":{},\"other\":{}}}}}",
+            frames.rx_total,
+            frames.rx_err_no_frame,
+            frames.rx_err_unknown_neighbor,
+            frames.rx_err_invalid_src_addr,
+            frames.rx_err_security,
+            frames.rx_err_fcs,
+            frames.rx_err_other
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+
+    fn child() -> Diagnostics {
+        Diagnostics {
+            role: Role::Child,
+            rloc16: 0x5c01,
+            channel: 15,
+            partition: Some(Partition {
+                id: 1234,
+                leader_router_id: 23,
+            }),
+            parent: Some(Parent {
+                rloc16: 0x5c00,
+                average_rssi: -61,
+                last_rssi: -58,
+                link_quality_in: 3,
+                link_quality_out: 2,
+                age_s: 4,
+            }),
+            frames: FrameCounters {
+                tx_total: 100,
+                tx_retry: 7,
+                tx_err_cca: 2,
+                rx_total: 90,
+                rx_err_fcs: 3,
+                rx_err_other: 1,
+                ..FrameCounters::default()
+            },
+        }
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut json: String<DIAGNOSTICS_SIZE> = String::new();
+        child().write_json(&mut json).unwrap();
+        assert_eq!(
+            json,
+            "{\"role\":\"child\",\"rloc16\":\"0x5c01\",\"channel\":15,\
+             \"partition\":{\"id\":1234,\"leader\":23},\
+             \"parent\":{\"rloc16\":\"0x5c00\",\"rssi\":-61,\"last_rssi\":-58,\"lqi_in\":3,\"lqi_out\":2,\"age\":4},\
+             \"tx\":{\"total\":100,\"retry\":7,\"cca\":2,\"abort\":0,\"busy\":0},\
+             \"rx\":{\"total\":90,\"no_frame\":0,\"unknown_neighbor\":0,\"invalid_src\":0,\"security\":0,\"fcs\":3,\"other\":1}}"
+        );
+
+        let detached = Diagnostics {
+            role: Role::Detached,
+            partition: None,
+            parent: None,
+            ..child()
+        };
+        json.clear();
+        detached.write_json(&mut json).unwrap();
+        assert!(json.starts_with(
+            "{\"role\":\"detached\",\"rloc16\":\"0x5c01\",\"channel\":15,\"partition\":null,\"parent\":null,"
+        ));
+    }
+
+    #[test]
+    fn largest_report_fits() {
+        let largest = Diagnostics {
+            role: Role::Detached,
+            rloc16: u16::MAX,
+            channel: u8::MAX,
+            partition: Some(Partition {
+                id: u32::MAX,
+                leader_router_id: u8::MAX,
+            }),
+            parent: Some(Parent {
+                rloc16: u16::MAX,
+                average_rssi: i8::MIN,
+                last_rssi: i8::MIN,
+                link_quality_in: u8::MAX,
+                link_quality_out: u8::MAX,
+                age_s: u8::MAX,
+            }),
+            frames: FrameCounters {
+                tx_total: u32::MAX,
+                tx_retry: u32::MAX,
+                tx_err_cca: u32::MAX,
+                tx_err_abort: u32::MAX,
+                tx_err_busy_channel: u32::MAX,
+                rx_total: u32::MAX,
+                rx_err_no_frame: u32::MAX,
+                rx_err_unknown_neighbor: u32::MAX,
+                rx_err_invalid_src_addr: u32::MAX,
+                rx_err_security: u32::MAX,
+                rx_err_fcs: u32::MAX,
+                rx_err_other: u32::MAX,
+            },
+        };
+
+        let mut json: String<DIAGNOSTICS_SIZE> = String::new();
+        largest.write_json(&mut json).unwrap();
+    }
+
+    #[test]
+    fn error_counters_saturate() {
+        let frames = FrameCounters {
+            tx_err_cca: u32::MAX,
+            tx_err_abort: 1,
+            rx_err_fcs: 3,
+            rx_err_security: 4,
+            ..FrameCounters::default()
+        };
+        assert_eq!(frames.tx_errors(), u32::MAX);
+        assert_eq!(frames.rx_errors(), 7);
+    }
+}
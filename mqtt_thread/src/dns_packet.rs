@@ -0,0 +1,333 @@
+//! Minimal unicast DNS (RFC 1035) message encoding and decoding for AAAA lookups.
+//!
+//! Names are written uncompressed, compressed names are skipped when parsing. The answer
+//! section is not checked against the question name, so CNAME chains resolved by the server
+//! are followed implicitly.
+
+use core::net::Ipv6Addr;
+
+use defmt::Format;
+use heapless::Vec;
+
+pub const DNS_PORT: u16 = 53;
+
+pub const TYPE_AAAA: u16 = 28;
+
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+
+const HEADER_LEN: usize = 12;
+const MAX_NAME_LEN: usize = 255;
+
+pub const MAX_ANSWERS: usize = 4;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// The packet ended in the middle of a field
+    Truncated,
+    /// The output buffer is too small for the message
+    BufferTooSmall,
+    /// A label is empty, longer than 63 bytes or uses a reserved label type
+    InvalidLabel,
+    /// The name is longer than 255 bytes
+    NameTooLong,
+    /// Not a response to our query
+    UnexpectedId,
+    /// The server answered with the given response code, e.g. 3 (NXDOMAIN)
+    ResponseCode(u8),
+}
+
+impl core::fmt::Display for DnsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "DNS error: {:?}", self)
+    }
+}
+
+/// Encode a recursive query for `name` (e.g. `ipv4only.arpa`), returns the length of the
+/// message.
+pub fn encode_query(id: u16, name: &str, qtype: u16, buf: &mut [u8]) -> Result<usize, DnsError> {
+    let name = name.trim_end_matches('.');
+    if name.len() + 2 > MAX_NAME_LEN {
+        return Err(DnsError::NameTooLong);
+    }
+    let len = HEADER_LEN + name.len() + 2 + 4;
+    if buf.len() < len {
+        return Err(DnsError::BufferTooSmall);
+    }
+
+    buf[..HEADER_LEN].fill(0);
+    buf[0..2].copy_from_slice(&id.to_be_bytes());
+    buf[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    buf[4..6].copy_from_slice(&1u16.to_be_bytes());
+
+    let mut pos = HEADER_LEN;
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError::InvalidLabel);
+        }
+        buf[pos] = label.len() as u8;
+        buf[pos + 1..pos + 1 + label.len()].copy_from_slice(label.as_bytes());
+        pos += 1 + label.len();
+    }
+    buf[pos] = 0;
+    pos += 1;
+
+    buf[pos..pos + 2].copy_from_slice(&qtype.to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(pos + 4)
+}
+
+/// AAAA records and their TTL from the answer section of the response to query `id`.
+pub fn aaaa_records(packet: &[u8], id: u16) -> Result<Vec<(Ipv6Addr, u32), MAX_ANSWERS>, DnsError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DnsError::Truncated);
+    }
+
+    let flags = read_u16(packet, 2)?;
+    if read_u16(packet, 0)? != id || flags & FLAG_RESPONSE == 0 {
+        return Err(DnsError::UnexpectedId);
+    }
+    if flags & RCODE_MASK != 0 {
+        return Err(DnsError::ResponseCode((flags & RCODE_MASK) as u8));
+    }
+
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(packet, pos)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let after_name = skip_name(packet, pos)?;
+        let rtype = read_u16(packet, after_name)?;
+        let class = read_u16(packet, after_name + 2)?;
+        let ttl = read_u32(packet, after_name + 4)?;
+        let rdlen = read_u16(packet, after_name + 8)? as usize;
+        let rdata = packet
+            .get(after_name + 10..after_name + 10 + rdlen)
+            .ok_or(DnsError::Truncated)?;
+
+        if rtype == TYPE_AAAA && class == CLASS_IN && rdlen == 16 {
+            let address = Ipv6Addr::from(<[u8; 16]>::try_from(rdata).unwrap());
+            let _ = records.push((address, ttl));
+        }
+
+        pos = after_name + 10 + rdlen;
+    }
+
+    Ok(records)
+}
+
+/// Position after the (possibly compressed) name at `pos`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Result<usize, DnsError> {
+    loop {
+        let len = *packet.get(pos).ok_or(DnsError::Truncated)?;
+        match len {
+            0 => return Ok(pos + 1),
+            // a compression pointer ends the name
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len if len & 0xc0 != 0 => return Err(DnsError::InvalidLabel),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Result<u16, DnsError> {
+    let bytes = packet.get(pos..pos + 2).ok_or(DnsError::Truncated)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(packet: &[u8], pos: usize) -> Result<u32, DnsError> {
+    let bytes = packet.get(pos..pos + 4).ok_or(DnsError::Truncated)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+    const QUERY: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+        \x08ipv4only\x04arpa\x00\x00\x1c\x00\x01";
+
+    struct Response {
+        buf: [u8; 256],
+        len: usize,
+    }
+
+    impl Response {
+        /// Response with the question of [`QUERY`]
+        fn new(flags: u16, answers: u16) -> Self {
+            let mut response = Self {
+                buf: [0; 256],
+                len: 0,
+            };
+            response = response.bytes(QUERY);
+            response.buf[2..4].copy_from_slice(&flags.to_be_bytes());
+            response.buf[6..8].copy_from_slice(&answers.to_be_bytes());
+            response
+        }
+
+        fn bytes(mut self, bytes: &[u8]) -> Self {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+            self
+        }
+
+        /// Record with the name compressed to the question name at offset 12
+        fn record(self, rtype: u16, ttl: u32, rdata: &[u8]) -> Self {
+            self.bytes(&[0xc0, 12])
+                .bytes(&rtype.to_be_bytes())
+                .bytes(&CLASS_IN.to_be_bytes())
+                .bytes(&ttl.to_be_bytes())
+                .bytes(&(rdata.len() as u16).to_be_bytes())
+                .bytes(rdata)
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    fn well_known(last: u8) -> Ipv6Addr {
+        Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0xc000, last as u16)
+    }
+
+    #[test]
+    fn encodes_queries() {
+        let mut buf = [0; 64];
+        let len = encode_query(ID, "ipv4only.arpa", TYPE_AAAA, &mut buf).unwrap();
+        assert_eq!(&buf[..len], QUERY);
+
+        // a trailing dot is the same name
+        let len = encode_query(ID, "ipv4only.arpa.", TYPE_AAAA, &mut buf).unwrap();
+        assert_eq!(&buf[..len], QUERY);
+
+        assert_eq!(
+            encode_query(ID, "ipv4only.arpa", TYPE_AAAA, &mut buf[..QUERY.len() - 1]),
+            Err(DnsError::BufferTooSmall)
+        );
+        assert_eq!(
+            encode_query(ID, "ipv4only..arpa", TYPE_AAAA, &mut buf),
+            Err(DnsError::InvalidLabel)
+        );
+        let long_label = core::str::from_utf8(&[b'a'; 64]).unwrap();
+        assert_eq!(
+            encode_query(ID, long_label, TYPE_AAAA, &mut [0; 128]),
+            Err(DnsError::InvalidLabel)
+        );
+        // 128 labels `a`, 255 bytes without the trailing dot
+        let labels = [*b"a."; 128];
+        let long_name = core::str::from_utf8(labels.as_flattened()).unwrap();
+        assert_eq!(
+            encode_query(ID, long_name, TYPE_AAAA, &mut [0; 512]),
+            Err(DnsError::NameTooLong)
+        );
+    }
+
+    #[test]
+    fn parses_compressed_aaaa_answers() {
+        let response = Response::new(0x8180, 2)
+            .record(TYPE_AAAA, 3600, &well_known(0xaa).octets())
+            .record(TYPE_AAAA, 600, &well_known(0xab).octets());
+
+        let records = aaaa_records(response.as_slice(), ID).unwrap();
+        assert_eq!(
+            records.as_slice(),
+            &[(well_known(0xaa), 3600), (well_known(0xab), 600)]
+        );
+    }
+
+    #[test]
+    fn follows_uncompressed_cname_chains() {
+        // the CNAME record is skipped, the AAAA record uses an uncompressed name
+        let response = Response::new(0x8180, 2)
+            .record(5, 300, b"\x03nat\x04arpa\x00")
+            .bytes(b"\x03nat\x04arpa\x00")
+            .bytes(&TYPE_AAAA.to_be_bytes())
+            .bytes(&CLASS_IN.to_be_bytes())
+            .bytes(&60u32.to_be_bytes())
+            .bytes(&16u16.to_be_bytes())
+            .bytes(&well_known(0xaa).octets());
+
+        let records = aaaa_records(response.as_slice(), ID).unwrap();
+        assert_eq!(records.as_slice(), &[(well_known(0xaa), 60)]);
+    }
+
+    #[test]
+    fn ignores_other_records() {
+        let response = Response::new(0x8180, 3)
+            .record(1, 3600, &[192, 0, 0, 170])
+            .record(TYPE_AAAA, 3600, &[0; 4])
+            .record(TYPE_AAAA, 3600, &well_known(0xaa).octets());
+
+        let records = aaaa_records(response.as_slice(), ID).unwrap();
+        assert_eq!(records.as_slice(), &[(well_known(0xaa), 3600)]);
+
+        let empty = Response::new(0x8180, 0);
+        assert!(aaaa_records(empty.as_slice(), ID).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_first_answers() {
+        let mut response = Response::new(0x8180, MAX_ANSWERS as u16 + 1);
+        for last in 0..=MAX_ANSWERS as u8 {
+            response = response.record(TYPE_AAAA, 60, &well_known(last).octets());
+        }
+
+        let records = aaaa_records(response.as_slice(), ID).unwrap();
+        assert_eq!(records.len(), MAX_ANSWERS);
+        assert_eq!(
+            records[MAX_ANSWERS - 1].0,
+            well_known(MAX_ANSWERS as u8 - 1)
+        );
+    }
+
+    #[test]
+    fn rejects_unexpected_responses() {
+        let response = Response::new(0x8180, 1).record(TYPE_AAAA, 60, &well_known(0xaa).octets());
+
+        assert_eq!(
+            aaaa_records(response.as_slice(), ID + 1),
+            Err(DnsError::UnexpectedId)
+        );
+        // our own query is no response
+        assert_eq!(aaaa_records(QUERY, ID), Err(DnsError::UnexpectedId));
+
+        let nxdomain = Response::new(0x8183, 0);
+        assert_eq!(
+            aaaa_records(nxdomain.as_slice(), ID),
+            Err(DnsError::ResponseCode(3))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let response = Response::new(0x8180, 1).record(TYPE_AAAA, 60, &well_known(0xaa).octets());
+        let packet = response.as_slice();
+
+        assert_eq!(aaaa_records(&packet[..11], ID), Err(DnsError::Truncated));
+        // in the question, the record header and the address
+        for len in [20, QUERY.len() + 6, packet.len() - 1] {
+            assert_eq!(
+                aaaa_records(&packet[..len], ID),
+                Err(DnsError::Truncated),
+                "length {}",
+                len
+            );
+        }
+
+        let reserved_label = Response::new(0x8180, 1).bytes(&[0x80, 0]);
+        assert_eq!(
+            aaaa_records(reserved_label.as_slice(), ID),
+            Err(DnsError::InvalidLabel)
+        );
+    }
+}
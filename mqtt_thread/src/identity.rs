@@ -0,0 +1,135 @@
+//! IEEE 802.15.4 identity of the device.
+//!
+//! The EUI-64 is derived from the factory MAC address in the eFuses the same way ESP-IDF does
+//! for `ESP_MAC_IEEE802154`: `ff:fe` is inserted between the OUI and the device specific part.
+//! It stays the same across resets and reflashing, so the extended address and the addresses
+//! derived from it stay stable for the border router and allow-lists. A configured EUI-64
+//! takes precedence.
+
+use core::str::FromStr;
+
+use defmt::{info, Format};
+use esp_hal::efuse::Efuse;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityError {
+    /// Expected 16 hex digits, optionally separated by `:` or `-`
+    InvalidEui64,
+}
+
+impl core::fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Invalid EUI-64, expected 16 hex digits")
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Eui64(pub [u8; 8]);
+
+impl Eui64 {
+    /// EUI-64 of a MAC-48 with `ff:fe` inserted after the OUI.
+    pub fn from_mac(mac: [u8; 6]) -> Self {
+        Eui64([mac[0], mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]])
+    }
+}
+
+impl FromStr for Eui64 {
+    type Err = IdentityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut eui64 = [0; 8];
+        let mut digits = s.bytes().filter(|b| *b != b':' && *b != b'-');
+
+        for byte in eui64.iter_mut() {
+            let high = digits.next().ok_or(IdentityError::InvalidEui64)?;
+            let low = digits.next().ok_or(IdentityError::InvalidEui64)?;
+            *byte = (hex_digit(high)? << 4) | hex_digit(low)?;
+        }
+        if digits.next().is_some() {
+            return Err(IdentityError::InvalidEui64);
+        }
+
+        Ok(Eui64(eui64))
+    }
+}
+
+fn hex_digit(digit: u8) -> Result<u8, IdentityError> {
+    (digit as char)
+        .to_digit(16)
+        .map(|value| value as u8)
+        .ok_or(IdentityError::InvalidEui64)
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum IdentitySource {
+    /// Derived from the factory MAC address
+    Factory,
+    /// Set in the configuration
+    Configured,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub eui64: Eui64,
+    pub source: IdentitySource,
+}
+
+impl Identity {
+    /// Identity from `configured` or, if it is empty, the factory MAC address.
+    pub fn load(configured: &str) -> Result<Self, IdentityError> {
+        let identity = if configured.is_empty() {
+            Identity {
+                eui64: Eui64::from_mac(Efuse::mac_address()),
+                source: IdentitySource::Factory,
+            }
+        } else {
+            Identity {
+                eui64: configured.parse()?,
+                source: IdentitySource::Configured,
+            }
+        };
+
+        info!(
+            "IEEE EUI-64 {:02x} ({:?})",
+            identity.eui64.0, identity.source
+        );
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eui64_from_mac_inserts_fffe() {
+        assert_eq!(
+            Eui64::from_mac([0x40, 0x4c, 0xca, 0x12, 0x34, 0x56]),
+            Eui64([0x40, 0x4c, 0xca, 0xff, 0xfe, 0x12, 0x34, 0x56])
+        );
+    }
+
+    #[test]
+    fn parses_eui64_notations() {
+        let expected = Eui64([0x40, 0x4c, 0xca, 0xff, 0xfe, 0x12, 0x34, 0x56]);
+        assert_eq!("404ccafffe123456".parse(), Ok(expected));
+        assert_eq!("40:4c:ca:ff:fe:12:34:56".parse(), Ok(expected));
+        assert_eq!("40-4C-CA-FF-FE-12-34-56".parse(), Ok(expected));
+    }
+
+    #[test]
+    fn rejects_invalid_eui64() {
+        assert_eq!(
+            "404ccafffe1234".parse::<Eui64>(),
+            Err(IdentityError::InvalidEui64)
+        );
+        assert_eq!(
+            "404ccafffe12345678".parse::<Eui64>(),
+            Err(IdentityError::InvalidEui64)
+        );
+        assert_eq!(
+            "404ccafffe12345g".parse::<Eui64>(),
+            Err(IdentityError::InvalidEui64)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Thread commissioning as a Joiner (MeshCoP) instead of a configured dataset.
+//!
+//! The device advertises itself with its PSKd (Pre-Shared Key for the Device), a commissioner
+//! on the border router admits it, e.g. with `ot-ctl commissioner joiner add <eui64> <pskd>`,
+//! and hands over the active operational dataset. OpenThread stores the dataset in its
+//! settings (see [`crate::settings_store`]), later boots attach without commissioning.
+//!
+//! The openthread bindings have no joiner API, the OpenThread C API is used directly. The
+//! instance is the singleton created by `OpenThread::new`, calls are made from the task that
+//! drives the commissioning and never overlap with calls of the bindings on this single core.
+
+use core::ffi::{c_char, c_void};
+
+use defmt::{info, warn, Format};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use openthread::sys::{
+    otDatasetIsCommissioned, otError, otError_OT_ERROR_NONE, otInstanceInitSingle, otJoinerStart,
+};
+
+use crate::secret::Secret;
+
+/// Vendor name sent to the commissioner, the name of the crate including this module
+const VENDOR_NAME: &core::ffi::CStr =
+    match core::ffi::CStr::from_bytes_with_nul(concat!(env!("CARGO_PKG_NAME"), "\0").as_bytes()) {
+        Ok(name) => name,
+        Err(_) => panic!("CARGO_PKG_NAME contains a NUL byte"),
+    };
+
+const PSKD_MIN_LEN: usize = 6;
+const PSKD_MAX_LEN: usize = 32;
+
+const JOIN_ATTEMPTS: u32 = 5;
+const JOIN_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+static JOIN_RESULT: Signal<CriticalSectionRawMutex, otError> = Signal::new();
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum JoinerError {
+    /// 6 to 32 characters of `0-9` and `A-Z` without `I`, `O`, `Q` and `Z`
+    InvalidPskd,
+    /// OpenThread refused to start the joiner, e.g. because Thread is already enabled
+    Start(otError),
+    /// No commissioner admitted the device, e.g. `NotFound` or `Security` for a wrong PSKd
+    Failed(otError),
+}
+
+impl core::fmt::Display for JoinerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Thread joiner error: {:?}", self)
+    }
+}
+
+/// How the device gets the credentials of the Thread network.
+#[derive(Debug, Format, Clone, Copy)]
+pub enum Provisioning<'a> {
+    /// Active operational dataset as TLV hex string
+    Dataset(Secret<&'a str>),
+    /// Commissioning by a commissioner with this PSKd
+    Joiner(Secret<&'a str>),
+}
+
+impl<'a> Provisioning<'a> {
+    /// A configured dataset takes precedence over the PSKd.
+    pub fn from_config(dataset: Secret<&'a str>, pskd: Secret<&'a str>) -> Option<Self> {
+        if !dataset.expose().is_empty() {
+            Some(Provisioning::Dataset(dataset))
+        } else if !pskd.expose().is_empty() {
+            Some(Provisioning::Joiner(pskd))
+        } else {
+            None
+        }
+    }
+}
+
+/// Check the PSKd format of the Thread specification.
+pub fn validate_pskd(pskd: &str) -> Result<(), JoinerError> {
+    let valid_char = |c: u8| {
+        c.is_ascii_digit() || (c.is_ascii_uppercase() && !matches!(c, b'I' | b'O' | b'Q' | b'Z'))
+    };
+
+    if (PSKD_MIN_LEN..=PSKD_MAX_LEN).contains(&pskd.len()) && pskd.bytes().all(valid_char) {
+        Ok(())
+    } else {
+        Err(JoinerError::InvalidPskd)
+    }
+}
+
+/// Whether a dataset was received or configured before, e.g. on an earlier boot.
+pub fn is_commissioned() -> bool {
+    unsafe { otDatasetIsCommissioned(otInstanceInitSingle()) }
+}
+
+unsafe extern "C" fn joiner_callback(error: otError, _context: *mut c_void) {
+    JOIN_RESULT.signal(error);
+}
+
+/// Run the joiner until a commissioner admits the device.
+///
+/// IPv6 has to be enabled and Thread disabled, Thread is enabled by the caller afterwards.
+pub async fn join(pskd: Secret<&str>) -> Result<(), JoinerError> {
+    validate_pskd(pskd.expose())?;
+
+    let mut pskd_c: String<{ PSKD_MAX_LEN + 1 }> = String::new();
+    // cannot fail, the length was validated
+    let _ = pskd_c.push_str(pskd.expose());
+    let _ = pskd_c.push('\0');
+
+    let mut last_error = otError_OT_ERROR_NONE;
+
+    for attempt in 1..=JOIN_ATTEMPTS {
+        info!(
+            "Joining a Thread network (attempt {}/{})",
+            attempt, JOIN_ATTEMPTS
+        );
+
+        JOIN_RESULT.reset();
+        let error = unsafe {
+            otJoinerStart(
+                otInstanceInitSingle(),
+                pskd_c.as_ptr() as *const c_char,
+                core::ptr::null(),
+                VENDOR_NAME.as_ptr(),
+                core::ptr::null(),
+                core::ptr::null(),
+                core::ptr::null(),
+                Some(joiner_callback),
+                core::ptr::null_mut(),
+            )
+        };
+        if error != otError_OT_ERROR_NONE {
+            return Err(JoinerError::Start(error));
+        }
+
+        let error = JOIN_RESULT.wait().await;
+        if error == otError_OT_ERROR_NONE {
+            info!("Commissioned, the dataset is stored in the settings");
+            return Ok(());
+        }
+
+        warn!("Joining failed with OpenThread error {}", error);
+        last_error = error;
+        Timer::after(JOIN_RETRY_INTERVAL).await;
+    }
+
+    Err(JoinerError::Failed(last_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_pskd() {
+        assert_eq!(validate_pskd("J01NME"), Ok(()));
+        assert_eq!(validate_pskd("ABCDEFGH12345678"), Ok(()));
+        assert_eq!(validate_pskd("0123456789ABCDEFGHJKLMNPRSTUVWXY"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_pskd() {
+        assert_eq!(validate_pskd("J01NM"), Err(JoinerError::InvalidPskd));
+        assert_eq!(
+            validate_pskd("0123456789ABCDEFGHJKLMNPRSTUVWXY0"),
+            Err(JoinerError::InvalidPskd)
+        );
+        // lowercase and ambiguous characters
+        assert_eq!(validate_pskd("j01nme"), Err(JoinerError::InvalidPskd));
+        assert_eq!(validate_pskd("J01NMEI"), Err(JoinerError::InvalidPskd));
+        assert_eq!(validate_pskd("J0INME"), Err(JoinerError::InvalidPskd));
+        assert_eq!(validate_pskd("QUIZ99"), Err(JoinerError::InvalidPskd));
+    }
+
+    #[test]
+    fn dataset_takes_precedence() {
+        assert!(matches!(
+            Provisioning::from_config(Secret::new("0e08"), Secret::new("J01NME")),
+            Some(Provisioning::Dataset(_))
+        ));
+        assert!(matches!(
+            Provisioning::from_config(Secret::new(""), Secret::new("J01NME")),
+            Some(Provisioning::Joiner(_))
+        ));
+        assert!(Provisioning::from_config(Secret::new(""), Secret::new("")).is_none());
+    }
+}
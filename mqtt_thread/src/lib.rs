@@ -1 +1,28 @@
 #![no_std]
+
+pub mod address;
+pub mod coap;
+pub mod coap_client;
+pub mod coap_exchange;
+pub mod connectivity;
+pub mod dataset;
+pub mod device_type;
+pub mod diagnostics;
+pub mod diagnostics_report;
+pub mod dns_packet;
+pub mod identity;
+pub mod joiner;
+pub mod nat64;
+pub mod nat64_discovery;
+pub mod ot_settings;
+pub mod resolver;
+// shared with the other examples
+#[path = "../../mqtt_led_relay/src/mqtt_transport.rs"]
+pub mod mqtt_transport;
+#[path = "../../mqtt_led/src/secret.rs"]
+pub mod secret;
+pub mod settings_store;
+pub mod sizing;
+pub mod sleepy;
+pub mod srp;
+pub mod supervisor;
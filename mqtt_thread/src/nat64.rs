@@ -0,0 +1,322 @@
+//! IPv4-embedded IPv6 addresses (RFC 6052) for reaching IPv4 hosts through a NAT64 gateway,
+//! e.g. the one of an OpenThread border router.
+
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+
+use defmt::Format;
+
+/// Octet 8 (bits 64 to 71, the "u" octet) has to be zero and is skipped by the IPv4 address.
+const U_OCTET: usize = 8;
+
+/// Name resolved to discover the NAT64 prefix (RFC 7050)
+pub const IPV4ONLY_ARPA: &str = "ipv4only.arpa";
+
+/// Well-known IPv4 addresses of `ipv4only.arpa` (RFC 7050, section 2.2)
+const IPV4ONLY_ADDRESSES: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// Allowed prefix lengths, in the order they are tried when discovering a prefix
+const PREFIX_LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Nat64Error {
+    /// Only 32, 40, 48, 56, 64 and 96 are allowed
+    InvalidPrefixLength(u8),
+    /// The address is not covered by the prefix
+    PrefixMismatch,
+    /// Not of the form `<address>/<length>`
+    InvalidPrefix,
+}
+
+impl core::fmt::Display for Nat64Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Nat64Error::InvalidPrefixLength(len) => write!(
+                f,
+                "invalid NAT64 prefix length {}, expected one of 32, 40, 48, 56, 64, 96",
+                len
+            ),
+            Nat64Error::PrefixMismatch => write!(f, "address does not match the NAT64 prefix"),
+            Nat64Error::InvalidPrefix => {
+                write!(f, "invalid NAT64 prefix, expected <address>/<length>")
+            }
+        }
+    }
+}
+
+/// A NAT64 prefix with one of the prefix lengths of RFC 6052.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// Bits of `prefix` beyond `len` are cleared.
+    pub fn new(prefix: Ipv6Addr, len: u8) -> Result<Self, Nat64Error> {
+        let start = ipv4_start(len)?;
+
+        let mut octets = prefix.octets();
+        octets[start..].fill(0);
+
+        Ok(Nat64Prefix {
+            prefix: Ipv6Addr::from(octets),
+            len,
+        })
+    }
+
+    pub fn prefix(&self) -> Ipv6Addr {
+        self.prefix
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    pub fn synthesize(&self, ipv4: Ipv4Addr) -> Ipv6Addr {
+        // the length was validated on construction
+        synthesize(self.prefix, self.len, ipv4).unwrap()
+    }
+
+    pub fn extract(&self, ipv6: Ipv6Addr) -> Result<Ipv4Addr, Nat64Error> {
+        extract(self.prefix, self.len, ipv6)
+    }
+}
+
+impl FromStr for Nat64Prefix {
+    type Err = Nat64Error;
+
+    /// Parse `<address>/<length>`, e.g. `64:ff9b::/96`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, len) = s.split_once('/').ok_or(Nat64Error::InvalidPrefix)?;
+        let prefix = prefix.parse().map_err(|_| Nat64Error::InvalidPrefix)?;
+        let len = len.parse().map_err(|_| Nat64Error::InvalidPrefix)?;
+        Nat64Prefix::new(prefix, len)
+    }
+}
+
+/// Find the NAT64 prefix in the AAAA records of `ipv4only.arpa` (RFC 7050, section 3).
+pub fn discover_prefix(addresses: &[Ipv6Addr]) -> Option<Nat64Prefix> {
+    addresses.iter().find_map(|address| {
+        PREFIX_LENGTHS.into_iter().find_map(|len| {
+            let ipv4 = extract(*address, len, *address).ok()?;
+            IPV4ONLY_ADDRESSES
+                .contains(&ipv4)
+                .then(|| Nat64Prefix::new(*address, len).ok())
+                .flatten()
+        })
+    })
+}
+
+/// Embed `ipv4` into the NAT64 prefix, mirrors `Ip6::Address::SynthesizeFromIp4Address`
+/// of OpenThread.
+///
+/// Bits of `prefix` beyond `prefix_len` are ignored, the "u" octet and the suffix are zero.
+pub fn synthesize(
+    prefix: Ipv6Addr,
+    prefix_len: u8,
+    ipv4: Ipv4Addr,
+) -> Result<Ipv6Addr, Nat64Error> {
+    let start = ipv4_start(prefix_len)?;
+
+    let mut octets = [0; 16];
+    octets[..start].copy_from_slice(&prefix.octets()[..start]);
+
+    for (index, byte) in ipv4_indices(start).zip(ipv4.octets()) {
+        octets[index] = byte;
+    }
+
+    Ok(Ipv6Addr::from(octets))
+}
+
+/// Extract the IPv4 address embedded by [`synthesize`].
+pub fn extract(prefix: Ipv6Addr, prefix_len: u8, ipv6: Ipv6Addr) -> Result<Ipv4Addr, Nat64Error> {
+    let start = ipv4_start(prefix_len)?;
+
+    let octets = ipv6.octets();
+    if octets[..start] != prefix.octets()[..start] {
+        return Err(Nat64Error::PrefixMismatch);
+    }
+
+    let mut ipv4 = [0; 4];
+    for (byte, index) in ipv4.iter_mut().zip(ipv4_indices(start)) {
+        *byte = octets[index];
+    }
+
+    Ok(Ipv4Addr::from(ipv4))
+}
+
+/// Index of the first octet of the embedded IPv4 address.
+fn ipv4_start(prefix_len: u8) -> Result<usize, Nat64Error> {
+    match prefix_len {
+        32 | 40 | 48 | 56 | 64 | 96 => Ok(prefix_len as usize / 8),
+        _ => Err(Nat64Error::InvalidPrefixLength(prefix_len)),
+    }
+}
+
+fn ipv4_indices(start: usize) -> impl Iterator<Item = usize> {
+    (start..16).filter(|index| *index != U_OCTET).take(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPV4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 33);
+
+    /// Examples of RFC 6052, section 2.4
+    const EXAMPLES: [(Ipv6Addr, u8, Ipv6Addr); 6] = [
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            32,
+            Ipv6Addr::new(0x2001, 0xdb8, 0xc000, 0x221, 0, 0, 0, 0),
+        ),
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0x100, 0, 0, 0, 0, 0),
+            40,
+            Ipv6Addr::new(0x2001, 0xdb8, 0x1c0, 0x2, 0x21, 0, 0, 0),
+        ),
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0, 0, 0, 0, 0),
+            48,
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0xc000, 0x2, 0x2100, 0, 0),
+        ),
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x300, 0, 0, 0, 0),
+            56,
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x3c0, 0, 0x221, 0, 0),
+        ),
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x344, 0, 0, 0, 0),
+            64,
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x344, 0xc0, 0x2, 0x2100, 0),
+        ),
+        (
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x344, 0, 0, 0, 0),
+            96,
+            Ipv6Addr::new(0x2001, 0xdb8, 0x122, 0x344, 0, 0, 0xc000, 0x221),
+        ),
+    ];
+
+    #[test]
+    fn synthesize_rfc6052_examples() {
+        for (prefix, len, expected) in EXAMPLES {
+            assert_eq!(synthesize(prefix, len, IPV4), Ok(expected), "/{}", len);
+        }
+    }
+
+    #[test]
+    fn extract_rfc6052_examples() {
+        for (prefix, len, address) in EXAMPLES {
+            assert_eq!(extract(prefix, len, address), Ok(IPV4), "/{}", len);
+        }
+    }
+
+    #[test]
+    fn u_octet_is_skipped() {
+        // /40, /48 and /56 split the IPv4 address around octet 8, /64 starts after it
+        for (prefix, len, _) in &EXAMPLES[1..5] {
+            let address = synthesize(*prefix, *len, Ipv4Addr::new(255, 255, 255, 255)).unwrap();
+            assert_eq!(address.octets()[U_OCTET], 0, "/{}", len);
+        }
+    }
+
+    #[test]
+    fn well_known_prefix() {
+        let prefix = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+        let address = synthesize(prefix, 96, IPV4).unwrap();
+        assert_eq!(
+            address,
+            Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0xc000, 0x221)
+        );
+        assert_eq!(extract(prefix, 96, address), Ok(IPV4));
+    }
+
+    #[test]
+    fn bits_beyond_prefix_are_ignored() {
+        let prefix = Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0x2, 0xffff, 0xffff, 0xffff, 0xffff);
+        let address = synthesize(prefix, 64, IPV4).unwrap();
+        assert_eq!(
+            address,
+            Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0x2, 0xc0, 0x2, 0x2100, 0)
+        );
+    }
+
+    #[test]
+    fn invalid_prefix_length() {
+        let prefix = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+        for len in [0, 8, 33, 72, 128] {
+            assert_eq!(
+                synthesize(prefix, len, IPV4),
+                Err(Nat64Error::InvalidPrefixLength(len))
+            );
+            assert_eq!(
+                extract(prefix, len, Ipv6Addr::UNSPECIFIED),
+                Err(Nat64Error::InvalidPrefixLength(len))
+            );
+        }
+    }
+
+    #[test]
+    fn extract_prefix_mismatch() {
+        let prefix = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+        let address = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0xc000, 0x221);
+        assert_eq!(
+            extract(prefix, 96, address),
+            Err(Nat64Error::PrefixMismatch)
+        );
+    }
+
+    #[test]
+    fn discover_well_known_prefix() {
+        let addresses = [
+            Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0xc000, 0xaa),
+            Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0xc000, 0xab),
+        ];
+        let prefix = discover_prefix(&addresses).unwrap();
+        assert_eq!(
+            prefix.prefix(),
+            Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0)
+        );
+        assert_eq!(prefix.prefix_len(), 96);
+    }
+
+    #[test]
+    fn discover_network_specific_prefixes() {
+        for (prefix, len, _) in EXAMPLES {
+            let address = synthesize(prefix, len, Ipv4Addr::new(192, 0, 0, 171)).unwrap();
+            let discovered = discover_prefix(&[address]).unwrap();
+            assert_eq!(discovered.prefix_len(), len);
+            assert_eq!(
+                discovered.synthesize(IPV4),
+                synthesize(prefix, len, IPV4).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn discover_without_nat64() {
+        let address = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        assert_eq!(discover_prefix(&[address]), None);
+        assert_eq!(discover_prefix(&[]), None);
+    }
+
+    #[test]
+    fn parse_prefix() {
+        let prefix: Nat64Prefix = "fdb4:4e7f:4e8d:2::/96".parse().unwrap();
+        assert_eq!(
+            prefix.prefix(),
+            Ipv6Addr::new(0xfdb4, 0x4e7f, 0x4e8d, 0x2, 0, 0, 0, 0)
+        );
+        assert_eq!(prefix.prefix_len(), 96);
+        assert_eq!(
+            "64:ff9b::/80".parse::<Nat64Prefix>(),
+            Err(Nat64Error::InvalidPrefixLength(80))
+        );
+        assert_eq!(
+            "64:ff9b::".parse::<Nat64Prefix>(),
+            Err(Nat64Error::InvalidPrefix)
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Keeps the NAT64 prefix of the Thread network up to date.
+//!
+//! Border routers publish their NAT64 prefix as an external route with the NAT64 flag in the
+//! network data. The openthread bindings do not expose the network data, so it is read with the
+//! OpenThread C API like in [`crate::sleepy`]. Without such a route the prefix is discovered
+//! with an AAAA query for `ipv4only.arpa` (RFC 7050) sent to the DNS server of the border
+//! router. Both are checked again when the answer expires and whenever OpenThread reports a
+//! change, e.g. new network data. The configured prefix is only used while nothing is found.
+
+use core::net::Ipv6Addr;
+
+use defmt::{debug, info, warn, Format};
+use embassy_futures::select::select;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal, watch::Watch};
+use embassy_time::{with_timeout, Duration, Timer};
+use heapless::Vec;
+use openthread::sys::{
+    otError_OT_ERROR_NONE, otExternalRouteConfig, otInstanceInitSingle, otNetDataGetNextRoute,
+    otNetworkDataIterator, OT_NETWORK_DATA_ITERATOR_INIT,
+};
+
+use crate::connectivity;
+use crate::dns_packet::{self, DnsError, DNS_PORT, MAX_ANSWERS, TYPE_AAAA};
+use crate::nat64::{self, Nat64Prefix, IPV4ONLY_ARPA};
+use crate::sizing::DNS_BUFFER_SIZE;
+
+const QUERY_ATTEMPTS: u16 = 3;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Bounds for the TTL of the discovered prefix (RFC 7050, section 3.1)
+const MIN_REDISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_REDISCOVERY_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Changes are reported in bursts (role, addresses, network data), wait for them to settle
+const CHANGE_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+pub const MAX_SUBSCRIBERS: usize = 2;
+
+static PREFIX: Watch<CriticalSectionRawMutex, PrefixState, MAX_SUBSCRIBERS> = Watch::new();
+static NETWORK_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixSource {
+    /// NAT64 route in the network data
+    NetworkData,
+    /// Answer of the border router for `ipv4only.arpa`
+    Discovered,
+    /// `nat64_prefix` of the configuration
+    Configured,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixState {
+    pub prefix: Nat64Prefix,
+    pub source: PrefixSource,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryError {
+    Socket,
+    Timeout,
+    Dns(DnsError),
+    /// The answer contains no address with an embedded well-known IPv4 address
+    NotAdvertised,
+}
+
+impl From<DnsError> for DiscoveryError {
+    fn from(e: DnsError) -> Self {
+        DiscoveryError::Dns(e)
+    }
+}
+
+impl core::fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "NAT64 prefix discovery failed: {:?}", self)
+    }
+}
+
+/// Current NAT64 prefix, `None` until one is discovered or configured.
+pub fn current() -> Option<PrefixState> {
+    PREFIX.try_get()
+}
+
+/// Wait until a NAT64 prefix is known.
+///
+/// Returns `None` if all `MAX_SUBSCRIBERS` receivers are already in use.
+pub async fn wait_prefix() -> Option<PrefixState> {
+    if let Some(state) = current() {
+        return Some(state);
+    }
+
+    Some(PREFIX.receiver()?.get().await)
+}
+
+/// Trigger a new discovery, called whenever OpenThread reports a change.
+pub fn network_changed() {
+    NETWORK_CHANGED.signal(());
+}
+
+fn publish(state: PrefixState) {
+    PREFIX.sender().send_if_modified(|current| {
+        if *current == Some(state) {
+            return false;
+        }
+
+        info!(
+            "NAT64 prefix {}/{} ({:?})",
+            state.prefix.prefix(),
+            state.prefix.prefix_len(),
+            state.source
+        );
+        *current = Some(state);
+        true
+    });
+}
+
+/// Reads the NAT64 prefix from the network data or discovers it via `dns_server`, falls back
+/// to `configured`.
+#[embassy_executor::task]
+pub async fn nat64_task(
+    stack: Stack<'static>,
+    dns_server: Option<Ipv6Addr>,
+    configured: Option<Nat64Prefix>,
+) {
+    let fallback = || {
+        if let Some(prefix) = configured {
+            publish(PrefixState {
+                prefix,
+                source: PrefixSource::Configured,
+            });
+        }
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; DNS_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; DNS_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    let dns_server = match socket.bind(0) {
+        Ok(()) => dns_server,
+        Err(e) => {
+            warn!("Failed to bind NAT64 discovery socket: {:?}", e);
+            None
+        }
+    };
+
+    let mut id: u16 = 0;
+
+    loop {
+        NETWORK_CHANGED.reset();
+
+        if connectivity::wait_online().await.is_none() {
+            warn!("No connectivity receiver available for NAT64 discovery");
+            fallback();
+            return;
+        }
+
+        let next_discovery = if let Some(prefix) = network_data_prefix() {
+            publish(PrefixState {
+                prefix,
+                source: PrefixSource::NetworkData,
+            });
+            // changes of the network data are reported
+            MAX_REDISCOVERY_INTERVAL
+        } else if let Some(dns_server) = dns_server {
+            match discover(&mut socket, dns_server, &mut id).await {
+                Ok((prefix, ttl)) => {
+                    publish(PrefixState {
+                        prefix,
+                        source: PrefixSource::Discovered,
+                    });
+                    Duration::from_secs(ttl as u64)
+                        .max(MIN_REDISCOVERY_INTERVAL)
+                        .min(MAX_REDISCOVERY_INTERVAL)
+                }
+                Err(e) => {
+                    warn!("{:?}", e);
+                    // keep a previously discovered prefix until the network changes
+                    if current().is_none() {
+                        fallback();
+                    }
+                    RETRY_INTERVAL
+                }
+            }
+        } else {
+            fallback();
+            RETRY_INTERVAL
+        };
+
+        select(Timer::after(next_discovery), async {
+            NETWORK_CHANGED.wait().await;
+            Timer::after(CHANGE_SETTLE_TIME).await;
+        })
+        .await;
+    }
+}
+
+/// First external route of the network data flagged as NAT64 prefix.
+fn network_data_prefix() -> Option<Nat64Prefix> {
+    let instance = unsafe { otInstanceInitSingle() };
+    let mut iterator: otNetworkDataIterator = OT_NETWORK_DATA_ITERATOR_INIT;
+    let mut route: otExternalRouteConfig = unsafe { core::mem::zeroed() };
+
+    while unsafe { otNetDataGetNextRoute(instance, &mut iterator, &mut route) }
+        == otError_OT_ERROR_NONE
+    {
+        if !route.mNat64() {
+            continue;
+        }
+
+        let prefix = Ipv6Addr::from(unsafe { route.mPrefix.mPrefix.mFields.m8 });
+        match Nat64Prefix::new(prefix, route.mPrefix.mLength) {
+            Ok(prefix) => return Some(prefix),
+            Err(e) => debug!("Ignoring NAT64 route {}: {:?}", prefix, e),
+        }
+    }
+
+    None
+}
+
+async fn discover(
+    socket: &mut UdpSocket<'_>,
+    dns_server: Ipv6Addr,
+    id: &mut u16,
+) -> Result<(Nat64Prefix, u32), DiscoveryError> {
+    let mut packet = [0; DNS_BUFFER_SIZE];
+    let server = IpEndpoint::new(dns_server.into(), DNS_PORT);
+
+    for _ in 0..QUERY_ATTEMPTS {
+        *id = id.wrapping_add(1);
+        debug!("Querying {} for {}", dns_server, IPV4ONLY_ARPA);
+
+        let len = dns_packet::encode_query(*id, IPV4ONLY_ARPA, TYPE_AAAA, &mut packet)?;
+        socket
+            .send_to(&packet[..len], server)
+            .await
+            .map_err(|_| DiscoveryError::Socket)?;
+
+        let records = with_timeout(QUERY_TIMEOUT, async {
+            loop {
+                let (len, _) = match socket.recv_from(&mut packet).await {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+
+                match dns_packet::aaaa_records(&packet[..len], *id) {
+                    Err(DnsError::UnexpectedId) => continue,
+                    result => return result,
+                }
+            }
+        })
+        .await;
+
+        let records = match records {
+            Ok(records) => records?,
+            Err(_) => continue,
+        };
+
+        let addresses: Vec<Ipv6Addr, MAX_ANSWERS> =
+            records.iter().map(|(address, _)| *address).collect();
+        let ttl = records.iter().map(|(_, ttl)| *ttl).min().unwrap_or(0);
+
+        return nat64::discover_prefix(&addresses)
+            .map(|prefix| (prefix, ttl))
+            .ok_or(DiscoveryError::NotAdvertised);
+    }
+
+    Err(DiscoveryError::Timeout)
+}
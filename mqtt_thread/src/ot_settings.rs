@@ -0,0 +1,39 @@
+//! [`SettingsStore`] as the settings backend of OpenThread.
+
+use defmt::warn;
+use embedded_storage::nor_flash::NorFlash;
+use openthread::{Settings, SettingsError};
+
+use crate::settings_store::{SettingsStore, StoreError};
+
+fn to_settings_error(e: StoreError) -> SettingsError {
+    warn!("Failed to persist OpenThread settings: {:?}", e);
+    SettingsError::NoBufs
+}
+
+impl<F: NorFlash> Settings for SettingsStore<F> {
+    fn clear(&mut self) -> Result<(), SettingsError> {
+        SettingsStore::clear(self).map_err(to_settings_error)
+    }
+
+    fn add(&mut self, key: u16, value: &[u8]) -> Result<(), SettingsError> {
+        SettingsStore::add(self, key, value).map_err(to_settings_error)
+    }
+
+    fn remove(&mut self, key: u16, index: Option<usize>) -> Result<bool, SettingsError> {
+        SettingsStore::remove(self, key, index).map_err(to_settings_error)
+    }
+
+    fn set(&mut self, key: u16, value: &[u8]) -> Result<(), SettingsError> {
+        SettingsStore::set(self, key, value).map_err(to_settings_error)
+    }
+
+    fn get(
+        &mut self,
+        key: u16,
+        index: usize,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, SettingsError> {
+        Ok(SettingsStore::get(self, key, index, buf))
+    }
+}
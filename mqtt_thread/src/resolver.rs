@@ -0,0 +1,78 @@
+//! Resolves host names on the Thread network.
+//!
+//! Queries go to the DNS server of the border router configured in the stack (see
+//! [`crate::supervisor`]). With DNS64 the server synthesizes AAAA records for IPv4-only hosts
+//! itself, otherwise the A record is translated with the current NAT64 prefix. IPv4 and IPv6
+//! literals are accepted as well, so the same `mqtt_fqdn` works on Wi-Fi and Thread builds.
+
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use defmt::{debug, Format};
+use embassy_net::{dns::DnsQueryType, IpAddress, Stack};
+
+use crate::nat64_discovery;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveError {
+    Dns(embassy_net::dns::Error),
+    /// The name has no AAAA record and no record of the expected type
+    NoAddress,
+    /// IPv4 hosts are unreachable without a NAT64 prefix
+    NoNat64Prefix,
+}
+
+impl From<embassy_net::dns::Error> for ResolveError {
+    fn from(e: embassy_net::dns::Error) -> Self {
+        ResolveError::Dns(e)
+    }
+}
+
+impl core::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Failed to resolve host: {:?}", self)
+    }
+}
+
+/// IPv6 address to connect to for `host`, a host name or an IPv4 or IPv6 literal.
+pub async fn resolve(stack: Stack<'static>, host: &str) -> Result<Ipv6Addr, ResolveError> {
+    if let Ok(address) = host.parse::<Ipv6Addr>() {
+        return Ok(address);
+    }
+    if let Ok(address) = host.parse::<Ipv4Addr>() {
+        return synthesize(address).await;
+    }
+
+    match stack.dns_query(host, DnsQueryType::Aaaa).await {
+        Ok(addresses) => {
+            if let Some(address) = addresses.iter().find_map(|address| match address {
+                IpAddress::Ipv6(address) => Some(*address),
+                _ => None,
+            }) {
+                return Ok(address);
+            }
+        }
+        Err(e) => debug!("No AAAA record for {}: {:?}", host, e),
+    }
+
+    // without DNS64 on the border router
+    let addresses = stack.dns_query(host, DnsQueryType::A).await?;
+    let address = addresses
+        .iter()
+        .find_map(|address| match address {
+            IpAddress::Ipv4(address) => Some(*address),
+            _ => None,
+        })
+        .ok_or(ResolveError::NoAddress)?;
+
+    synthesize(address).await
+}
+
+async fn synthesize(address: Ipv4Addr) -> Result<Ipv6Addr, ResolveError> {
+    let state = nat64_discovery::wait_prefix()
+        .await
+        .ok_or(ResolveError::NoNat64Prefix)?;
+    let address = state.prefix.synthesize(address);
+    debug!("Synthesized {} with the NAT64 prefix", address);
+
+    Ok(address)
+}
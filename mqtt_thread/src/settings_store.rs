@@ -0,0 +1,604 @@
+//! Key-value store for the OpenThread settings in a dedicated flash partition.
+//!
+//! The settings are kept in RAM and the whole image is written on every change, which is
+//! rare (attach, role change, every few thousand frames for the frame counters). The partition
+//! holds two banks of one erase sector each. An update always goes to the bank not holding the
+//! current image: the sector is erased, the records are written and the header is written
+//! last. An update interrupted by a reset leaves a bank without a valid header, so the previous
+//! image is loaded on the next boot.
+//!
+//! Bank layout (all integers little endian):
+//!
+//! | offset | size | field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 4    | magic `OTST`                                   |
+//! | 4      | 4    | generation, incremented on every update        |
+//! | 8      | 4    | length of the records                          |
+//! | 12     | 4    | CRC-32 of generation, length and records       |
+//! | 16     | n    | records: key (2), value length (2), value      |
+//!
+//! The partition is `ot_settings` in `partitions.csv`.
+
+use defmt::Format;
+use embedded_storage::nor_flash::NorFlash;
+
+/// Offset of the `ot_settings` partition, has to match `partitions.csv`
+pub const PARTITION_OFFSET: u32 = 0x3f_0000;
+/// One erase sector per bank
+pub const BANK_SIZE: usize = 4096;
+pub const PARTITION_SIZE: usize = 2 * BANK_SIZE;
+
+const MAGIC: [u8; 4] = *b"OTST";
+const HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 4;
+
+/// Space for records in RAM and in each bank
+pub const CAPACITY: usize = BANK_SIZE - HEADER_LEN;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    /// The records do not fit into a bank
+    NoSpace,
+    /// Reading, writing or erasing the flash failed
+    Flash,
+}
+
+impl core::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Settings store error: {:?}", self)
+    }
+}
+
+/// OpenThread settings, keys can have multiple values which are addressed by their index.
+pub struct SettingsStore<F> {
+    flash: F,
+    offset: u32,
+    records: [u8; CAPACITY],
+    len: usize,
+    generation: u32,
+    /// Bank holding the current image, `None` if the partition is empty
+    active: Option<usize>,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    /// Load the latest valid image from the partition at `offset`, starts empty if there is
+    /// none.
+    pub fn new(flash: F, offset: u32) -> Result<Self, StoreError> {
+        const {
+            assert!(BANK_SIZE.is_multiple_of(F::ERASE_SIZE));
+            assert!(HEADER_LEN.is_multiple_of(F::WRITE_SIZE));
+            assert!(CAPACITY.is_multiple_of(F::WRITE_SIZE));
+            assert!(CAPACITY.is_multiple_of(F::READ_SIZE));
+        }
+
+        let mut store = SettingsStore {
+            flash,
+            offset,
+            records: [0; CAPACITY],
+            len: 0,
+            generation: 0,
+            active: None,
+        };
+        store.load()?;
+
+        Ok(store)
+    }
+
+    /// Number of successful updates, useful to estimate flash wear.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Bytes used by the records.
+    pub fn used(&self) -> usize {
+        self.len
+    }
+
+    /// Copy value `index` of `key` into `buf`, truncated if `buf` is too small. Returns the
+    /// full length of the value.
+    pub fn get(&self, key: u16, index: usize, buf: &mut [u8]) -> Option<usize> {
+        let (_, value) = self.records().filter(|(k, _)| *k == key).nth(index)?;
+
+        let len = value.len().min(buf.len());
+        buf[..len].copy_from_slice(&value[..len]);
+
+        Some(value.len())
+    }
+
+    /// Replace all values of `key` with `value`.
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), StoreError> {
+        let previous = (self.records, self.len);
+
+        self.remove_records(key, None);
+        if let Err(e) = self.append(key, value) {
+            (self.records, self.len) = previous;
+            return Err(e);
+        }
+
+        self.commit()
+    }
+
+    /// Add `value` as the last value of `key`.
+    pub fn add(&mut self, key: u16, value: &[u8]) -> Result<(), StoreError> {
+        self.append(key, value)?;
+
+        self.commit()
+    }
+
+    /// Remove value `index` of `key` or all values if `index` is `None`. Returns `false` if
+    /// nothing was removed.
+    pub fn remove(&mut self, key: u16, index: Option<usize>) -> Result<bool, StoreError> {
+        if !self.remove_records(key, index) {
+            return Ok(false);
+        }
+
+        self.commit()?;
+        Ok(true)
+    }
+
+    /// Remove all settings.
+    pub fn clear(&mut self) -> Result<(), StoreError> {
+        self.len = 0;
+
+        self.commit()
+    }
+
+    /// Erase the partition, the next boot starts like a new device.
+    pub fn wipe(&mut self) -> Result<(), StoreError> {
+        factory_reset(&mut self.flash, self.offset)?;
+
+        self.len = 0;
+        self.generation = 0;
+        self.active = None;
+        Ok(())
+    }
+
+    fn records(&self) -> Records<'_> {
+        Records {
+            data: &self.records[..self.len],
+        }
+    }
+
+    fn append(&mut self, key: u16, value: &[u8]) -> Result<(), StoreError> {
+        let end = self.len + RECORD_HEADER_LEN + value.len();
+        if end > CAPACITY || value.len() > u16::MAX as usize {
+            return Err(StoreError::NoSpace);
+        }
+
+        let record = &mut self.records[self.len..end];
+        record[0..2].copy_from_slice(&key.to_le_bytes());
+        record[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        record[RECORD_HEADER_LEN..].copy_from_slice(value);
+        self.len = end;
+
+        Ok(())
+    }
+
+    fn remove_records(&mut self, key: u16, index: Option<usize>) -> bool {
+        let mut removed = false;
+        let mut matches = 0;
+        let mut pos = 0;
+
+        while pos < self.len {
+            let record_key = u16::from_le_bytes([self.records[pos], self.records[pos + 1]]);
+            let value_len = u16::from_le_bytes([self.records[pos + 2], self.records[pos + 3]]);
+            let record_len = RECORD_HEADER_LEN + value_len as usize;
+
+            let remove = record_key == key
+                && match index {
+                    Some(index) => {
+                        matches += 1;
+                        matches - 1 == index
+                    }
+                    None => true,
+                };
+
+            if remove {
+                self.records.copy_within(pos + record_len..self.len, pos);
+                self.len -= record_len;
+                removed = true;
+            } else {
+                pos += record_len;
+            }
+        }
+
+        removed
+    }
+
+    fn load(&mut self) -> Result<(), StoreError> {
+        let mut latest: Option<(usize, u32, usize)> = None;
+
+        for bank in 0..2 {
+            let mut header = [0; HEADER_LEN];
+            self.flash
+                .read(self.bank_offset(bank), &mut header)
+                .map_err(|_| StoreError::Flash)?;
+
+            if header[0..4] != MAGIC {
+                continue;
+            }
+            let generation = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            let len = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+            let crc = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+            if len > CAPACITY {
+                continue;
+            }
+
+            self.flash
+                .read(
+                    self.bank_offset(bank) + HEADER_LEN as u32,
+                    &mut self.records[..padded(len, F::READ_SIZE)],
+                )
+                .map_err(|_| StoreError::Flash)?;
+            if checksum(generation, &self.records[..len]) != crc {
+                continue;
+            }
+
+            let newer = match latest {
+                Some((_, latest_generation, _)) => {
+                    (generation.wrapping_sub(latest_generation) as i32) > 0
+                }
+                None => true,
+            };
+            if newer {
+                latest = Some((bank, generation, len));
+            }
+        }
+
+        match latest {
+            Some((bank, generation, len)) => {
+                // the records of the other bank may still be in the buffer
+                self.flash
+                    .read(
+                        self.bank_offset(bank) + HEADER_LEN as u32,
+                        &mut self.records[..padded(len, F::READ_SIZE)],
+                    )
+                    .map_err(|_| StoreError::Flash)?;
+                self.len = len;
+                self.generation = generation;
+                self.active = Some(bank);
+            }
+            None => {
+                self.len = 0;
+                self.generation = 0;
+                self.active = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the records to the inactive bank, the header is written last.
+    fn commit(&mut self) -> Result<(), StoreError> {
+        let bank = match self.active {
+            Some(bank) => 1 - bank,
+            None => 0,
+        };
+        let generation = self.generation.wrapping_add(1);
+        let offset = self.bank_offset(bank);
+
+        self.flash
+            .erase(offset, offset + BANK_SIZE as u32)
+            .map_err(|_| StoreError::Flash)?;
+
+        // padding is written as erased flash
+        let padded_len = padded(self.len, F::WRITE_SIZE);
+        self.records[self.len..padded_len].fill(0xff);
+        self.flash
+            .write(offset + HEADER_LEN as u32, &self.records[..padded_len])
+            .map_err(|_| StoreError::Flash)?;
+
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&generation.to_le_bytes());
+        header[8..12].copy_from_slice(&(self.len as u32).to_le_bytes());
+        header[12..16]
+            .copy_from_slice(&checksum(generation, &self.records[..self.len]).to_le_bytes());
+        self.flash
+            .write(offset, &header)
+            .map_err(|_| StoreError::Flash)?;
+
+        self.generation = generation;
+        self.active = Some(bank);
+        Ok(())
+    }
+
+    fn bank_offset(&self, bank: usize) -> u32 {
+        self.offset + (bank * BANK_SIZE) as u32
+    }
+}
+
+/// Erase the settings partition at `offset`.
+///
+/// Usable while OpenThread owns the [`SettingsStore`], the device has to be reset right after.
+pub fn factory_reset<F: NorFlash>(flash: &mut F, offset: u32) -> Result<(), StoreError> {
+    flash
+        .erase(offset, offset + PARTITION_SIZE as u32)
+        .map_err(|_| StoreError::Flash)
+}
+
+struct Records<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < RECORD_HEADER_LEN {
+            return None;
+        }
+
+        let key = u16::from_le_bytes([self.data[0], self.data[1]]);
+        let len = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
+        let value = self.data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+        self.data = &self.data[RECORD_HEADER_LEN + len..];
+
+        Some((key, value))
+    }
+}
+
+fn padded(len: usize, align: usize) -> usize {
+    len.div_ceil(align) * align
+}
+
+fn checksum(generation: u32, records: &[u8]) -> u32 {
+    let crc = crc32_update(0xffff_ffff, &generation.to_le_bytes());
+    let crc = crc32_update(crc, &(records.len() as u32).to_le_bytes());
+    !crc32_update(crc, records)
+}
+
+/// CRC-32 (IEEE 802.3), bitwise to avoid a 1 KiB table
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+    use heapless::Vec;
+
+    const OFFSET: u32 = 0x1000;
+
+    /// NOR flash in RAM, writes can only clear bits. Fails all writes after `writes_left`
+    /// reaches zero to simulate a reset in the middle of an update.
+    struct MemFlash {
+        data: [u8; OFFSET as usize + PARTITION_SIZE],
+        writes_left: Option<usize>,
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            MemFlash {
+                data: [0xff; OFFSET as usize + PARTITION_SIZE],
+                writes_left: None,
+            }
+        }
+    }
+
+    impl ErrorType for MemFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MemFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MemFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 4096;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            assert_eq!(from as usize % Self::ERASE_SIZE, 0);
+            assert_eq!(to as usize % Self::ERASE_SIZE, 0);
+            self.data[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+            assert_eq!(bytes.len() % Self::WRITE_SIZE, 0);
+            if let Some(writes_left) = self.writes_left.as_mut() {
+                if *writes_left == 0 {
+                    return Err(NorFlashErrorKind::Other);
+                }
+                *writes_left -= 1;
+            }
+
+            let offset = offset as usize;
+            for (cell, byte) in self.data[offset..offset + bytes.len()]
+                .iter_mut()
+                .zip(bytes)
+            {
+                *cell &= *byte;
+            }
+            Ok(())
+        }
+    }
+
+    fn reload(store: SettingsStore<MemFlash>) -> SettingsStore<MemFlash> {
+        let mut flash = store.flash;
+        flash.writes_left = None;
+        SettingsStore::new(flash, OFFSET).unwrap()
+    }
+
+    fn get(store: &SettingsStore<MemFlash>, key: u16, index: usize) -> Option<([u8; 16], usize)> {
+        let mut buf = [0; 16];
+        store.get(key, index, &mut buf).map(|len| (buf, len))
+    }
+
+    fn value(store: &SettingsStore<MemFlash>, key: u16, index: usize) -> Option<Vec<u8, 16>> {
+        get(store, key, index).map(|(buf, len)| Vec::from_slice(&buf[..len]).unwrap())
+    }
+
+    #[test]
+    fn empty_partition_starts_empty() {
+        let store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        assert_eq!(store.used(), 0);
+        assert_eq!(store.generation(), 0);
+        assert_eq!(value(&store, 1, 0), None);
+    }
+
+    #[test]
+    fn settings_survive_reboot() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        // active dataset, network info and a child
+        store.set(1, &[0x0e, 0x08, 0, 0, 0, 0, 0, 1]).unwrap();
+        store.set(3, b"frame counters").unwrap();
+        store.add(6, b"child 1").unwrap();
+        store.add(6, b"child 2").unwrap();
+
+        let store = reload(store);
+        assert_eq!(store.generation(), 4);
+        assert_eq!(value(&store, 1, 0).unwrap(), [0x0e, 0x08, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(value(&store, 3, 0).unwrap(), b"frame counters");
+        assert_eq!(value(&store, 6, 0).unwrap(), b"child 1");
+        assert_eq!(value(&store, 6, 1).unwrap(), b"child 2");
+        assert_eq!(value(&store, 6, 2), None);
+    }
+
+    #[test]
+    fn set_replaces_all_values() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.add(6, b"a").unwrap();
+        store.add(7, b"other").unwrap();
+        store.add(6, b"b").unwrap();
+        store.set(6, b"c").unwrap();
+
+        assert_eq!(value(&store, 6, 0).unwrap(), b"c");
+        assert_eq!(value(&store, 6, 1), None);
+        assert_eq!(value(&store, 7, 0).unwrap(), b"other");
+    }
+
+    #[test]
+    fn remove_by_index_and_all() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.add(6, b"a").unwrap();
+        store.add(6, b"b").unwrap();
+        store.add(6, b"c").unwrap();
+
+        assert!(store.remove(6, Some(1)).unwrap());
+        assert_eq!(value(&store, 6, 0).unwrap(), b"a");
+        assert_eq!(value(&store, 6, 1).unwrap(), b"c");
+        assert!(!store.remove(6, Some(2)).unwrap());
+
+        assert!(store.remove(6, None).unwrap());
+        assert_eq!(value(&store, 6, 0), None);
+        assert!(!store.remove(6, None).unwrap());
+    }
+
+    #[test]
+    fn get_truncates_but_reports_full_length() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.set(1, b"0123456789").unwrap();
+
+        let mut buf = [0; 4];
+        assert_eq!(store.get(1, 0, &mut buf), Some(10));
+        assert_eq!(&buf, b"0123");
+    }
+
+    #[test]
+    fn interrupted_update_keeps_previous_image() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.set(1, b"old").unwrap();
+        store.set(2, b"kept").unwrap();
+
+        // records are written, the header is not
+        store.flash.writes_left = Some(1);
+        assert_eq!(store.set(1, b"new"), Err(StoreError::Flash));
+
+        let store = reload(store);
+        assert_eq!(value(&store, 1, 0).unwrap(), b"old");
+        assert_eq!(value(&store, 2, 0).unwrap(), b"kept");
+        assert_eq!(store.generation(), 2);
+    }
+
+    #[test]
+    fn corrupted_bank_is_ignored() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.set(1, b"first").unwrap();
+        store.set(1, b"second").unwrap();
+
+        // flip a bit in the records of the newer bank
+        let mut flash = store.flash;
+        flash.data[OFFSET as usize + BANK_SIZE + HEADER_LEN + RECORD_HEADER_LEN] &= 0xfe;
+
+        let store = SettingsStore::new(flash, OFFSET).unwrap();
+        assert_eq!(value(&store, 1, 0).unwrap(), b"first");
+    }
+
+    #[test]
+    fn updates_alternate_between_banks() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        for i in 0..5u8 {
+            store.set(1, &[i]).unwrap();
+            assert_eq!(store.active, Some(i as usize % 2));
+        }
+
+        let store = reload(store);
+        assert_eq!(value(&store, 1, 0).unwrap(), [4]);
+    }
+
+    #[test]
+    fn generation_wraps_around() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.generation = u32::MAX - 1;
+        store.set(1, b"a").unwrap();
+        store.set(1, b"b").unwrap();
+        assert_eq!(store.generation(), 0);
+
+        let store = reload(store);
+        assert_eq!(value(&store, 1, 0).unwrap(), b"b");
+    }
+
+    #[test]
+    fn full_store_rejects_values_and_keeps_state() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.set(1, &[0xaa; CAPACITY - RECORD_HEADER_LEN]).unwrap();
+
+        assert_eq!(store.add(2, b"x"), Err(StoreError::NoSpace));
+        assert_eq!(
+            store.set(1, &[0xbb; CAPACITY - RECORD_HEADER_LEN + 1]),
+            Err(StoreError::NoSpace)
+        );
+        assert_eq!(get(&store, 1, 0).unwrap().1, CAPACITY - RECORD_HEADER_LEN);
+
+        store.clear().unwrap();
+        store.add(2, b"x").unwrap();
+    }
+
+    #[test]
+    fn factory_reset_erases_partition() {
+        let mut store = SettingsStore::new(MemFlash::new(), OFFSET).unwrap();
+        store.set(1, b"dataset").unwrap();
+        store.set(2, b"network").unwrap();
+
+        store.wipe().unwrap();
+        assert_eq!(value(&store, 1, 0), None);
+
+        let store = reload(store);
+        assert_eq!(store.used(), 0);
+        assert_eq!(value(&store, 2, 0), None);
+    }
+}
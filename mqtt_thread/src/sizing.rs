@@ -0,0 +1,128 @@
+//! Socket counts and buffer sizes of all network services in one place.
+//!
+//! Every service adds an entry to [`SERVICES`], the number of sockets of the embassy-net stack
+//! is derived from it. Socket buffers live in the futures of the tasks, i.e. in the executor
+//! arena, so their sum is checked against [`TASK_ARENA_SIZE`] at compile time. The heap is
+//! used by OpenThread and the radio driver, it grows with the `ftd` feature for routers.
+//!
+//! The TCP buffers default to 4 KiB each, the `small-tcp-buffers` feature reduces them to
+//! 1 KiB (one full-sized segment over 6LoWPAN) to make room for further services.
+
+use defmt::{info, Format};
+use embassy_net::StackResources;
+
+/// Size of the esp-alloc heap.
+pub const HEAP_SIZE: usize = 72 * 1024 + ROUTER_HEAP_SIZE;
+
+/// Heap for the OpenThread messages of a router, frames queued for its children and forwarded
+/// for its neighbors. The child table is sized per device type, see
+/// [`crate::device_type::DeviceType::max_children`].
+pub const ROUTER_HEAP_SIZE: usize = if cfg!(feature = "ftd") { 24 * 1024 } else { 0 };
+
+/// Has to match the `task-arena-size-*` feature of embassy-executor in Cargo.toml.
+pub const TASK_ARENA_SIZE: usize = 20480;
+
+/// Part of the arena kept free for the state of the futures besides the socket buffers.
+const TASK_ARENA_RESERVE: usize = 4096;
+
+/// MTU of the Thread network interface.
+pub const IPV6_PACKET_SIZE: usize = 1280;
+
+pub const TCP_RX_BUFFER_SIZE: usize = if cfg!(feature = "small-tcp-buffers") { 1024 } else { 4096 };
+pub const TCP_TX_BUFFER_SIZE: usize = if cfg!(feature = "small-tcp-buffers") { 1024 } else { 4096 };
+
+/// Largest diagnostics report, see [`crate::diagnostics`].
+pub use crate::diagnostics::DIAGNOSTICS_SIZE;
+
+/// Size of the MQTT packet buffers, fits the diagnostics report and its topic.
+pub const MQTT_BUFFER_SIZE: usize = DIAGNOSTICS_SIZE + 64;
+
+/// Largest DNS message sent or received, larger responses are dropped.
+pub const DNS_BUFFER_SIZE: usize = 256;
+
+/// Largest CoAP request of the client, the header, token and path besides the diagnostics
+/// report.
+pub const COAP_REQUEST_SIZE: usize = DIAGNOSTICS_SIZE + 64;
+
+/// Largest CoAP response or notification received by the client.
+pub const COAP_RESPONSE_SIZE: usize = 256;
+
+/// Memory used by one network service.
+#[derive(Debug, Format, Clone, Copy)]
+pub struct Service {
+    pub name: &'static str,
+    /// Sockets of the embassy-net stack
+    pub sockets: usize,
+    /// Socket and packet buffers in the executor arena
+    pub buffers: usize,
+}
+
+pub const SERVICES: &[Service] = &[
+    Service {
+        name: "mqtt",
+        sockets: 1,
+        buffers: TCP_RX_BUFFER_SIZE + TCP_TX_BUFFER_SIZE + 2 * MQTT_BUFFER_SIZE,
+    },
+    Service {
+        name: "dns",
+        sockets: 1,
+        buffers: 0,
+    },
+    Service {
+        name: "nat64-discovery",
+        sockets: 1,
+        buffers: 3 * DNS_BUFFER_SIZE,
+    },
+    Service {
+        name: "coap",
+        sockets: 1,
+        // socket buffers, the received datagram and the request kept for retransmissions
+        buffers: 2 * COAP_REQUEST_SIZE + 2 * COAP_RESPONSE_SIZE,
+    },
+];
+
+/// Sockets of the embassy-net stack.
+pub const STACK_SOCKETS: usize = {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < SERVICES.len() {
+        sum += SERVICES[i].sockets;
+        i += 1;
+    }
+    sum
+};
+
+/// Socket buffers of all services in the executor arena.
+pub const ARENA_BUFFERS: usize = {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < SERVICES.len() {
+        sum += SERVICES[i].buffers;
+        i += 1;
+    }
+    sum
+};
+
+const _: () = assert!(
+    ARENA_BUFFERS + TASK_ARENA_RESERVE <= TASK_ARENA_SIZE,
+    "network buffers exceed the executor arena, enable `small-tcp-buffers` or increase the arena"
+);
+
+/// Log the memory budget of the network services.
+pub fn log_budget() {
+    for service in SERVICES {
+        info!(
+            "Network service {}: {} socket(s), {} bytes of buffers",
+            service.name, service.sockets, service.buffers
+        );
+    }
+    info!(
+        "Network budget: {} sockets ({} bytes static), {}/{} bytes of arena ({} reserved), {} bytes of heap",
+        STACK_SOCKETS,
+        core::mem::size_of::<StackResources<STACK_SOCKETS>>(),
+        ARENA_BUFFERS,
+        TASK_ARENA_SIZE,
+        TASK_ARENA_RESERVE,
+        HEAP_SIZE,
+    );
+}
@@ -0,0 +1,259 @@
+//! Sleepy End Device (SED) operation for battery powered nodes.
+//!
+//! A SED keeps its receiver off while idle (`rx-on-when-idle` cleared in the link mode) and
+//! polls its parent for buffered frames every poll period. With the `csl` feature and an
+//! OpenThread build with `OPENTHREAD_CONFIG_MAC_CSL_RECEIVER_ENABLE` the device additionally
+//! samples the channel every CSL period, so the parent can send without waiting for a poll.
+//!
+//! Application work is batched around the wake-ups: [`next_window`] rounds deadlines up to the
+//! next poll, so the CPU and radio wake once for both. [`DutyCycle`] measures the time the
+//! application is awake and reads the MAC counters of OpenThread for the telemetry.
+//!
+//! The openthread bindings have no API for the link mode and the poll period, the OpenThread C
+//! API is used directly like in [`crate::joiner`].
+
+use defmt::{info, Format};
+use embassy_time::{Duration, Instant};
+use openthread::sys::{
+    otError, otError_OT_ERROR_NONE, otInstanceInitSingle, otLinkGetCounters, otLinkModeConfig,
+    otLinkSetPollPeriod, otThreadSetLinkMode,
+};
+
+/// Shortest poll period OpenThread accepts, `OPENTHREAD_CONFIG_MAC_MINIMUM_POLL_PERIOD`
+pub const MIN_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum SleepyError {
+    /// Shorter than [`MIN_POLL_PERIOD`]
+    InvalidPollPeriod,
+    LinkMode(otError),
+    PollPeriod(otError),
+    /// CSL is not compiled into OpenThread or the period is invalid
+    Csl(otError),
+}
+
+impl core::fmt::Display for SleepyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Sleepy end device error: {:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepyConfig {
+    pub poll_period: Duration,
+    /// Only used with the `csl` feature
+    pub csl_period: Option<Duration>,
+}
+
+impl SleepyConfig {
+    /// `None` for an always-on child if `poll_period_ms` is 0.
+    pub fn from_config(poll_period_ms: u32, csl_period_ms: u32) -> Option<Self> {
+        if poll_period_ms == 0 {
+            return None;
+        }
+
+        Some(SleepyConfig {
+            poll_period: Duration::from_millis(poll_period_ms as u64),
+            csl_period: (csl_period_ms > 0).then(|| Duration::from_millis(csl_period_ms as u64)),
+        })
+    }
+}
+
+/// Configure the link mode and poll period, has to be called before Thread is enabled.
+pub fn enable(config: &SleepyConfig) -> Result<(), SleepyError> {
+    if config.poll_period < MIN_POLL_PERIOD {
+        return Err(SleepyError::InvalidPollPeriod);
+    }
+
+    let instance = unsafe { otInstanceInitSingle() };
+
+    // all flags cleared: receiver off while idle, minimal device, stable network data only
+    let mode: otLinkModeConfig = unsafe { core::mem::zeroed() };
+    check(unsafe { otThreadSetLinkMode(instance, mode) }).map_err(SleepyError::LinkMode)?;
+
+    let poll_period_ms = config.poll_period.as_millis() as u32;
+    check(unsafe { otLinkSetPollPeriod(instance, poll_period_ms) })
+        .map_err(SleepyError::PollPeriod)?;
+
+    #[cfg(feature = "csl")]
+    if let Some(csl_period) = config.csl_period {
+        let csl_period_us = csl_period.as_micros() as u32;
+        check(unsafe { openthread::sys::otLinkSetCslPeriod(instance, csl_period_us) })
+            .map_err(SleepyError::Csl)?;
+    }
+
+    info!(
+        "Sleepy end device, poll period {} ms, CSL period {:?} ms",
+        poll_period_ms,
+        config
+            .csl_period
+            .filter(|_| cfg!(feature = "csl"))
+            .map(|period| period.as_millis())
+    );
+    Ok(())
+}
+
+fn check(error: otError) -> Result<(), otError> {
+    if error == otError_OT_ERROR_NONE {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// `deadline` rounded up to the next poll after `epoch`.
+///
+/// The parent only delivers frames at polls, waking up in between gains nothing.
+pub fn next_window(deadline: Instant, epoch: Instant, poll_period: Duration) -> Instant {
+    if deadline <= epoch || poll_period.as_ticks() == 0 {
+        return deadline;
+    }
+
+    let since_epoch = (deadline - epoch).as_ticks();
+    let polls = since_epoch.div_ceil(poll_period.as_ticks());
+    epoch + Duration::from_ticks(polls * poll_period.as_ticks())
+}
+
+/// Duty cycle of the application since boot.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycleStats {
+    /// Application wake-ups
+    pub wakeups: u32,
+    /// Time awake in the wake-ups
+    pub awake_ms: u64,
+    pub elapsed_ms: u64,
+    /// Data requests sent to the parent
+    pub data_polls: u32,
+    pub tx_frames: u32,
+    pub rx_frames: u32,
+}
+
+impl DutyCycleStats {
+    /// Share of time awake in 1/1000
+    pub fn awake_permille(&self) -> u64 {
+        (self.awake_ms * 1000)
+            .checked_div(self.elapsed_ms)
+            .unwrap_or(0)
+    }
+}
+
+/// Measures the awake time of the application between [`DutyCycle::wake`] and
+/// [`DutyCycle::sleep`].
+#[derive(Debug, Clone, Copy)]
+pub struct DutyCycle {
+    started: Instant,
+    awake_since: Option<Instant>,
+    awake: Duration,
+    wakeups: u32,
+}
+
+impl DutyCycle {
+    pub fn new(now: Instant) -> Self {
+        DutyCycle {
+            started: now,
+            awake_since: None,
+            awake: Duration::from_ticks(0),
+            wakeups: 0,
+        }
+    }
+
+    pub fn wake(&mut self, now: Instant) {
+        if self.awake_since.is_none() {
+            self.awake_since = Some(now);
+            self.wakeups += 1;
+        }
+    }
+
+    pub fn sleep(&mut self, now: Instant) {
+        if let Some(since) = self.awake_since.take() {
+            self.awake += now - since;
+        }
+    }
+
+    /// Stats without the MAC counters
+    pub fn stats(&self, now: Instant) -> DutyCycleStats {
+        let awake = self.awake
+            + self
+                .awake_since
+                .map_or(Duration::from_ticks(0), |since| now - since);
+
+        DutyCycleStats {
+            wakeups: self.wakeups,
+            awake_ms: awake.as_millis(),
+            elapsed_ms: (now - self.started).as_millis(),
+            data_polls: 0,
+            tx_frames: 0,
+            rx_frames: 0,
+        }
+    }
+
+    /// Stats including the MAC counters of OpenThread
+    pub fn measure(&self, now: Instant) -> DutyCycleStats {
+        let counters = unsafe { &*otLinkGetCounters(otInstanceInitSingle()) };
+
+        DutyCycleStats {
+            data_polls: counters.mTxDataPoll,
+            tx_frames: counters.mTxTotal,
+            rx_frames: counters.mRxTotal,
+            ..self.stats(now)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_poll_period_is_always_on() {
+        assert_eq!(SleepyConfig::from_config(0, 500), None);
+        assert_eq!(
+            SleepyConfig::from_config(3000, 0),
+            Some(SleepyConfig {
+                poll_period: Duration::from_secs(3),
+                csl_period: None
+            })
+        );
+    }
+
+    #[test]
+    fn windows_align_to_polls() {
+        let epoch = Instant::from_secs(10);
+        let poll_period = Duration::from_secs(3);
+
+        assert_eq!(
+            next_window(Instant::from_secs(14), epoch, poll_period),
+            Instant::from_secs(16)
+        );
+        assert_eq!(
+            next_window(Instant::from_secs(16), epoch, poll_period),
+            Instant::from_secs(16)
+        );
+        assert_eq!(
+            next_window(Instant::from_millis(16_001), epoch, poll_period),
+            Instant::from_secs(19)
+        );
+        // before the first poll
+        assert_eq!(
+            next_window(Instant::from_secs(5), epoch, poll_period),
+            Instant::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn duty_cycle_counts_awake_time() {
+        let mut duty_cycle = DutyCycle::new(Instant::from_secs(0));
+
+        duty_cycle.wake(Instant::from_millis(1000));
+        duty_cycle.wake(Instant::from_millis(1005));
+        duty_cycle.sleep(Instant::from_millis(1020));
+        duty_cycle.sleep(Instant::from_millis(1030));
+        duty_cycle.wake(Instant::from_millis(3000));
+
+        let stats = duty_cycle.stats(Instant::from_millis(3010));
+        assert_eq!(stats.wakeups, 2);
+        assert_eq!(stats.awake_ms, 30);
+        assert_eq!(stats.elapsed_ms, 3010);
+        assert_eq!(stats.awake_permille(), 9);
+    }
+}
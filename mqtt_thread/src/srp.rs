@@ -0,0 +1,150 @@
+//! Registration with the SRP (Service Registration Protocol, RFC 9665) server of the border
+//! router.
+//!
+//! The OpenThread SRP client runs in autostart mode, it picks the server from the network data
+//! and renews the leases before they expire. The advertising proxy of the border router
+//! publishes the registered host and services via mDNS on the LAN, e.g. as
+//! `mqtt-thread-1a2b3c.local`. The host addresses are registered automatically, so OMR
+//! addresses added later are picked up as well.
+
+use core::fmt::Write;
+
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::String;
+use openthread::{OpenThread, OtError, SrpConf, SrpService};
+
+/// Services of the SRP client, see `OtSrpResources`
+pub const SRP_SERVICES: usize = 1;
+/// Buffer for the host name, service and instance names of the SRP client
+pub const SRP_BUFFER_SIZE: usize = 256;
+
+pub const HOST_NAME_LEN: usize = 32;
+
+/// Lease of host and services, renewed by OpenThread
+const LEASE_SECS: u32 = 2 * 3600;
+/// Lease of the host name key, the name stays reserved for us that long after the last renewal
+const KEY_LEASE_SECS: u32 = 14 * 24 * 3600;
+const TTL_SECS: u32 = 300;
+
+const DEREGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+const DEREGISTER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Format)]
+pub enum SrpError {
+    /// Only lowercase letters, digits and `-` are allowed
+    InvalidHostName,
+    HostNameTooLong,
+    OpenThread(OtError),
+    /// The server did not confirm the removal in time
+    Timeout,
+}
+
+impl From<OtError> for SrpError {
+    fn from(e: OtError) -> Self {
+        SrpError::OpenThread(e)
+    }
+}
+
+impl core::fmt::Display for SrpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "SRP error: {:?}", self)
+    }
+}
+
+/// A service offered by the device, e.g. `_coap._udp` on port 5683.
+#[derive(Debug, Format, Clone, Copy)]
+pub struct ServiceRegistration<'a> {
+    /// Service type like `_coap._udp`
+    pub service_type: &'a str,
+    pub instance_name: &'a str,
+    pub port: u16,
+}
+
+/// Unique host name from `prefix` and the last three bytes of the EUI-64, e.g.
+/// `mqtt-thread-1a2b3c`.
+pub fn host_name(prefix: &str, ieee_eui64: &[u8; 8]) -> Result<String<HOST_NAME_LEN>, SrpError> {
+    if prefix.is_empty()
+        || !prefix
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    {
+        return Err(SrpError::InvalidHostName);
+    }
+
+    let mut name = String::new();
+    write!(
+        name,
+        "{}-{:02x}{:02x}{:02x}",
+        prefix, ieee_eui64[5], ieee_eui64[6], ieee_eui64[7]
+    )
+    .map_err(|_| SrpError::HostNameTooLong)?;
+
+    Ok(name)
+}
+
+/// Register `host_name` and optionally `service`, the registration is sent as soon as an SRP
+/// server is known.
+pub fn register(
+    ot: &OpenThread<'_>,
+    host_name: &str,
+    service: Option<&ServiceRegistration<'_>>,
+) -> Result<(), SrpError> {
+    ot.srp_set_conf(&SrpConf {
+        host_name,
+        host_addrs: &[],
+        ttl: TTL_SECS,
+        default_lease_secs: LEASE_SECS,
+        default_key_lease_secs: KEY_LEASE_SECS,
+    })?;
+
+    if let Some(service) = service {
+        ot.srp_add_service(&SrpService {
+            name: service.service_type,
+            instance_name: service.instance_name,
+            subtype_labels: core::iter::empty(),
+            txt_entries: core::iter::empty(),
+            port: service.port,
+            priority: 0,
+            weight: 0,
+            lease_secs: 0,
+            key_lease_secs: 0,
+        })?;
+        info!(
+            "Registering {}.{} on port {} via SRP",
+            service.instance_name, service.service_type, service.port
+        );
+    }
+
+    ot.srp_autostart()?;
+    info!("Registering host {} via SRP", host_name);
+
+    Ok(())
+}
+
+/// Remove host and services from the SRP server, e.g. before disabling Thread.
+///
+/// Without a running SRP client the registration is only cleared locally.
+pub async fn deregister(ot: &OpenThread<'_>) -> Result<(), SrpError> {
+    if !ot.srp_running()? {
+        ot.srp_remove_all(true)?;
+        return Ok(());
+    }
+
+    ot.srp_remove_all(false)?;
+
+    let deadline = Instant::now() + DEREGISTER_TIMEOUT;
+    while !ot.srp_is_empty()? {
+        if Instant::now() >= deadline {
+            warn!("SRP server did not confirm the removal");
+            ot.srp_remove_all(true)?;
+            return Err(SrpError::Timeout);
+        }
+        Timer::after(DEREGISTER_POLL_INTERVAL).await;
+    }
+
+    ot.srp_stop()?;
+    info!("Removed SRP registration");
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Supervises the Thread interface and keeps embassy-net in sync with it.
+//!
+//! OpenThread reports role, parent and address changes through `wait_changed`. The supervisor
+//! re-selects the source address on every change (see [`crate::address`]), reapplies the IPv6
+//! configuration of the stack, publishes the connectivity state and reports transitions as
+//! [`SupervisorEvent`]s. Connections bound to an address that went away are useless, so clients
+//! are asked to reconnect with [`wait_reconnect`].
+
+use core::net::Ipv6Addr;
+
+use defmt::{info, warn, Format};
+use embassy_net::{ConfigV6, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{PubSubChannel, Subscriber},
+    signal::Signal,
+};
+use heapless::Vec;
+use openthread::{DeviceRole, OpenThread};
+
+use crate::address::{self, SourceAddress, MAX_ADDRESSES};
+use crate::connectivity::{self, ConnectivityState, LostReason};
+use crate::nat64_discovery;
+
+const EVENT_CAPACITY: usize = 4;
+/// Maximum number of tasks that can subscribe to supervisor events at the same time
+pub const MAX_SUBSCRIBERS: usize = 2;
+
+static EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    SupervisorEvent,
+    EVENT_CAPACITY,
+    MAX_SUBSCRIBERS,
+    1,
+> = PubSubChannel::new();
+static RECONNECT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub type EventSubscriber = Subscriber<
+    'static,
+    CriticalSectionRawMutex,
+    SupervisorEvent,
+    EVENT_CAPACITY,
+    MAX_SUBSCRIBERS,
+    1,
+>;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    RoleChanged {
+        from: DeviceRole,
+        to: DeviceRole,
+    },
+    /// Attached to a different parent, the router ID is the upper 6 bits of the RLOC16
+    ParentChanged {
+        rloc16: u16,
+    },
+    /// The source address of the stack changed, `None` if there is no usable address
+    AddressChanged(Option<Ipv6Addr>),
+}
+
+/// Subscribe to supervisor events. Slow subscribers miss the oldest events.
+///
+/// Returns `None` if all `MAX_SUBSCRIBERS` subscribers are already in use.
+pub fn subscribe() -> Option<EventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+/// Wait until open connections have to be re-established.
+pub async fn wait_reconnect() {
+    RECONNECT.wait().await
+}
+
+/// Forget reconnect requests issued before a new connection is established.
+pub fn reset_reconnect() {
+    RECONNECT.reset();
+}
+
+fn report(event: SupervisorEvent) {
+    info!("Thread: {:?}", event);
+    EVENTS.immediate_publisher().publish_immediate(event);
+}
+
+fn request_reconnect() {
+    RECONNECT.signal(());
+}
+
+/// State of the supervised interface as seen after the last change.
+struct Supervisor {
+    stack: Stack<'static>,
+    dns_server: Option<Ipv6Addr>,
+    role: DeviceRole,
+    addresses: Vec<(Ipv6Addr, u8), MAX_ADDRESSES>,
+    rloc16: Option<u16>,
+    source: Option<SourceAddress>,
+}
+
+impl Supervisor {
+    fn update_role(&mut self, role: DeviceRole) {
+        if role == self.role {
+            return;
+        }
+
+        report(SupervisorEvent::RoleChanged {
+            from: self.role,
+            to: role,
+        });
+        self.role = role;
+
+        connectivity::publish_role(role);
+        if matches!(role, DeviceRole::Disabled | DeviceRole::Detached) {
+            request_reconnect();
+        }
+    }
+
+    fn update_addresses(&mut self, addresses: Vec<(Ipv6Addr, u8), MAX_ADDRESSES>) {
+        if addresses == self.addresses {
+            // reattached with the addresses it had before detaching
+            if self.source.is_some() && connectivity::current() == ConnectivityState::LinkUp {
+                self.apply();
+            }
+            return;
+        }
+
+        info!("Got new IPv6 address(es) from OpenThread: {:?}", addresses);
+
+        let rloc16 = address::rloc16(&addresses);
+        if let (Some(previous), Some(rloc16)) = (self.rloc16, rloc16) {
+            // a child keeps its RLOC16 as long as it stays with its parent
+            if previous != rloc16 && self.role == DeviceRole::Child {
+                report(SupervisorEvent::ParentChanged { rloc16 });
+            }
+        }
+        if rloc16.is_some() {
+            self.rloc16 = rloc16;
+        }
+
+        let selected = address::select_source(&addresses);
+        self.addresses = addresses;
+
+        if selected == self.source {
+            return;
+        }
+
+        let changed =
+            selected.map(|source| source.address) != self.source.map(|source| source.address);
+        self.source = selected;
+        self.apply();
+
+        if changed {
+            report(SupervisorEvent::AddressChanged(
+                selected.map(|source| source.address),
+            ));
+            request_reconnect();
+        }
+    }
+
+    /// Apply the selected source address to the stack and publish the connectivity state.
+    fn apply(&self) {
+        let Some(source) = self.source else {
+            warn!("No usable IPv6 address");
+            self.stack.set_config_v6(ConfigV6::None);
+            if connectivity::current().is_online() {
+                connectivity::publish(ConnectivityState::Lost(LostReason::AddressLost));
+            }
+            return;
+        };
+
+        if source.kind.is_off_mesh_routable() {
+            info!("Selected {:?} address {}", source.kind, source.address);
+        } else {
+            warn!(
+                "Selected {:?} address {}, hosts outside of the Thread network are unreachable until a border router advertises a prefix",
+                source.kind, source.address
+            );
+        }
+
+        self.stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+            address: Ipv6Cidr::new(source.address, source.prefix_len),
+            gateway: source.gateway,
+            dns_servers: self.dns_server.into_iter().collect(),
+        }));
+        connectivity::publish(ConnectivityState::IpAcquired(source.address.into()));
+    }
+}
+
+/// Follows OpenThread state changes and keeps the IPv6 configuration of `stack` up to date.
+///
+/// `dns_server` is used for name resolution, see [`crate::resolver`].
+#[embassy_executor::task]
+pub async fn supervisor_task(
+    ot: OpenThread<'static>,
+    stack: Stack<'static>,
+    dns_server: Option<Ipv6Addr>,
+) -> ! {
+    let mut supervisor = Supervisor {
+        stack,
+        dns_server,
+        role: DeviceRole::Disabled,
+        addresses: Vec::new(),
+        rloc16: None,
+        source: None,
+    };
+    connectivity::publish_role(supervisor.role);
+
+    loop {
+        supervisor.update_role(ot.net_status().role);
+
+        let mut addresses = Vec::new();
+        ot.ipv6_addrs(|address| {
+            if let Some(address) = address {
+                let _ = addresses.push(address);
+            }
+
+            Ok(())
+        })
+        .unwrap();
+        supervisor.update_addresses(addresses);
+
+        ot.wait_changed().await;
+        nat64_discovery::network_changed();
+    }
+}
@@ -1,10 +1,38 @@
+// no_std dataset codec shared with the firmware
+#[allow(dead_code)]
+#[path = "../mqtt_thread/src/dataset.rs"]
+mod dataset;
+
 fn main() {
+    validate_dataset();
     linker_be_nice();
     println!("cargo:rustc-link-arg=-Tdefmt.x");
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Fail the build instead of `set_active_dataset_tlv_hexstr` at runtime.
+fn validate_dataset() {
+    println!("cargo:rerun-if-env-changed=THREAD_DATASET");
+    // the same lookup as `dotenv!`, variables of the environment take precedence
+    match dotenvy::dotenv() {
+        Ok(path) => println!("cargo:rerun-if-changed={}", path.display()),
+        Err(_) => println!("cargo:rerun-if-changed=.env"),
+    }
+    let Ok(hex) = std::env::var("THREAD_DATASET") else {
+        // reported by `dotenv!`
+        return;
+    };
+    if hex.is_empty() {
+        // commissioned with THREAD_PSKD
+        return;
+    }
+
+    if let Err(e) = dataset::Dataset::from_hex(&hex).and_then(|dataset| dataset.validate_active()) {
+        panic!("Invalid THREAD_DATASET: {e}");
+    }
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
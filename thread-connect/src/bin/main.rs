@@ -1,19 +1,35 @@
 #![no_std]
 #![no_main]
 
+use core::fmt::{self, Write};
 use core::net::{Ipv6Addr, SocketAddrV6};
 
-use defmt::info;
+use defmt::{debug, error, info, warn};
 use dotenvy_macro::dotenv;
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
 use esp_hal::rng::Rng;
 use esp_hal::timer::systimer::SystemTimer;
+use esp_storage::FlashStorage;
 use openthread::esp::{EspRadio, Ieee802154};
 use openthread::{
-    BytesFmt, OpenThread, OtResources, OtRngCore, OtUdpResources, SimpleRamSettings, UdpSocket
+    BytesFmt, DeviceRole, OpenThread, OtResources, OtSrpResources, OtUdpResources, UdpSocket,
 };
 use panic_rtt_target as _;
+use thread_connect::address::{self, MAX_ADDRESSES};
+use thread_connect::coap;
+use thread_connect::coap_server::{Device, Server};
+use thread_connect::dataset::Dataset;
+use thread_connect::device_type::{self, DeviceType};
+use thread_connect::diagnostics;
+use thread_connect::identity::Identity;
+use thread_connect::joiner::{self, Provisioning};
+use thread_connect::outputs::{self, OutputKind, RELAY_COUNT};
+use thread_connect::secret::Secret;
+use thread_connect::settings_store::{self, SettingsStore, PARTITION_OFFSET};
+use thread_connect::srp::{self, ServiceRegistration, SRP_BUFFER_SIZE, SRP_SERVICES};
 
 use tinyrlibc as _;
 
@@ -32,12 +48,24 @@ macro_rules! mk_static {
     }};
 }
 
-const BOUND_PORT: u16 = 1212;
+/// Registered via SRP with the last 3 bytes of the EUI-64 appended
+const HOST_NAME_PREFIX: &str = "thread-connect";
+const SERVICE_TYPE: &str = "_coap._udp";
 
 const UDP_SOCKETS_BUF: usize = 1280;
 const UDP_MAX_SOCKETS: usize = 2;
 
-const THREAD_DATASET: &str = dotenv!("THREAD_DATASET");
+/// Active operational dataset as TLV hex, empty to be commissioned with `THREAD_PSKD`
+const THREAD_DATASET: Secret<&str> = Secret::new(dotenv!("THREAD_DATASET"));
+/// PSKd for joiner commissioning, e.g. `ot-ctl commissioner joiner add <eui64> J01NME`
+const THREAD_PSKD: Secret<&str> = Secret::new(match option_env!("THREAD_PSKD") {
+    Some(pskd) => pskd,
+    None => "",
+});
+/// Overrides the EUI-64 derived from the factory MAC address, e.g. `40:4c:ca:ff:fe:12:34:56`
+const IEEE_EUI64: Option<&str> = option_env!("IEEE_EUI64");
+/// `mtd`, or `router` and `leader` with the `ftd` feature to strengthen the mesh on mains power
+const THREAD_DEVICE_TYPE: Option<&str> = option_env!("THREAD_DEVICE_TYPE");
 
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
@@ -51,23 +79,81 @@ async fn main(spawner: Spawner) {
     info!("Embassy initialized!");
 
     let rng = mk_static!(Rng, Rng::new(peripherals.RNG));
+    let initial_message_id = rng.random() as u16;
+
+    // relay module on GPIO23 (power), GPIO18 and GPIO19, LED on GPIO7
+    let relay_power = Output::new(peripherals.GPIO23, Level::Low, OutputConfig::default());
+    let relays = [
+        Output::new(peripherals.GPIO18, Level::Low, OutputConfig::default()),
+        Output::new(peripherals.GPIO19, Level::Low, OutputConfig::default()),
+    ];
+    let led = Output::new(peripherals.GPIO7, Level::Low, OutputConfig::default());
+    spawner
+        .spawn(outputs::outputs_task(relay_power, relays, led))
+        .unwrap();
+
+    let device_type = match DeviceType::from_config(THREAD_DEVICE_TYPE.unwrap_or("")) {
+        Ok(device_type) => device_type,
+        Err(e) => {
+            error!("Invalid THREAD_DEVICE_TYPE: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = device_type.validate(false) {
+        error!("Unsupported THREAD_DEVICE_TYPE: {:?}", e);
+        return;
+    }
+
+    let ieee_euid64 = match Identity::load(IEEE_EUI64.unwrap_or("")) {
+        Ok(identity) => identity.eui64.0,
+        Err(e) => {
+            error!("Invalid IEEE_EUI64: {:?}", e);
+            return;
+        }
+    };
 
-    let mut ieee_euid64 = [0; 8];
-    rng.fill_bytes(&mut ieee_euid64);
+    let host_name = match srp::host_name(HOST_NAME_PREFIX, &ieee_euid64) {
+        Ok(host_name) => host_name,
+        Err(e) => {
+            error!("Invalid host name: {:?}", e);
+            return;
+        }
+    };
 
     let ot_resources = mk_static!(OtResources, OtResources::new());
     let ot_udp_resources =
         mk_static!(OtUdpResources<UDP_MAX_SOCKETS, UDP_SOCKETS_BUF>, OtUdpResources::new());
-    let ot_settings_buf = mk_static!([u8; 1024], [0; 1024]);
+    let ot_srp_resources = mk_static!(
+        OtSrpResources<SRP_SERVICES, SRP_BUFFER_SIZE>,
+        OtSrpResources::new()
+    );
+
+    let mut flash = FlashStorage::new();
 
-    let ot_settings = mk_static!(SimpleRamSettings, SimpleRamSettings::new(ot_settings_buf));
+    // holding the BOOT button while resetting forgets the Thread network
+    let boot_button = Input::new(peripherals.GPIO9, InputConfig::default().with_pull(Pull::Up));
+    if boot_button.is_low() {
+        info!("BOOT button held, erasing the Thread settings");
+        if let Err(e) = settings_store::factory_reset(&mut flash, PARTITION_OFFSET) {
+            error!("Factory reset failed: {:?}", e);
+        }
+    }
 
-    let ot = OpenThread::new_with_udp(
+    let ot_settings = match SettingsStore::new(flash, PARTITION_OFFSET) {
+        Ok(store) => mk_static!(SettingsStore<FlashStorage>, store),
+        Err(e) => {
+            error!("Failed to load the Thread settings: {:?}", e);
+            return;
+        }
+    };
+
+    let ot = OpenThread::new_with_udp_srp(
         ieee_euid64,
         rng,
         ot_settings,
         ot_resources,
         ot_udp_resources,
+        ot_srp_resources,
     )
     .unwrap();
 
@@ -81,34 +167,176 @@ async fn main(spawner: Spawner) {
         ))
         .unwrap();
 
-    spawner.spawn(run_ot_ip_info(ot.clone())).unwrap();
-
-    info!("Dataset: {}", THREAD_DATASET);
+    spawner
+        .spawn(run_ot_ip_info(ot.clone(), device_type))
+        .unwrap();
 
-    ot.set_active_dataset_tlv_hexstr(THREAD_DATASET).unwrap();
     ot.enable_ipv6(true).unwrap();
+    match Provisioning::from_config(THREAD_DATASET, THREAD_PSKD) {
+        Some(Provisioning::Dataset(dataset)) => {
+            // validated by build.rs
+            if let Ok(decoded) = Dataset::from_hex(dataset.expose()) {
+                info!(
+                    "Network {:?} on channel {:?}, PAN ID {:?}",
+                    decoded.network_name,
+                    decoded.channel.map(|channel| channel.channel),
+                    decoded.pan_id
+                );
+            }
+            ot.set_active_dataset_tlv_hexstr(dataset.expose()).unwrap();
+        }
+        Some(Provisioning::Joiner(_)) if joiner::is_commissioned() => {
+            info!("Using the dataset received during commissioning");
+        }
+        Some(Provisioning::Joiner(pskd)) => {
+            info!(
+                "Waiting for a commissioner to admit EUI-64 {:02x}",
+                ieee_euid64
+            );
+            if let Err(e) = joiner::join(pskd).await {
+                error!("Commissioning failed: {:?}", e);
+                return;
+            }
+        }
+        None => {
+            error!("Neither THREAD_DATASET nor THREAD_PSKD is set");
+            return;
+        }
+    }
+    if let Err(e) = device_type::configure(&ot, device_type) {
+        error!("Failed to configure the device type: {:?}", e);
+        return;
+    }
     ot.enable_thread(true).unwrap();
 
+    let service = ServiceRegistration {
+        service_type: SERVICE_TYPE,
+        instance_name: &host_name,
+        port: coap::PORT,
+    };
+    if let Err(e) = srp::register(&ot, &host_name, Some(&service)) {
+        error!("SRP registration failed: {:?}", e);
+    }
+
     let socket = UdpSocket::bind(
-        ot,
-        &SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, BOUND_PORT, 0, 0),
+        ot.clone(),
+        &SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, coap::PORT, 0, 0),
     )
     .unwrap();
 
-    info!(
-        "Opened socket on port {} and waiting for packets...",
-        BOUND_PORT
-    );
+    info!("CoAP server listening on port {}", coap::PORT);
 
     let buf: &mut [u8] = unsafe { mk_static!([u8; UDP_SOCKETS_BUF]).assume_init_mut() };
+    let out: &mut [u8] = unsafe { mk_static!([u8; UDP_SOCKETS_BUF]).assume_init_mut() };
+
+    let mut server = Server::new(initial_message_id);
+    let mut board = Board {
+        ot: ot.clone(),
+        host_name: &host_name,
+        eui64: ieee_euid64,
+        factory_reset_requested: false,
+    };
 
     loop {
-        let (len, local, remote) = socket.recv(buf).await.unwrap();
+        let (len, local, remote) = match select(socket.recv(buf), outputs::wait_changed()).await {
+            Either::First(Ok(received)) => received,
+            Either::First(Err(e)) => {
+                error!("Failed to receive: {:?}", e);
+                continue;
+            }
+            Either::Second(()) => {
+                while let Some((remote, len)) = server.next_notification(&board, out) {
+                    if let Err(e) = socket.send(&out[..len], None, &remote).await {
+                        error!("Failed to notify {}: {:?}", remote, e);
+                    }
+                }
+                continue;
+            }
+        };
 
-        info!("Got {} from {} on {}", BytesFmt(&buf[..len]), remote, local);
+        debug!("Got {} from {} on {}", BytesFmt(&buf[..len]), remote, local);
+
+        if let Some(len) = server.handle(&buf[..len], remote, &mut board, out) {
+            if let Err(e) = socket.send(&out[..len], Some(&local), &remote).await {
+                error!("Failed to respond to {}: {:?}", remote, e);
+            }
+        }
 
-        socket.send(b"Hello", Some(&local), &remote).await.unwrap();
-        info!("Sent `b\"Hello\"`");
+        if board.factory_reset_requested {
+            info!("Factory reset requested by {}", remote);
+            // give the response time to leave before the radio goes away
+            Timer::after(Duration::from_millis(500)).await;
+            if let Err(e) = srp::deregister(&ot).await {
+                error!("SRP deregistration failed: {:?}", e);
+            }
+            // OpenThread owns the settings store, erase the partition behind its back and
+            // restart before it writes again
+            if let Err(e) =
+                settings_store::factory_reset(&mut FlashStorage::new(), PARTITION_OFFSET)
+            {
+                error!("Factory reset failed: {:?}", e);
+            }
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
+/// The outputs and the identity behind the CoAP resources
+struct Board<'a> {
+    ot: OpenThread<'static>,
+    host_name: &'a str,
+    eui64: [u8; 8],
+    /// Set by `/sys/factory-reset`, carried out after the response is sent
+    factory_reset_requested: bool,
+}
+
+impl Device for Board<'_> {
+    fn relay_count(&self) -> u8 {
+        RELAY_COUNT
+    }
+
+    fn output(&self, output: OutputKind) -> bool {
+        outputs::state(output)
+    }
+
+    fn set_output(&mut self, output: OutputKind, on: bool) -> bool {
+        outputs::request(output, on)
+    }
+
+    fn write_info(&self, w: &mut dyn Write) -> fmt::Result {
+        write!(w, "{{\"host\":\"{}\",\"eui64\":\"", self.host_name)?;
+        for byte in self.eui64 {
+            write!(w, "{:02x}", byte)?;
+        }
+        write!(
+            w,
+            "\",\"uptime\":{},\"version\":\"{}\"}}",
+            Instant::now().as_secs(),
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    fn write_diagnostics(&self, w: &mut dyn Write) -> fmt::Result {
+        diagnostics::collect().write_json(w)
+    }
+
+    fn mesh_local_prefix(&self) -> Option<[u16; 4]> {
+        let mut addresses = heapless::Vec::<(Ipv6Addr, u8), MAX_ADDRESSES>::new();
+        self.ot
+            .ipv6_addrs(|address| {
+                if let Some(address) = address {
+                    let _ = addresses.push(address);
+                }
+
+                Ok(())
+            })
+            .ok()?;
+        address::mesh_local_prefix(&addresses)
+    }
+
+    fn factory_reset(&mut self) -> bool {
+        self.factory_reset_requested = true;
+        true
     }
 }
 
@@ -118,10 +346,21 @@ async fn run_ot(ot: OpenThread<'static>, radio: EspRadio<'static>) -> ! {
 }
 
 #[embassy_executor::task]
-async fn run_ot_ip_info(ot: OpenThread<'static>) -> ! {
+async fn run_ot_ip_info(ot: OpenThread<'static>, device_type: DeviceType) -> ! {
     let mut curr_addrs = heapless::Vec::<(Ipv6Addr, u8), 4>::new();
+    let mut curr_role = None;
 
     loop {
+        let role = ot.net_status().role;
+        if curr_role != Some(role) {
+            curr_role = Some(role);
+            diagnostics::dump();
+            let attached = !matches!(role, DeviceRole::Disabled | DeviceRole::Detached);
+            if attached && !device_type.accepts(role) {
+                warn!("Attached as {:?}, unexpected for a {:?} device", role, device_type);
+            }
+        }
+
         let mut addrs = heapless::Vec::<(Ipv6Addr, u8), 4>::new();
         ot.ipv6_addrs(|addr| {
             if let Some(addr) = addr {
@@ -0,0 +1,1108 @@
+//! CoAP resources of the device.
+//!
+//! | Resource            | Methods           | Representation                 |
+//! |---------------------|-------------------|--------------------------------|
+//! | `/led`              | GET, PUT, observe | `on` or `off`                  |
+//! | `/relay/<n>`        | GET, PUT, observe | `on` or `off`, `n` starts at 0 |
+//! | `/sys/info`         | GET               | JSON                           |
+//! | `/sys/diagnostics`  | GET               | JSON                           |
+//! | `/sys/factory-reset`| POST              | empty                          |
+//! | `/.well-known/core` | GET               | link format (RFC 6690)         |
+//!
+//! Confirmable requests are answered with piggybacked responses. Duplicates are processed
+//! again, which is fine as GET and PUT are idempotent. Representations larger than the block
+//! size requested by the client are transferred block-wise with Block2. Observers are notified
+//! with non-confirmable notifications and removed when they answer one with a reset.
+//!
+//! A factory reset is only accepted from link-local and mesh-local addresses, i.e. from inside
+//! the Thread network and not through a border router.
+//!
+//! The server has no I/O, [`Server::handle`] turns a request into a response and
+//! [`Server::next_notification`] yields pending notifications, both into caller provided
+//! buffers. The hardware is behind [`Device`].
+
+use core::fmt::{self, Write};
+use core::net::SocketAddrV6;
+
+use defmt::{debug, Format};
+use heapless::Vec;
+
+use crate::address::{self, AddressKind};
+use crate::coap::{
+    content_format, option, parse_header, Block, Code, Message, MessageType, MessageWriter,
+    MAX_TOKEN_LEN,
+};
+use crate::diagnostics_report::DIAGNOSTICS_SIZE;
+use crate::output_kind::OutputKind;
+
+/// Observers of all resources together
+pub const MAX_OBSERVERS: usize = 4;
+
+/// Largest representation, `/.well-known/core`, `/sys/info` and `/sys/diagnostics` have to fit
+const REPRESENTATION_SIZE: usize = DIAGNOSTICS_SIZE;
+
+const MAX_PATH_SEGMENTS: usize = 4;
+
+/// Observe sequence numbers have 24 bits
+const OBSERVE_SEQUENCE_MASK: u32 = 0x00ff_ffff;
+
+/// The hardware and state behind the resources.
+pub trait Device {
+    fn relay_count(&self) -> u8;
+
+    fn output(&self, output: OutputKind) -> bool;
+
+    /// Returns `false` if the request could not be queued.
+    fn set_output(&mut self, output: OutputKind, on: bool) -> bool;
+
+    /// JSON object of `/sys/info`
+    fn write_info(&self, w: &mut dyn Write) -> fmt::Result;
+
+    /// JSON object of `/sys/diagnostics`
+    fn write_diagnostics(&self, w: &mut dyn Write) -> fmt::Result;
+
+    /// Mesh-local prefix of the Thread network, `None` while detached
+    fn mesh_local_prefix(&self) -> Option<[u16; 4]>;
+
+    /// Erase the settings and restart once the response is sent. Returns `false` if the reset
+    /// could not be scheduled.
+    fn factory_reset(&mut self) -> bool;
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Output(OutputKind),
+    SysInfo,
+    SysDiagnostics,
+    SysFactoryReset,
+    WellKnownCore,
+}
+
+impl Resource {
+    fn content_format(&self) -> u16 {
+        match self {
+            Resource::Output(_) => content_format::TEXT_PLAIN,
+            Resource::SysInfo | Resource::SysDiagnostics | Resource::SysFactoryReset => {
+                content_format::JSON
+            }
+            Resource::WellKnownCore => content_format::LINK_FORMAT,
+        }
+    }
+}
+
+/// Resource of a `Uri-Path`, `None` if there is none.
+pub fn route<'a>(path: impl Iterator<Item = &'a str>, relay_count: u8) -> Option<Resource> {
+    let mut segments: Vec<&str, MAX_PATH_SEGMENTS> = Vec::new();
+    for segment in path {
+        segments.push(segment).ok()?;
+    }
+
+    match segments.as_slice() {
+        ["led"] => Some(Resource::Output(OutputKind::Led)),
+        ["relay", n] => match n.parse::<u8>() {
+            Ok(n) if n < relay_count => Some(Resource::Output(OutputKind::Relay(n))),
+            _ => None,
+        },
+        ["sys", "info"] => Some(Resource::SysInfo),
+        ["sys", "diagnostics"] => Some(Resource::SysDiagnostics),
+        ["sys", "factory-reset"] => Some(Resource::SysFactoryReset),
+        [".well-known", "core"] => Some(Resource::WellKnownCore),
+        _ => None,
+    }
+}
+
+/// `on`, `off`, `1`, `0`, `true` or `false`
+pub fn parse_state(payload: &[u8]) -> Option<bool> {
+    match payload {
+        b"on" | b"1" | b"true" => Some(true),
+        b"off" | b"0" | b"false" => Some(false),
+        _ => None,
+    }
+}
+
+fn state_name(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Observer {
+    remote: SocketAddrV6,
+    token: Vec<u8, MAX_TOKEN_LEN>,
+    output: OutputKind,
+    /// State of the last notification
+    state: bool,
+    /// Message ID of the last notification, a reset with it cancels the observation
+    message_id: u16,
+}
+
+/// Writes into a fixed buffer, fails if it is full.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+pub struct Server {
+    observers: Vec<Observer, MAX_OBSERVERS>,
+    next_message_id: u16,
+    observe_sequence: u32,
+}
+
+/// Response before it is written
+struct Response<'a> {
+    code: Code,
+    content_format: Option<u16>,
+    observe: Option<u32>,
+    block2: Option<Block>,
+    size2: Option<u32>,
+    payload: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    fn empty(code: Code) -> Self {
+        Response {
+            code,
+            content_format: None,
+            observe: None,
+            block2: None,
+            size2: None,
+            payload: &[],
+        }
+    }
+}
+
+impl Server {
+    /// `initial_message_id` should be random to not collide with IDs from before a restart.
+    pub fn new(initial_message_id: u16) -> Self {
+        Server {
+            observers: Vec::new(),
+            next_message_id: initial_message_id,
+            observe_sequence: 0,
+        }
+    }
+
+    pub fn observer_count(&self) -> usize {
+        self.observers.len()
+    }
+
+    fn message_id(&mut self) -> u16 {
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        message_id
+    }
+
+    fn observe_sequence(&mut self) -> u32 {
+        self.observe_sequence = (self.observe_sequence + 1) & OBSERVE_SEQUENCE_MASK;
+        self.observe_sequence
+    }
+
+    /// Handle a datagram from `remote`, returns the length of the response in `out`.
+    pub fn handle(
+        &mut self,
+        data: &[u8],
+        remote: SocketAddrV6,
+        device: &mut impl Device,
+        out: &mut [u8],
+    ) -> Option<usize> {
+        let request = match Message::parse(data) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("Invalid CoAP message from {}: {:?}", remote, e);
+                // confirmable messages with format errors are rejected
+                return match parse_header(data) {
+                    Ok((MessageType::Confirmable, _, message_id, _)) => self.reset(message_id, out),
+                    _ => None,
+                };
+            }
+        };
+
+        match request.message_type {
+            MessageType::Reset => {
+                self.observers.retain(|observer| {
+                    observer.remote != remote || observer.message_id != request.message_id
+                });
+                return None;
+            }
+            MessageType::Acknowledgement => return None,
+            MessageType::Confirmable | MessageType::NonConfirmable => (),
+        }
+
+        if !request.code.is_request() {
+            // CoAP ping or a response we did not ask for
+            return match request.message_type {
+                MessageType::Confirmable => self.reset(request.message_id, out),
+                _ => None,
+            };
+        }
+
+        let mut representation = [0; REPRESENTATION_SIZE];
+        let response = self.respond(&request, remote, device, &mut representation);
+
+        let (message_type, message_id) = match request.message_type {
+            MessageType::Confirmable => (MessageType::Acknowledgement, request.message_id),
+            _ => (MessageType::NonConfirmable, self.message_id()),
+        };
+        write_response(out, message_type, message_id, request.token, &response).ok()
+    }
+
+    fn reset(&self, message_id: u16, out: &mut [u8]) -> Option<usize> {
+        MessageWriter::new(out, MessageType::Reset, Code::EMPTY, message_id, &[])
+            .ok()
+            .map(MessageWriter::finish)
+    }
+
+    fn respond<'r>(
+        &mut self,
+        request: &Message,
+        remote: SocketAddrV6,
+        device: &mut impl Device,
+        representation: &'r mut [u8; REPRESENTATION_SIZE],
+    ) -> Response<'r> {
+        if let Some((number, _)) = request.options().find(|(number, _)| {
+            option::is_critical(*number)
+                && !matches!(
+                    *number,
+                    option::URI_HOST
+                        | option::URI_PORT
+                        | option::URI_PATH
+                        | option::URI_QUERY
+                        | option::ACCEPT
+                        | option::BLOCK2
+                        | option::BLOCK1
+                )
+        }) {
+            debug!("Unsupported critical option {}", number);
+            return Response::empty(Code::BAD_OPTION);
+        }
+
+        let Some(resource) = route(request.path(), device.relay_count()) else {
+            return Response::empty(Code::NOT_FOUND);
+        };
+
+        match (request.code, resource) {
+            (Code::GET, _) => self.get(request, remote, resource, &*device, representation),
+            (Code::PUT, Resource::Output(output)) => Self::put(request, output, device),
+            (Code::POST, Resource::SysFactoryReset) => Self::factory_reset(remote, device),
+            _ => Response::empty(Code::METHOD_NOT_ALLOWED),
+        }
+    }
+
+    fn get<'r>(
+        &mut self,
+        request: &Message,
+        remote: SocketAddrV6,
+        resource: Resource,
+        device: &impl Device,
+        representation: &'r mut [u8; REPRESENTATION_SIZE],
+    ) -> Response<'r> {
+        let mut cursor = Cursor {
+            buf: representation,
+            len: 0,
+        };
+        let written = match resource {
+            Resource::Output(output) => cursor.write_str(state_name(device.output(output))),
+            Resource::SysInfo => device.write_info(&mut cursor),
+            Resource::SysDiagnostics => device.write_diagnostics(&mut cursor),
+            Resource::WellKnownCore => write_link_format(&mut cursor, device.relay_count()),
+            Resource::SysFactoryReset => return Response::empty(Code::METHOD_NOT_ALLOWED),
+        };
+        if written.is_err() {
+            return Response::empty(Code::INTERNAL_SERVER_ERROR);
+        }
+        let len = cursor.len;
+
+        let observe = match (resource, request.uint_option(option::OBSERVE)) {
+            (Resource::Output(output), Some(0)) => {
+                self.register(remote, request.token, output, device.output(output))
+            }
+            (Resource::Output(_), Some(1)) => {
+                self.deregister(remote, request.token);
+                None
+            }
+            _ => None,
+        };
+
+        let mut response = Response {
+            code: Code::CONTENT,
+            content_format: Some(resource.content_format()),
+            observe,
+            block2: None,
+            size2: None,
+            payload: &representation[..len],
+        };
+
+        let requested = request.uint_option(option::BLOCK2).map(Block::decode);
+        let block = match requested {
+            Some(None) => return Response::empty(Code::BAD_OPTION),
+            Some(Some(block)) => block,
+            None if len > 16 << Block::MAX_SZX => Block {
+                num: 0,
+                more: false,
+                szx: Block::MAX_SZX,
+            },
+            None => return response,
+        };
+
+        if block.offset() >= len.max(1) {
+            return Response::empty(Code::BAD_OPTION);
+        }
+        let end = (block.offset() + block.size()).min(len);
+        response.payload = &representation[block.offset()..end];
+        response.block2 = Some(Block {
+            more: end < len,
+            ..block
+        });
+        if block.num == 0 {
+            response.size2 = Some(len as u32);
+        }
+        response
+    }
+
+    fn put<'r>(request: &Message, output: OutputKind, device: &mut impl Device) -> Response<'r> {
+        if let Some(block) = request.uint_option(option::BLOCK1).map(Block::decode) {
+            // the representations are a few bytes, only single block requests are accepted
+            if !matches!(
+                block,
+                Some(Block {
+                    num: 0,
+                    more: false,
+                    ..
+                })
+            ) {
+                return Response::empty(Code::REQUEST_ENTITY_TOO_LARGE);
+            }
+        }
+        match request.uint_option(option::CONTENT_FORMAT) {
+            None | Some(0) => (),
+            Some(_) => return Response::empty(Code::UNSUPPORTED_CONTENT_FORMAT),
+        }
+
+        let Some(on) = parse_state(request.payload) else {
+            return Response::empty(Code::BAD_REQUEST);
+        };
+        if device.set_output(output, on) {
+            Response::empty(Code::CHANGED)
+        } else {
+            Response::empty(Code::SERVICE_UNAVAILABLE)
+        }
+    }
+
+    fn factory_reset<'r>(remote: SocketAddrV6, device: &mut impl Device) -> Response<'r> {
+        let local = matches!(
+            address::classify(remote.ip(), device.mesh_local_prefix()),
+            Some(
+                AddressKind::LinkLocal
+                    | AddressKind::Rloc
+                    | AddressKind::Aloc
+                    | AddressKind::MeshLocalEid
+            )
+        );
+        if !local {
+            debug!("Factory reset from {} refused", remote);
+            return Response::empty(Code::FORBIDDEN);
+        }
+
+        if device.factory_reset() {
+            Response::empty(Code::CHANGED)
+        } else {
+            Response::empty(Code::SERVICE_UNAVAILABLE)
+        }
+    }
+
+    /// Observe sequence number of the registration, `None` if the registry is full.
+    fn register(
+        &mut self,
+        remote: SocketAddrV6,
+        token: &[u8],
+        output: OutputKind,
+        state: bool,
+    ) -> Option<u32> {
+        // a new registration with the same token replaces the old one
+        self.deregister(remote, token);
+
+        let observer = Observer {
+            remote,
+            token: Vec::from_slice(token).ok()?,
+            output,
+            state,
+            message_id: 0,
+        };
+        self.observers.push(observer).ok()?;
+        Some(self.observe_sequence())
+    }
+
+    fn deregister(&mut self, remote: SocketAddrV6, token: &[u8]) {
+        self.observers
+            .retain(|observer| observer.remote != remote || observer.token != token);
+    }
+
+    /// Next notification of an observer whose resource changed, call until `None`.
+    pub fn next_notification(
+        &mut self,
+        device: &impl Device,
+        out: &mut [u8],
+    ) -> Option<(SocketAddrV6, usize)> {
+        let index = self
+            .observers
+            .iter()
+            .position(|observer| device.output(observer.output) != observer.state)?;
+        let message_id = self.message_id();
+        let sequence = self.observe_sequence();
+
+        let observer = &mut self.observers[index];
+        observer.state = device.output(observer.output);
+        observer.message_id = message_id;
+
+        let response = Response {
+            code: Code::CONTENT,
+            content_format: Some(content_format::TEXT_PLAIN),
+            observe: Some(sequence),
+            block2: None,
+            size2: None,
+            payload: state_name(observer.state).as_bytes(),
+        };
+        let len = write_response(
+            out,
+            MessageType::NonConfirmable,
+            message_id,
+            &observer.token,
+            &response,
+        )
+        .ok()?;
+        Some((observer.remote, len))
+    }
+}
+
+fn write_link_format(w: &mut dyn Write, relay_count: u8) -> fmt::Result {
+    write!(w, "</led>;obs")?;
+    for n in 0..relay_count {
+        write!(w, ",</relay/{}>;obs", n)?;
+    }
+    write!(
+        w,
+        ",</sys/info>;ct=50,</sys/diagnostics>;ct=50,</sys/factory-reset>"
+    )
+}
+
+fn write_response(
+    out: &mut [u8],
+    message_type: MessageType,
+    message_id: u16,
+    token: &[u8],
+    response: &Response,
+) -> Result<usize, crate::coap::CoapError> {
+    let mut writer = MessageWriter::new(out, message_type, response.code, message_id, token)?;
+    if let Some(sequence) = response.observe {
+        writer.uint_option(option::OBSERVE, sequence)?;
+    }
+    if let Some(format) = response.content_format {
+        writer.uint_option(option::CONTENT_FORMAT, format as u32)?;
+    }
+    if let Some(block) = response.block2 {
+        writer.uint_option(option::BLOCK2, block.encode())?;
+    }
+    if let Some(size) = response.size2 {
+        writer.uint_option(option::SIZE2, size)?;
+    }
+    writer.payload(response.payload)?;
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv6Addr;
+
+    use super::*;
+
+    struct TestDevice {
+        led: bool,
+        relays: [bool; 2],
+        accept: bool,
+        factory_resets: usize,
+    }
+
+    impl TestDevice {
+        fn new() -> Self {
+            TestDevice {
+                led: false,
+                relays: [false; 2],
+                accept: true,
+                factory_resets: 0,
+            }
+        }
+    }
+
+    impl Device for TestDevice {
+        fn relay_count(&self) -> u8 {
+            2
+        }
+
+        fn output(&self, output: OutputKind) -> bool {
+            match output {
+                OutputKind::Led => self.led,
+                OutputKind::Relay(n) => self.relays[n as usize],
+            }
+        }
+
+        fn set_output(&mut self, output: OutputKind, on: bool) -> bool {
+            if self.accept {
+                match output {
+                    OutputKind::Led => self.led = on,
+                    OutputKind::Relay(n) => self.relays[n as usize] = on,
+                }
+            }
+            self.accept
+        }
+
+        fn write_info(&self, w: &mut dyn Write) -> fmt::Result {
+            write!(
+                w,
+                "{{\"name\":\"thread-connect-123456\",\"padding\":\"{:0>60}\"}}",
+                0
+            )
+        }
+
+        fn write_diagnostics(&self, w: &mut dyn Write) -> fmt::Result {
+            write!(w, "{{\"role\":\"child\",\"rloc16\":\"0x5c01\"}}")
+        }
+
+        fn mesh_local_prefix(&self) -> Option<[u16; 4]> {
+            Some([0xfd00, 0, 0, 0])
+        }
+
+        fn factory_reset(&mut self) -> bool {
+            self.factory_resets += 1;
+            self.accept
+        }
+    }
+
+    const REMOTE: SocketAddrV6 =
+        SocketAddrV6::new(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1), 5683, 0, 0);
+
+    fn request(
+        buf: &mut [u8],
+        message_type: MessageType,
+        code: Code,
+        path: &[&str],
+        options: &[(u16, u32)],
+        payload: &[u8],
+    ) -> usize {
+        let mut writer = MessageWriter::new(buf, message_type, code, 0x0100, &[0x42]).unwrap();
+        for (number, value) in options
+            .iter()
+            .filter(|(number, _)| *number < option::URI_PATH)
+        {
+            writer.uint_option(*number, *value).unwrap();
+        }
+        for segment in path {
+            writer.option(option::URI_PATH, segment.as_bytes()).unwrap();
+        }
+        for (number, value) in options
+            .iter()
+            .filter(|(number, _)| *number > option::URI_PATH)
+        {
+            writer.uint_option(*number, *value).unwrap();
+        }
+        writer.payload(payload).unwrap();
+        writer.finish()
+    }
+
+    fn exchange<'a>(
+        server: &mut Server,
+        device: &mut TestDevice,
+        data: &[u8],
+        out: &'a mut [u8],
+    ) -> Option<Message<'a>> {
+        let len = server.handle(data, REMOTE, device, out)?;
+        Some(Message::parse(&out[..len]).unwrap())
+    }
+
+    #[test]
+    fn routes_resources() {
+        assert_eq!(
+            route(["led"].into_iter(), 2),
+            Some(Resource::Output(OutputKind::Led))
+        );
+        assert_eq!(
+            route(["relay", "1"].into_iter(), 2),
+            Some(Resource::Output(OutputKind::Relay(1)))
+        );
+        assert_eq!(route(["relay", "2"].into_iter(), 2), None);
+        assert_eq!(route(["relay", "x"].into_iter(), 2), None);
+        assert_eq!(route(["relay"].into_iter(), 2), None);
+        assert_eq!(
+            route(["sys", "info"].into_iter(), 2),
+            Some(Resource::SysInfo)
+        );
+        assert_eq!(
+            route(["sys", "diagnostics"].into_iter(), 2),
+            Some(Resource::SysDiagnostics)
+        );
+        assert_eq!(
+            route(["sys", "factory-reset"].into_iter(), 2),
+            Some(Resource::SysFactoryReset)
+        );
+        assert_eq!(
+            route([".well-known", "core"].into_iter(), 2),
+            Some(Resource::WellKnownCore)
+        );
+        assert_eq!(route([].into_iter(), 2), None);
+        assert_eq!(route(["a", "b", "c", "d", "e"].into_iter(), 2), None);
+    }
+
+    #[test]
+    fn get_is_piggybacked_on_the_ack() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        device.led = true;
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["led"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+
+        assert_eq!(response.message_type, MessageType::Acknowledgement);
+        assert_eq!(response.message_id, 0x0100);
+        assert_eq!(response.token, &[0x42]);
+        assert_eq!(response.code, Code::CONTENT);
+        assert_eq!(
+            response.uint_option(option::CONTENT_FORMAT),
+            Some(content_format::TEXT_PLAIN as u32)
+        );
+        assert_eq!(response.payload, b"on");
+    }
+
+    #[test]
+    fn non_confirmable_requests_get_new_message_ids() {
+        let mut server = Server::new(7);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::NonConfirmable,
+            Code::GET,
+            &["led"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+
+        assert_eq!(response.message_type, MessageType::NonConfirmable);
+        assert_eq!(response.message_id, 7);
+    }
+
+    #[test]
+    fn put_switches_outputs() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::PUT,
+            &["relay", "1"],
+            &[],
+            b"on",
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::CHANGED);
+        assert_eq!(device.relays, [false, true]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::PUT,
+            &["led"],
+            &[],
+            b"dim",
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::BAD_REQUEST);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::PUT,
+            &["led"],
+            &[(option::CONTENT_FORMAT, content_format::JSON as u32)],
+            b"true",
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::UNSUPPORTED_CONTENT_FORMAT);
+
+        device.accept = false;
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::PUT,
+            &["led"],
+            &[],
+            b"on",
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn rejects_unknown_paths_methods_and_options() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["fan"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::NOT_FOUND);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::PUT,
+            &["sys", "info"],
+            &[],
+            b"on",
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::METHOD_NOT_ALLOWED);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::DELETE,
+            &["led"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::METHOD_NOT_ALLOWED);
+
+        // unknown critical option 9
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["led"],
+            &[(9, 1)],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::BAD_OPTION);
+    }
+
+    #[test]
+    fn pings_and_malformed_messages_are_reset() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let mut out = [0; 64];
+
+        let ping = [0x40, 0x00, 0x12, 0x34];
+        let response = exchange(&mut server, &mut device, &ping, &mut out).unwrap();
+        assert_eq!(response.message_type, MessageType::Reset);
+        assert_eq!(response.message_id, 0x1234);
+
+        let truncated_option = [0x40, 0x01, 0x12, 0x35, 0xb3, b'l'];
+        let response = exchange(&mut server, &mut device, &truncated_option, &mut out).unwrap();
+        assert_eq!(response.message_type, MessageType::Reset);
+        assert_eq!(response.message_id, 0x1235);
+
+        // non-confirmable messages are silently ignored
+        let non = [0x50, 0x01, 0x12, 0x36, 0xb3, b'l'];
+        assert_eq!(server.handle(&non, REMOTE, &mut device, &mut out), None);
+    }
+
+    #[test]
+    fn transfers_block_wise() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let mut info = Cursor {
+            buf: &mut [0; REPRESENTATION_SIZE],
+            len: 0,
+        };
+        device.write_info(&mut info).unwrap();
+        let info_len = info.len;
+        assert!(info_len > 64 && info_len <= 128);
+
+        let first = Block {
+            num: 0,
+            more: false,
+            szx: 2,
+        };
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["sys", "info"],
+            &[(option::BLOCK2, first.encode())],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.payload.len(), 64);
+        assert_eq!(
+            response.uint_option(option::BLOCK2).and_then(Block::decode),
+            Some(Block {
+                more: true,
+                ..first
+            })
+        );
+        assert_eq!(response.uint_option(option::SIZE2), Some(info_len as u32));
+
+        let second = Block { num: 1, ..first };
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["sys", "info"],
+            &[(option::BLOCK2, second.encode())],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.payload.len(), info_len - 64);
+        assert_eq!(
+            response.uint_option(option::BLOCK2).and_then(Block::decode),
+            Some(second)
+        );
+        assert_eq!(response.uint_option(option::SIZE2), None);
+
+        // beyond the end
+        let third = Block { num: 2, ..first };
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["sys", "info"],
+            &[(option::BLOCK2, third.encode())],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::BAD_OPTION);
+    }
+
+    #[test]
+    fn serves_diagnostics() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["sys", "diagnostics"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::CONTENT);
+        assert_eq!(
+            response.payload,
+            b"{\"role\":\"child\",\"rloc16\":\"0x5c01\"}"
+        );
+        assert_eq!(
+            response.uint_option(option::CONTENT_FORMAT),
+            Some(content_format::JSON as u32)
+        );
+    }
+
+    #[test]
+    fn factory_reset_only_from_inside_the_mesh() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::POST,
+            &["sys", "factory-reset"],
+            &[],
+            &[],
+        );
+        for (remote, code) in [
+            ("fe80::1:2:3:4", Code::CHANGED),
+            // RLOC and mesh-local EID
+            ("fd00::ff:fe00:5c00", Code::CHANGED),
+            ("fd00::1:2:3:4", Code::CHANGED),
+            // OMR address of a host behind the border router
+            ("fd11:22::1:2:3:4", Code::FORBIDDEN),
+            ("2001:db8::1", Code::FORBIDDEN),
+        ] {
+            let remote = SocketAddrV6::new(remote.parse().unwrap(), 5683, 0, 0);
+            let len = server
+                .handle(&buf[..len], remote, &mut device, &mut out)
+                .unwrap();
+            assert_eq!(Message::parse(&out[..len]).unwrap().code, code);
+        }
+        assert_eq!(device.factory_resets, 3);
+
+        device.accept = false;
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::SERVICE_UNAVAILABLE);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["sys", "factory-reset"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.code, Code::METHOD_NOT_ALLOWED);
+        assert_eq!(device.factory_resets, 4);
+    }
+
+    #[test]
+    fn lists_resources() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &[".well-known", "core"],
+            &[],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+
+        assert_eq!(
+            response.payload,
+            b"</led>;obs,</relay/0>;obs,</relay/1>;obs,</sys/info>;ct=50,</sys/diagnostics>;ct=50,\
+              </sys/factory-reset>"
+        );
+        assert_eq!(
+            response.uint_option(option::CONTENT_FORMAT),
+            Some(content_format::LINK_FORMAT as u32)
+        );
+    }
+
+    #[test]
+    fn notifies_observers() {
+        let mut server = Server::new(0x200);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["relay", "0"],
+            &[(option::OBSERVE, 0)],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        let registered = response.uint_option(option::OBSERVE).unwrap();
+        assert_eq!(server.observer_count(), 1);
+
+        // unchanged
+        assert_eq!(server.next_notification(&device, &mut out), None);
+
+        device.relays[0] = true;
+        let (remote, len) = server.next_notification(&device, &mut out).unwrap();
+        assert_eq!(remote, REMOTE);
+        let notification = Message::parse(&out[..len]).unwrap();
+        assert_eq!(notification.message_type, MessageType::NonConfirmable);
+        assert_eq!(notification.token, &[0x42]);
+        assert_eq!(notification.payload, b"on");
+        assert!(notification.uint_option(option::OBSERVE).unwrap() > registered);
+        let message_id = notification.message_id;
+        assert_eq!(server.next_notification(&device, &mut out), None);
+
+        // a reset to the notification cancels the observation
+        let reset = MessageWriter::new(&mut buf, MessageType::Reset, Code::EMPTY, message_id, &[])
+            .unwrap()
+            .finish();
+        assert_eq!(
+            server.handle(&buf[..reset], REMOTE, &mut device, &mut out),
+            None
+        );
+        assert_eq!(server.observer_count(), 0);
+    }
+
+    #[test]
+    fn observe_deregistration_and_full_registry() {
+        let mut server = Server::new(0);
+        let mut device = TestDevice::new();
+        let (mut buf, mut out) = ([0; 64], [0; 512]);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["led"],
+            &[(option::OBSERVE, 0)],
+            &[],
+        );
+        for _ in 0..2 {
+            // the same token replaces the registration
+            exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        }
+        assert_eq!(server.observer_count(), 1);
+
+        let len = request(
+            &mut buf,
+            MessageType::Confirmable,
+            Code::GET,
+            &["led"],
+            &[(option::OBSERVE, 1)],
+            &[],
+        );
+        let response = exchange(&mut server, &mut device, &buf[..len], &mut out).unwrap();
+        assert_eq!(response.uint_option(option::OBSERVE), None);
+        assert_eq!(server.observer_count(), 0);
+
+        for port in 0..MAX_OBSERVERS as u16 + 1 {
+            let len = request(
+                &mut buf,
+                MessageType::Confirmable,
+                Code::GET,
+                &["led"],
+                &[(option::OBSERVE, 0)],
+                &[],
+            );
+            let remote = SocketAddrV6::new(*REMOTE.ip(), port, 0, 0);
+            let len = server
+                .handle(&buf[..len], remote, &mut device, &mut out)
+                .unwrap();
+            let response = Message::parse(&out[..len]).unwrap();
+            // the last one is answered without observe
+            assert_eq!(
+                response.uint_option(option::OBSERVE).is_some(),
+                (port as usize) < MAX_OBSERVERS
+            );
+        }
+    }
+}
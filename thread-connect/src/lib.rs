@@ -1 +1,33 @@
 #![no_std]
+
+pub mod coap_server;
+pub mod output_kind;
+pub mod outputs;
+
+// shared with mqtt_thread
+#[path = "../../mqtt_thread/src/address.rs"]
+pub mod address;
+#[path = "../../mqtt_thread/src/coap.rs"]
+pub mod coap;
+#[path = "../../mqtt_thread/src/dataset.rs"]
+pub mod dataset;
+#[path = "../../mqtt_thread/src/device_type.rs"]
+pub mod device_type;
+#[path = "../../mqtt_thread/src/diagnostics.rs"]
+pub mod diagnostics;
+#[path = "../../mqtt_thread/src/diagnostics_report.rs"]
+pub mod diagnostics_report;
+#[path = "../../mqtt_thread/src/identity.rs"]
+pub mod identity;
+#[path = "../../mqtt_thread/src/joiner.rs"]
+pub mod joiner;
+#[path = "../../mqtt_thread/src/ot_settings.rs"]
+pub mod ot_settings;
+#[path = "../../mqtt_thread/src/settings_store.rs"]
+pub mod settings_store;
+#[path = "../../mqtt_thread/src/srp.rs"]
+pub mod srp;
+
+// shared with the other examples
+#[path = "../../mqtt_led/src/secret.rs"]
+pub mod secret;
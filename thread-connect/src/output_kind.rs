@@ -0,0 +1,19 @@
+//! The switchable outputs, without the GPIOs of [`crate::outputs`].
+
+use defmt::Format;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    Relay(u8),
+    Led,
+}
+
+impl OutputKind {
+    /// Bit of the output in a set of states
+    pub(crate) fn bit(self) -> u32 {
+        match self {
+            OutputKind::Led => 1,
+            OutputKind::Relay(n) => 1 << (n + 1),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::info;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use esp_hal::gpio::Output;
+
+pub use crate::output_kind::OutputKind;
+
+/// Relays of the relay module, `/relay/0` to `/relay/1`
+pub const RELAY_COUNT: u8 = 2;
+
+static OUTPUT_REQUESTS: Channel<CriticalSectionRawMutex, (OutputKind, bool), 4> = Channel::new();
+
+/// Applied states, one bit per output
+static STATES: AtomicU32 = AtomicU32::new(0);
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Switch an output, applied by [`outputs_task`]. Returns `false` if the queue is full.
+pub fn request(kind: OutputKind, on: bool) -> bool {
+    OUTPUT_REQUESTS.try_send((kind, on)).is_ok()
+}
+
+/// Last state applied by [`outputs_task`].
+pub fn state(kind: OutputKind) -> bool {
+    STATES.load(Ordering::Relaxed) & kind.bit() != 0
+}
+
+/// Wait until [`outputs_task`] switched an output.
+pub async fn wait_changed() {
+    CHANGED.wait().await
+}
+
+/// Owns the GPIOs of the relays and the LED. The relay module is powered on start.
+#[embassy_executor::task]
+pub async fn outputs_task(
+    mut relay_power: Output<'static>,
+    mut relays: [Output<'static>; RELAY_COUNT as usize],
+    mut led: Output<'static>,
+) {
+    relay_power.set_high();
+
+    loop {
+        let (kind, on) = OUTPUT_REQUESTS.receive().await;
+        info!("Switching {:?} {}", kind, if on { "on" } else { "off" });
+
+        let output = match kind {
+            OutputKind::Relay(n) => match relays.get_mut(n as usize) {
+                Some(relay) => relay,
+                None => continue,
+            },
+            OutputKind::Led => &mut led,
+        };
+        if on {
+            output.set_high();
+            STATES.fetch_or(kind.bit(), Ordering::Relaxed);
+        } else {
+            output.set_low();
+            STATES.fetch_and(!kind.bit(), Ordering::Relaxed);
+        }
+        CHANGED.signal(());
+    }
+}
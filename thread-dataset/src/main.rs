@@ -0,0 +1,229 @@
+//! Pretty-prints and generates Thread operational datasets in TLV hex format.
+
+use std::fs::File;
+use std::io::Read;
+use std::net::Ipv6Addr;
+use std::process::ExitCode;
+
+#[allow(dead_code)]
+#[path = "../../mqtt_thread/src/dataset.rs"]
+mod dataset;
+
+use dataset::{
+    decode_hex, tlv, Channel, ChannelMask, Dataset, DatasetError, NetworkName, SecurityPolicy,
+    Timestamp, TlvIter, MAX_DATASET_LEN,
+};
+
+const USAGE: &str = "\
+usage: thread-dataset show [<hex>]
+       thread-dataset generate [options]
+
+show reads the dataset from stdin without <hex>.
+
+generate options, unset values are random:
+  --name <name>         network name, 1 to 16 bytes
+  --channel <11-26>     channel
+  --panid <hex>         PAN ID, e.g. 0x1234
+  --extpanid <hex>      extended PAN ID, 8 bytes
+  --prefix <prefix>     mesh-local prefix, e.g. fd00:db8:a0:0::/64
+  --key <hex>           network key, 16 bytes
+  --pskc <hex>          PSKc, 16 bytes";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("show") => show(args.get(1).map(String::as_str)),
+        Some("generate") => generate(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn show(hex: Option<&str>) -> Result<(), String> {
+    let hex = match hex {
+        Some(hex) => hex.to_string(),
+        None => {
+            let mut hex = String::new();
+            std::io::stdin()
+                .read_to_string(&mut hex)
+                .map_err(|e| format!("failed to read stdin: {e}"))?;
+            hex
+        }
+    };
+
+    let mut buf = [0; MAX_DATASET_LEN];
+    let len = decode_hex(&hex, &mut buf).map_err(error)?;
+    let data = &buf[..len];
+    let dataset = Dataset::decode(data).map_err(error)?;
+
+    print_dataset(&dataset);
+    for tlv in TlvIter::new(data).flatten() {
+        if tlv::name(tlv.tlv_type) == "Unknown" {
+            println!("TLV {}: {}", tlv.tlv_type, to_hex(tlv.value));
+        }
+    }
+
+    dataset
+        .validate_active()
+        .map_err(|e| format!("not usable as active dataset: {e}"))
+}
+
+fn print_dataset(dataset: &Dataset) {
+    let print_timestamp = |label: &str, timestamp: &Option<Timestamp>| {
+        if let Some(timestamp) = timestamp {
+            println!(
+                "{label}: {} (ticks {}{})",
+                timestamp.seconds,
+                timestamp.ticks,
+                if timestamp.authoritative {
+                    ", authoritative"
+                } else {
+                    ""
+                }
+            );
+        }
+    };
+
+    print_timestamp("Active Timestamp", &dataset.active_timestamp);
+    print_timestamp("Pending Timestamp", &dataset.pending_timestamp);
+    if let Some(delay) = dataset.delay_timer {
+        println!("Delay Timer: {delay} ms");
+    }
+    if let Some(channel) = dataset.channel {
+        println!("Channel: {} (page {})", channel.channel, channel.page);
+    }
+    if let Some(mask) = dataset.channel_mask {
+        println!("Channel Mask: 0x{:08x}", mask.0);
+    }
+    if let Some(extended_pan_id) = dataset.extended_pan_id {
+        println!("Ext PAN ID: {}", to_hex(&extended_pan_id));
+    }
+    if let Some(prefix) = dataset.mesh_local_prefix {
+        let mut address = [0; 16];
+        address[..8].copy_from_slice(&prefix);
+        println!("Mesh Local Prefix: {}/64", Ipv6Addr::from(address));
+    }
+    if let Some(key) = dataset.network_key {
+        println!("Network Key: {}", to_hex(&key));
+    }
+    if let Some(name) = &dataset.network_name {
+        println!("Network Name: {}", name.as_str());
+    }
+    if let Some(pan_id) = dataset.pan_id {
+        println!("PAN ID: 0x{pan_id:04x}");
+    }
+    if let Some(pskc) = dataset.pskc {
+        println!("PSKc: {}", to_hex(&pskc));
+    }
+    if let Some(policy) = dataset.security_policy {
+        println!(
+            "Security Policy: {} hours, flags {}",
+            policy.rotation_time,
+            to_hex(&policy.flags)
+        );
+    }
+}
+
+fn generate(args: &[String]) -> Result<(), String> {
+    let mut random = [0; 64];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut random))
+        .map_err(|e| format!("failed to read /dev/urandom: {e}"))?;
+
+    let pan_id = u16::from_be_bytes([random[0], random[1]]) % 0xffff;
+    let mut prefix = [0xfd, 0, 0, 0, 0, 0, 0, 0];
+    prefix[1..6].copy_from_slice(&random[2..7]);
+    let mut dataset = Dataset {
+        active_timestamp: Some(Timestamp {
+            seconds: 1,
+            ticks: 0,
+            authoritative: false,
+        }),
+        channel: Some(Channel {
+            page: 0,
+            channel: 11 + random[7] as u16 % 16,
+        }),
+        channel_mask: Some(ChannelMask::ALL),
+        extended_pan_id: Some(random[8..16].try_into().unwrap()),
+        mesh_local_prefix: Some(prefix),
+        network_key: Some(random[16..32].try_into().unwrap()),
+        network_name: Some(NetworkName::new(&format!("OpenThread-{pan_id:04x}")).map_err(error)?),
+        pan_id: Some(pan_id),
+        pskc: Some(random[32..48].try_into().unwrap()),
+        security_policy: Some(SecurityPolicy::DEFAULT),
+        ..Dataset::default()
+    };
+
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{option} needs a value\n\n{USAGE}"))?;
+        let invalid = || format!("invalid value for {option}: {value}");
+
+        match option.as_str() {
+            "--name" => dataset.network_name = Some(NetworkName::new(value).map_err(error)?),
+            "--channel" => {
+                dataset.channel = Some(Channel {
+                    page: 0,
+                    channel: value.parse().map_err(|_| invalid())?,
+                })
+            }
+            "--panid" => {
+                let hex = value.trim_start_matches("0x");
+                dataset.pan_id = Some(u16::from_str_radix(hex, 16).map_err(|_| invalid())?)
+            }
+            "--extpanid" => dataset.extended_pan_id = Some(parse_bytes(value).ok_or_else(invalid)?),
+            "--prefix" => {
+                dataset.mesh_local_prefix = Some(parse_prefix(value).ok_or_else(invalid)?)
+            }
+            "--key" => dataset.network_key = Some(parse_bytes(value).ok_or_else(invalid)?),
+            "--pskc" => dataset.pskc = Some(parse_bytes(value).ok_or_else(invalid)?),
+            _ => return Err(format!("unknown option {option}\n\n{USAGE}")),
+        }
+    }
+
+    dataset.validate_active().map_err(error)?;
+
+    let mut buf = [0; MAX_DATASET_LEN];
+    let len = dataset.encode(&mut buf).map_err(error)?;
+    print_dataset(&dataset);
+    println!();
+    println!("{}", to_hex(&buf[..len]));
+
+    Ok(())
+}
+
+fn parse_bytes<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let mut bytes = [0; N];
+    match decode_hex(hex, &mut bytes) {
+        Ok(len) if len == N => Some(bytes),
+        _ => None,
+    }
+}
+
+/// `fd00:db8:a0:0::/64` or 8 bytes of hex
+fn parse_prefix(prefix: &str) -> Option<[u8; 8]> {
+    if let Some(address) = prefix.strip_suffix("/64") {
+        let address: Ipv6Addr = address.parse().ok()?;
+        address.octets()[..8].try_into().ok()
+    } else {
+        parse_bytes(prefix)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn error(e: DatasetError) -> String {
+    e.to_string()
+}
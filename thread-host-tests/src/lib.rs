@@ -0,0 +1,27 @@
+//! The protocol code of the Thread examples has no hardware dependencies, this crate includes
+//! it to run its unit tests on the host with `cargo test`.
+
+#![no_std]
+#![allow(dead_code)]
+
+// mqtt_thread
+#[path = "../../mqtt_thread/src/address.rs"]
+mod address;
+#[path = "../../mqtt_thread/src/coap.rs"]
+mod coap;
+#[path = "../../mqtt_thread/src/coap_exchange.rs"]
+mod coap_exchange;
+#[path = "../../mqtt_thread/src/diagnostics_report.rs"]
+mod diagnostics_report;
+#[path = "../../mqtt_thread/src/dns_packet.rs"]
+mod dns_packet;
+#[path = "../../mqtt_thread/src/nat64.rs"]
+mod nat64;
+#[path = "../../mqtt_thread/src/settings_store.rs"]
+mod settings_store;
+
+// thread-connect
+#[path = "../../thread-connect/src/coap_server.rs"]
+mod coap_server;
+#[path = "../../thread-connect/src/output_kind.rs"]
+mod output_kind;